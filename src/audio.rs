@@ -16,6 +16,11 @@ use tokio::time::timeout;
 #[folder = "audio/"]
 struct AudioAsset;
 
+/// 判断音频文件是否存在于嵌入的资源中
+pub fn has_audio_asset(file_name: &str) -> bool {
+    AudioAsset::get(file_name).is_some()
+}
+
 /// 使用 aplay 播放音频的实现
 pub struct AplayNotifier {
     config: Arc<AudioConfig>,
@@ -39,6 +44,11 @@ impl VoiceNotifier for AplayNotifier {
             AudioEvent::ConnectionStarted => &config.files.connection_started,
             AudioEvent::ConnectionSuccess => &config.files.connection_success,
             AudioEvent::ConnectionFailed => &config.files.connection_failed,
+            // 可选的提示音，未配置时不播放
+            AudioEvent::ProvisioningTimeout => match &config.files.provisioning_timeout {
+                Some(file) => file,
+                None => return,
+            },
         };
 
         // 从嵌入式资源中获取音频数据
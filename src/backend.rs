@@ -1,20 +1,47 @@
-use crate::config::{ApConfig, AppConfig, load_config_from_toml_str};
-use crate::structs::{ConnectionRequest, Network};
+use crate::channel;
+use crate::charset::{SsidEncoding, decode_ssid};
+use crate::config::{ApConfig, AppConfig};
+use crate::events::{ConnectStep, EventBus, ProvisionEvent, ProvisionerState};
+use crate::hostapd::HostapdConfig;
+use crate::psk::{derive_psk_hex, wpa_printf_quote};
+use crate::qr;
+use crate::shutdown;
+use crate::structs::{ConnectResult, ConnectionRequest, FailureReason, LastAttempt, Network, SavedNetwork};
 use crate::traits::{AudioEvent, VoiceNotifier};
+use crate::validation::{ValidationError, is_hex_psk, validate_connection_request};
 use anyhow::{Result, anyhow, Context};
 use async_trait::async_trait;
-use once_cell::sync::Lazy;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::fs;
 use tokio::process::Command;
 use wpa_ctrl::{WpaController, WpaControllerBuilder};
 
-// 从配置文件加载总配置
-static GLOBAL_APP_CONFIG: Lazy<AppConfig> = Lazy::new(|| {
-    const CONFIG_TOML: &str = include_str!("../configs.toml");
-    load_config_from_toml_str(CONFIG_TOML)
-});
+/// 取出 wpa_supplicant 配置中的 `network={...}` 块
+///
+/// `disable` 为 true 时在每块末尾加上 `disabled=1`，否则去掉已有的 `disabled=` 行。
+fn network_blocks(conf: &str, disable: bool) -> String {
+    let mut out = String::new();
+    let mut in_block = false;
+    for line in conf.lines() {
+        let trimmed = line.trim();
+        if !in_block && trimmed.starts_with("network={") {
+            in_block = true;
+        }
+        if !in_block || trimmed.starts_with("disabled=") {
+            continue;
+        }
+        if trimmed == "}" {
+            out.push_str(if disable { "\tdisabled=1\n}\n" } else { "}\n" });
+            in_block = false;
+        } else {
+            out.push_str(line);
+            out.push('\n');
+        }
+    }
+    out
+}
 
 /// 将 wpa_supplicant 输出中的 `\xHH` 转义序列反转义回原始字节。
 /// 主要用于处理扫描结果中 SSID 字段中的汉字等非 ASCII 字符。
@@ -98,34 +125,104 @@ pub struct WpaCtrlBackend {
     dnsmasq: Arc<tokio::sync::Mutex<Option<tokio::process::Child>>>,
     cmd_ctrl: Arc<Mutex<Option<WpaController>>>,
     audio_notifier: Arc<dyn VoiceNotifier>,
+    events: Arc<EventBus>,
+    last_attempt: Mutex<Option<LastAttempt>>,
+    // 最近一次扫描的结果
+    scan_cache: Mutex<Vec<Network>>,
+    // 保证同一时间只有一次扫描（双射频模式下可以随时重新扫描）
+    scan_lock: tokio::sync::Mutex<()>,
+    // hostapd_channel = "auto" 时选定的信道
+    ap_channel: Mutex<Option<u8>>,
+    // AP 当前是否由本进程启动并在运行
+    ap_active: AtomicBool,
+    // 是否有连接尝试正在进行
+    connecting: AtomicBool,
+    // 最近一次有客户端连接 AP 或有 HTTP 请求的时间（用于空闲超时）
+    last_activity: Mutex<tokio::time::Instant>,
+    // 连接成功后通知配网会话结束
+    provisioned: tokio::sync::Notify,
+    // 本地应用请求结束配网会话
+    stop_requested: tokio::sync::Notify,
+    // 设备当前所处的阶段
+    state: Mutex<ProvisionerState>,
+    // 最近一次连接尝试的结果（成功或失败，读取后不清除）
+    last_result: Mutex<Option<ConnectResult>>,
 }
 
 impl WpaCtrlBackend {
-    pub fn new() -> Result<Self> {
-        let app_config = GLOBAL_APP_CONFIG.clone();
-        let ap_config = Arc::new(app_config.ap.clone());
+    /// 创建后端：清理上次运行的残留状态，并启动一个全新的 wpa_supplicant
+    ///
+    /// 上次保存的网络会保留但先禁用，配网超时后可以回到这些网络。
+    pub async fn new(app_config: &AppConfig) -> Result<Self> {
+        Self::write_wpa_conf(&app_config.ap, true)?;
 
-        // 创建 wpa_supplicant 配置文件，使用控制套接字接口
+        // 清理过去的状态，启动一个新的 wpa_supplicant 守护进程
+        Self::perform_startup_cleanup(&app_config.ap).await?;
+
+        Self::attach(app_config)
+    }
+
+    /// 创建后端：和 `new` 一样重启 wpa_supplicant，但启用所有已保存的网络
+    ///
+    /// 用于 `run --if-unconfigured` 先尝试用已有配置联网。
+    pub async fn with_saved_networks(app_config: &AppConfig) -> Result<Self> {
+        Self::write_wpa_conf(&app_config.ap, false)?;
+        Self::perform_startup_cleanup(&app_config.ap).await?;
+        Self::attach(app_config)
+    }
+
+    /// 创建 wpa_supplicant 配置文件（使用控制套接字接口），保留原文件中的网络
+    fn write_wpa_conf(ap_config: &ApConfig, disable_saved: bool) -> Result<()> {
         let update_config_str = if ap_config.wpa_update_config { "1" } else { "0" };
+        let saved_networks = std::fs::read_to_string(&ap_config.wpa_conf_path)
+            .map(|old| network_blocks(&old, disable_saved))
+            .unwrap_or_default();
         let wpa_conf_content = format!(
-            "ctrl_interface=DIR={} GROUP={}\nupdate_config={}\n",
-            ap_config.wpa_ctrl_interface, ap_config.wpa_group, update_config_str
+            "ctrl_interface=DIR={} GROUP={}\nupdate_config={}\n{}",
+            ap_config.wpa_ctrl_interface, ap_config.wpa_group, update_config_str, saved_networks
         );
         std::fs::write(&ap_config.wpa_conf_path, wpa_conf_content.as_bytes())
             .context("Failed to write wpa_supplicant config")?;
 
         tracing::info!("Created wpa_supplicant config at: {}", ap_config.wpa_conf_path);
+        Ok(())
+    }
 
-        // 清理过去的状态，启动一个新的 wpa_supplicant 守护进程
-        Self::perform_startup_cleanup(&ap_config)?;
+    /// 用已保存的网络重启 wpa_supplicant 并重新打开控制接口
+    ///
+    /// `monitor` 在 wpa_supplicant 退出后使用，后端（以及事件订阅者）保持不变。
+    pub async fn restart_with_saved_networks(&self) -> Result<()> {
+        self.set_state(ProvisionerState::Starting);
+        Self::write_wpa_conf(&self.ap_config, false)?;
+        Self::perform_startup_cleanup(&self.ap_config).await?;
+        let cmd_ctrl = WpaControllerBuilder::new()
+            .open(self.ap_config.sta_interface())
+            .context("Failed to connect WpaController socket. Is wpa_supplicant running?")?;
+        *self.cmd_ctrl.lock().unwrap() = Some(cmd_ctrl);
+        Ok(())
+    }
 
-        tracing::debug!("Connecting CMD controller to {}", ap_config.interface_name);
+    /// 连接到已经在运行的 wpa_supplicant，不做任何清理
+    ///
+    /// 用于 `status`、`forget` 等只需要查询或修改现有状态的命令行子命令。
+    pub fn attach(app_config: &AppConfig) -> Result<Self> {
+        tracing::debug!("Connecting CMD controller to {}", app_config.ap.sta_interface());
         
         let cmd_ctrl = WpaControllerBuilder::new()
-            .open(&ap_config.interface_name)
+            .open(app_config.ap.sta_interface())
             .context("Failed to connect WpaController socket. Is wpa_supplicant running?")?;
 
-        let cmd_ctrl_arc = Arc::new(Mutex::new(Some(cmd_ctrl)));
+        Ok(Self::with_controller(app_config, Some(cmd_ctrl)))
+    }
+
+    /// 创建不连接 wpa_supplicant 的后端，只能用于 AP 相关操作（例如 `ap stop`）
+    pub fn detached(app_config: &AppConfig) -> Self {
+        Self::with_controller(app_config, None)
+    }
+
+    fn with_controller(app_config: &AppConfig, cmd_ctrl: Option<WpaController>) -> Self {
+        let ap_config = Arc::new(app_config.ap.clone());
+        let cmd_ctrl_arc = Arc::new(Mutex::new(cmd_ctrl));
 
         // === 创建音频 Notifier ===
         let audio_notifier = {
@@ -146,76 +243,273 @@ impl WpaCtrlBackend {
             }
         };
 
-        Ok(Self {
+        Self {
             ap_config,
             hostapd: Arc::new(tokio::sync::Mutex::new(None)),
             dnsmasq: Arc::new(tokio::sync::Mutex::new(None)),
             cmd_ctrl: cmd_ctrl_arc,
             audio_notifier,
-        })
+            events: Arc::new(EventBus::new()),
+            last_attempt: Mutex::new(None),
+            scan_cache: Mutex::new(Vec::new()),
+            scan_lock: tokio::sync::Mutex::new(()),
+            ap_channel: Mutex::new(None),
+            ap_active: AtomicBool::new(false),
+            connecting: AtomicBool::new(false),
+            last_activity: Mutex::new(tokio::time::Instant::now()),
+            provisioned: tokio::sync::Notify::new(),
+            stop_requested: tokio::sync::Notify::new(),
+            state: Mutex::new(ProvisionerState::Starting),
+            last_result: Mutex::new(None),
+        }
     }
 
     pub fn ap_config(&self) -> Arc<ApConfig> {
         self.ap_config.clone()
     }
 
+    /// 配网事件总线（供 SSE 等订阅）
+    pub fn events(&self) -> Arc<EventBus> {
+        self.events.clone()
+    }
+
+    /// AP 和 STA 是否使用不同的网卡（双射频模式）
+    pub fn is_dual_radio(&self) -> bool {
+        self.ap_config.is_dual_radio()
+    }
+
+    /// 最近一次扫描到的网络列表
+    pub fn cached_networks(&self) -> Vec<Network> {
+        self.scan_cache.lock().unwrap().clone()
+    }
+
+    /// 等待某次连接尝试成功（配网完成）
+    pub async fn wait_provisioned(&self) {
+        self.provisioned.notified().await;
+    }
+
+    /// 设备当前所处的阶段
+    pub fn state(&self) -> ProvisionerState {
+        *self.state.lock().unwrap()
+    }
+
+    /// 切换状态，发生变化时发布 `state_changed` 事件
+    pub fn set_state(&self, state: ProvisionerState) {
+        let mut current = self.state.lock().unwrap();
+        if *current == state {
+            return;
+        }
+        *current = state;
+        tracing::info!("State: {}", state.as_str());
+        // 持有状态锁发布，保证事件顺序与状态变化一致
+        self.events.publish(ProvisionEvent::StateChanged { state });
+    }
+
+    /// 根据链路事件更新在线状态；配网会话中不改变
+    pub fn set_link_state(&self, online: bool) {
+        if self.state().is_provisioning() {
+            return;
+        }
+        self.set_state(if online { ProvisionerState::Online } else { ProvisionerState::Offline });
+    }
+
+    /// 最近一次连接尝试的结果
+    pub fn last_result(&self) -> Option<ConnectResult> {
+        self.last_result.lock().unwrap().clone()
+    }
+
+    /// 请求结束正在进行的配网会话（关闭 AP 并回到已保存的网络）
+    pub fn request_stop(&self) -> Result<()> {
+        if self.is_connecting() {
+            return Err(anyhow!("a connection attempt is in progress"));
+        }
+        if !self.is_ap_active() {
+            return Err(anyhow!("not provisioning"));
+        }
+        self.stop_requested.notify_waiters();
+        Ok(())
+    }
+
+    /// 等待本地应用请求结束配网
+    pub async fn wait_stop_requested(&self) {
+        self.stop_requested.notified().await;
+    }
+
+    /// 扫描结果：`refresh` 时重新扫描，否则返回缓存
+    ///
+    /// 单网卡模式下 AP 运行时扫描需要关闭 AP，连接过程中扫描会打断连接，
+    /// 这两种情况下忽略 `refresh`。
+    pub async fn networks(&self, refresh: bool) -> Result<Vec<Network>> {
+        let can_scan = (self.is_dual_radio() || !self.is_ap_active()) && !self.is_connecting();
+        if refresh && can_scan {
+            self.scan().await
+        } else {
+            Ok(self.cached_networks())
+        }
+    }
+
+    /// 校验连接请求，并在后台开始连接（Web 门户和控制 socket 共用）
+    ///
+    /// 同一时间只允许一次连接：已有连接在进行时返回 `ValidationCode::Busy`，
+    /// 标记在后台任务（包括失败后恢复已保存的网络）结束时才清除。
+    pub fn start_connect(self: &Arc<Self>, req: ConnectionRequest) -> Result<(), ValidationError> {
+        if let Err(e) = validate_connection_request(&req, &self.cached_networks()) {
+            tracing::warn!(ssid = %req.display_ssid(), "Rejected connect request: {}", e);
+            return Err(e);
+        }
+        if self
+            .connecting
+            .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+            .is_err()
+        {
+            tracing::warn!(ssid = %req.display_ssid(), "Rejected connect request: a connection attempt is in progress");
+            return Err(ValidationError::busy());
+        }
+
+        let backend = self.clone();
+        tokio::spawn(async move {
+            // connect 在后台运行：停止 AP（双射频模式下不停止）-> 连接 -> DHCP，
+            // 成功时通知配网会话结束，失败时自己重启 AP，用户可以重新连上 AP 再试
+            if let Err(e) = backend.connect_replacing_saved(&req).await {
+                tracing::error!(
+                    "Connection failed: {}. AP should have been restarted automatically.",
+                    e
+                );
+            }
+            backend.connecting.store(false, Ordering::SeqCst);
+        });
+        Ok(())
+    }
+
+    /// 连接指定网络，供 `start_connect` 和命令行 `connect` 共用
+    ///
+    /// 不在配网会话中时（AP 未运行）先禁用已保存的网络，否则 wpa_supplicant
+    /// 可能仍连着旧网络；连接失败后重新启用它们。
+    pub async fn connect_replacing_saved(&self, req: &ConnectionRequest) -> Result<()> {
+        let outside_session = !self.is_ap_active();
+        if outside_session && let Err(e) = self.disable_saved_networks().await {
+            tracing::warn!("Failed to disable saved networks: {:#}", e);
+        }
+        let result = self.connect(req).await;
+        if result.is_err() && outside_session {
+            let _ = self.restore_saved_networks().await;
+        }
+        result
+    }
+
+    /// 播放语音提示
+    pub async fn play(&self, event: AudioEvent) {
+        self.audio_notifier.play(event).await;
+    }
+
+    /// AP 是否在运行
+    pub fn is_ap_active(&self) -> bool {
+        self.ap_active.load(Ordering::SeqCst)
+    }
+
+    /// 是否有连接尝试正在进行
+    pub fn is_connecting(&self) -> bool {
+        self.connecting.load(Ordering::SeqCst)
+    }
+
+    /// 记录一次用户活动（HTTP 请求、客户端连接 AP）
+    pub fn touch_activity(&self) {
+        *self.last_activity.lock().unwrap() = tokio::time::Instant::now();
+    }
+
+    /// 距离最近一次用户活动的时间
+    pub fn idle_for(&self) -> Duration {
+        self.last_activity.lock().unwrap().elapsed()
+    }
+
+    /// 公共方法：连接到 AP 的客户端数量（`iw dev <ap> station dump`），无法查询时返回 None
+    pub async fn associated_stations(&self) -> Option<usize> {
+        let output = Command::new("iw")
+            .args(["dev", self.ap_config.ap_interface(), "station", "dump"])
+            .output()
+            .await
+            .ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        let text = String::from_utf8_lossy(&output.stdout);
+        Some(text.lines().filter(|l| l.starts_with("Station ")).count())
+    }
+
+    /// 取出最近一次失败的连接尝试（取出后即清除，保证只展示一次）
+    pub fn take_last_attempt(&self) -> Option<LastAttempt> {
+        self.last_attempt.lock().unwrap().take()
+    }
+
     /// 在程序启动时执行的清理函数，用于处理上一次退出留下的所有状态。
     /// 这个函数会：
     /// 1. 杀死所有相关的孤儿进程 (wpa_supplicant, hostapd, dnsmasq)。
     /// 2. 清理 /tmp 中所有 wpa_ctrl 相关的客户端套接字。
     /// 3. 清理 wpa_supplicant 服务端套接字。
     /// 4. 启动一个全新的 wpa_supplicant 守护进程。
-    fn perform_startup_cleanup(config: &ApConfig) -> Result<()> {
+    async fn perform_startup_cleanup(config: &ApConfig) -> Result<()> {
         tracing::debug!("Performing robust startup cleanup...");
 
         // === 1. 杀死所有孤儿进程 ===
         // 我们使用 -9 (SIGKILL) 来确保它们被强行终止
-        let _ = std::process::Command::new("killall")
+        let _ = tokio::process::Command::new("killall")
             .arg("-9")
             .arg("wpa_supplicant")
-            .status();
-        let _ = std::process::Command::new("killall")
+            .status()
+            .await;
+        let _ = tokio::process::Command::new("killall")
             .arg("-9")
             .arg("hostapd")
-            .status();
-        let _ = std::process::Command::new("killall")
+            .status()
+            .await;
+        let _ = tokio::process::Command::new("killall")
             .arg("-9")
             .arg("dnsmasq")
-            .status();
+            .status()
+            .await;
         tracing::debug!("Orphan processes terminated.");
         
         // 短暂等待，确保进程完全退出，端口/资源被释放
-        std::thread::sleep(Duration::from_millis(500));
+        tokio::time::sleep(Duration::from_millis(500)).await;
 
         // === 新增：重置网络接口状态 ===
         // 这是为了清理 nl80211 驱动中可能残留的"脏"配置
         // 应对 kill -9 或其他非正常关机导致的状态卡死
         // 这个操作在嵌入式 Linux 上非常标准
-        tracing::debug!("Resetting interface {} state (down/up)...", config.interface_name);
-        let _ = std::process::Command::new("ip")
-            .arg("link")
-            .arg("set")
-            .arg(&config.interface_name)
-            .arg("down")
-            .status();
-        // 等待驱动响应
-        std::thread::sleep(Duration::from_millis(500));
-        
-        let _ = std::process::Command::new("ip")
-            .arg("link")
-            .arg("set")
-            .arg(&config.interface_name)
-            .arg("up")
-            .status();
-        // 等待接口就绪
-        std::thread::sleep(Duration::from_millis(500));
+        // 双射频模式下 AP 和 STA 两块网卡都要重置
+        let mut interfaces = vec![config.sta_interface()];
+        if config.is_dual_radio() {
+            interfaces.push(config.ap_interface());
+        }
+        for interface in interfaces {
+            tracing::debug!("Resetting interface {} state (down/up)...", interface);
+            let _ = tokio::process::Command::new("ip")
+                .arg("link")
+                .arg("set")
+                .arg(interface)
+                .arg("down")
+                .status()
+                .await;
+            // 等待驱动响应
+            tokio::time::sleep(Duration::from_millis(500)).await;
+
+            let _ = tokio::process::Command::new("ip")
+                .arg("link")
+                .arg("set")
+                .arg(interface)
+                .arg("up")
+                .status()
+                .await;
+            // 等待接口就绪
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
         tracing::debug!("Interface state reset complete.");
         // === 新增结束 ===
 
         // 清理/tmp/wpa_ctrl_1
         let wpa_ctrl_1 = std::path::Path::new("/tmp/wpa_ctrl_1");
         if wpa_ctrl_1.exists() {
-            match std::fs::remove_file(&wpa_ctrl_1) {
+            match std::fs::remove_file(wpa_ctrl_1) {
                 Ok(_) => tracing::debug!("Removed stale wpa_ctrl socket: {:?}", wpa_ctrl_1),
                 Err(e) => tracing::warn!("Failed to remove {:?}: {}", wpa_ctrl_1, e),
             }
@@ -226,7 +520,7 @@ impl WpaCtrlBackend {
         // === 清理 wpa_supplicant 服务端套接字 ===
         // 例如：/var/run/wpa_supplicant/wlan0
         let socket_path = std::path::Path::new(&config.wpa_ctrl_interface)
-            .join(&config.interface_name);
+            .join(config.sta_interface());
         if socket_path.exists() {
             match std::fs::remove_file(&socket_path) {
                 Ok(_) => tracing::debug!("Removed stale server socket: {:?}", socket_path),
@@ -237,12 +531,13 @@ impl WpaCtrlBackend {
 
         // === 启动一个全新的 wpa_supplicant 守护进程 ===
         tracing::info!("Attempting to start wpa_supplicant daemon...");
-        let status = std::process::Command::new("wpa_supplicant")
+        let status = tokio::process::Command::new("wpa_supplicant")
             .arg("-B")
-            .arg(format!("-i{}", config.interface_name))
+            .arg(format!("-i{}", config.sta_interface()))
             .arg("-c")
             .arg(&config.wpa_conf_path)
             .status()
+            .await
             .context("Failed to spawn wpa_supplicant daemon")?;
 
         if !status.success() {
@@ -250,7 +545,7 @@ impl WpaCtrlBackend {
         }
 
         tracing::info!("wpa_supplicant daemon started. Waiting for socket file...");
-        std::thread::sleep(Duration::from_secs(2));
+        tokio::time::sleep(Duration::from_secs(2)).await;
         Ok(())
     }
 
@@ -308,7 +603,8 @@ impl WpaCtrlBackend {
 
     /// 辅助函数：解析 SCAN_RESULTS 的输出
     /// 格式: bssid / frequency / signal level / flags / ssid
-    fn parse_scan_results(output: &str) -> Result<Vec<Network>> {
+    /// `legacy_charset` 为 SSID 不是合法 UTF-8 时优先尝试的字符集
+    fn parse_scan_results(output: &str, legacy_charset: SsidEncoding) -> Result<Vec<Network>> {
         let mut networks = Vec::new();
         for line in output.lines().skip(1) {
             let parts: Vec<&str> = line.split('\t').collect();
@@ -316,20 +612,25 @@ impl WpaCtrlBackend {
                 continue;
             }
 
+            let frequency: u32 = parts[1].parse().unwrap_or(0);
             let signal_dbm: i16 = parts[2].parse().unwrap_or(-100);
             let flags = parts[3];
 
             // wpa_supplicant 对包含非 ASCII 字节的 SSID 会以 `\xHH` 转义序列输出。
-            // 这里将其反转义回原始字节，然后尝试用 UTF-8 解码（使用 from_utf8_lossy 保持健壮性）。
+            // 这里将其反转义回原始字节；`ssid` 保持 UTF-8 (lossy) 解码以兼容旧前端，
+            // `display_name` 则按字符集检测结果解码（老式路由器常用 GBK）。
             let raw_ssid = parts[4];
             let ssid_bytes = unescape_wpa_ssid(raw_ssid);
-            let ssid = String::from_utf8_lossy(&ssid_bytes).to_string();
-
-            if ssid.is_empty() {
+            if ssid_bytes.is_empty() {
                 continue;
             }
+            let ssid = String::from_utf8_lossy(&ssid_bytes).to_string();
+            let (display_name, encoding) = decode_ssid(&ssid_bytes, legacy_charset);
 
-            let security = if flags.contains("WPA2") {
+            // 只支持 SAE 的网络标记为 WPA3；WPA2/WPA3 混合模式仍按 WPA2 (PSK) 处理
+            let security = if flags.contains("SAE") && !flags.contains("PSK") {
+                "WPA3".to_string()
+            } else if flags.contains("WPA2") {
                 "WPA2".to_string()
             } else if flags.contains("WPA") {
                 "WPA".to_string()
@@ -341,13 +642,72 @@ impl WpaCtrlBackend {
 
             networks.push(Network {
                 ssid,
+                ssid_hex: hex::encode(&ssid_bytes),
+                display_name,
+                encoding,
                 signal: signal_percent,
+                frequency,
                 security,
             });
         }
         Ok(networks)
     }
 
+    /// 辅助函数：从 STATUS 的输出中取出某个字段的值
+    /// 格式: 每行一个 key=value
+    fn parse_status_field<'a>(status: &'a str, field: &str) -> Option<&'a str> {
+        status
+            .lines()
+            .filter_map(|line| line.split_once('='))
+            .find(|(key, _)| *key == field)
+            .map(|(_, value)| value)
+    }
+
+    /// 发布连接子步骤事件
+    fn publish_step(&self, step: ConnectStep) {
+        self.events.publish(ProvisionEvent::ConnectStep { step });
+    }
+
+    /// 公共方法：扫描一次并更新缓存（不启动 AP）
+    ///
+    /// 单网卡模式下 AP 运行时无法扫描，只应在 AP 启动前调用；
+    /// 双射频模式下 STA 网卡独立工作，可以随时调用。
+    pub async fn scan(&self) -> Result<Vec<Network>> {
+        let _guard = self.scan_lock.lock().await;
+        let networks = self.scan_internal().await?;
+        *self.scan_cache.lock().unwrap() = networks.clone();
+        Ok(networks)
+    }
+
+    /// 公共方法：查询 wpa_supplicant 的 STATUS，返回 key=value 列表
+    pub async fn status(&self) -> Result<Vec<(String, String)>> {
+        let status = self.send_cmd("STATUS".to_string()).await?;
+        Ok(status
+            .lines()
+            .filter_map(|line| line.split_once('='))
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect())
+    }
+
+    /// 公共方法：检查 wpa_supplicant 控制接口是否正常（PING -> PONG）
+    pub async fn ping(&self) -> Result<()> {
+        let reply = self.send_cmd("PING".to_string()).await?;
+        if reply.trim() != "PONG" {
+            return Err(anyhow!("unexpected PING reply: {}", reply.trim()));
+        }
+        Ok(())
+    }
+
+    /// 公共方法：删除所有已保存的网络，并在允许时写回配置文件
+    pub async fn forget_networks(&self) -> Result<()> {
+        self.send_cmd("REMOVE_NETWORK all".to_string()).await?;
+        if self.ap_config.wpa_update_config {
+            self.send_cmd("SAVE_CONFIG".to_string()).await?;
+        }
+        tracing::info!("All saved networks removed.");
+        Ok(())
+    }
+
     /// 内部扫描方法（轮询模式）
     async fn scan_internal(&self) -> Result<Vec<Network>> {
         tracing::debug!("Sending SCAN command...");
@@ -359,11 +719,57 @@ impl WpaCtrlBackend {
         
         tracing::debug!("Scan wait complete, fetching results.");
         let results_str = self.send_cmd("SCAN_RESULTS".to_string()).await?;
-        Self::parse_scan_results(&results_str)
+        Self::parse_scan_results(&results_str, self.ap_config.ssid_legacy_charset)
+    }
+
+    /// hostapd 以 `-B` 后台运行时写入的 PID 文件
+    fn hostapd_pid_path(&self) -> String {
+        format!("{}.pid", self.ap_config.hostapd_conf_path)
+    }
+
+    /// AP 使用的信道
+    ///
+    /// `hostapd_channel = "auto"` 时在第一次启动 AP 前根据扫描缓存选择，之后重启 AP
+    /// 沿用同一信道，避免手机保存的网络频繁换信道（AP 运行后的扫描结果也会包含自己）。
+    fn ap_channel(&self) -> u8 {
+        if let Some(fixed) = self.ap_config.hostapd_channel.fixed() {
+            return fixed;
+        }
+        let mut chosen = self.ap_channel.lock().unwrap();
+        if let Some(channel) = *chosen {
+            return channel;
+        }
+
+        let hw_mode = &self.ap_config.hostapd_hw_mode;
+        let networks = self.scan_cache.lock().unwrap().clone();
+        if networks.is_empty() {
+            tracing::warn!("No scan results for hostapd_channel = \"auto\", choosing without them");
+        }
+        let country = self.ap_config.hostapd_country_code.as_deref();
+        let channel = match channel::select_channel(hw_mode, country, &networks) {
+            Some(choice) => {
+                let scores: Vec<String> = choice.scores.iter().map(|(c, s)| format!("{}:{}", c, s)).collect();
+                tracing::info!(
+                    "📡 Auto-selected AP channel {} (score {}, {} networks seen; channel:score {})",
+                    choice.channel,
+                    choice.score(),
+                    networks.len(),
+                    scores.join(" ")
+                );
+                choice.channel
+            }
+            None => channel::default_channel(hw_mode),
+        };
+        *chosen = Some(channel);
+        channel
     }
 
     /// 启动 AP 模式
-    async fn start_ap(&self) -> Result<()> {
+    pub async fn start_ap(&self) -> Result<()> {
+        // 连接失败后会重新开启 AP；正在退出时不能再启动，否则 hostapd 会在进程退出后遗留下来
+        if let Some(signal) = shutdown::requested_signal() {
+            return Err(anyhow!("not starting the AP, {} received", signal));
+        }
         // 使用 stop_ap() 而不是粗暴的 killall
         let _ = self.stop_ap().await;
 
@@ -373,7 +779,7 @@ impl WpaCtrlBackend {
             .arg("add")
             .arg(&self.ap_config.gateway_cidr)
             .arg("dev")
-            .arg(&self.ap_config.interface_name)
+            .arg(self.ap_config.ap_interface())
             .output()
             .await?;
 
@@ -384,19 +790,11 @@ impl WpaCtrlBackend {
             }
         }
 
-        // 生成 hostapd 配置
-        let hostapd_conf = format!(
-            "interface={}\nssid={}\nwpa={}\nwpa_passphrase={}\nhw_mode={}\nchannel={}\nwpa_key_mgmt={}\nwpa_pairwise={}\nrsn_pairwise={}\n",
-            self.ap_config.interface_name,
-            self.ap_config.ssid,
-            self.ap_config.hostapd_wpa,
-            self.ap_config.psk,
-            self.ap_config.hostapd_hw_mode,
-            self.ap_config.hostapd_channel,
-            self.ap_config.hostapd_wpa_key_mgmt,
-            self.ap_config.hostapd_wpa_pairwise,
-            self.ap_config.hostapd_rsn_pairwise
-        );
+        // 生成 hostapd 配置（取值已在加载配置时校验过）
+        let hostapd_conf = HostapdConfig::from_ap_config(&self.ap_config)
+            .channel(self.ap_channel())
+            .render()
+            .context("Invalid hostapd configuration")?;
 
         // 写入 hostapd 配置文件
         fs::write(&self.ap_config.hostapd_conf_path, hostapd_conf.as_bytes()).await?;
@@ -406,16 +804,19 @@ impl WpaCtrlBackend {
         );
 
         // 启动 hostapd
+        // -B 会让 hostapd 自己 fork 到后台，所以用 -P 记录真正的 PID，停止时据此结束进程
         let child = Command::new("hostapd")
             .arg(&self.ap_config.hostapd_conf_path)
             .arg("-B")
+            .arg("-P")
+            .arg(self.hostapd_pid_path())
             .spawn()?;
         *self.hostapd.lock().await = Some(child);
 
         // 启动 dnsmasq
         let ap_ip_only = self.ap_config.gateway_cidr.split('/').next().unwrap_or("");
         let dnsmasq_child = Command::new("dnsmasq")
-            .arg(format!("--interface={}", self.ap_config.interface_name))
+            .arg(format!("--interface={}", self.ap_config.ap_interface()))
             .arg(format!("--dhcp-range={}", self.ap_config.dhcp_range))
             .arg(format!("--address=/#/{}", ap_ip_only))
             .arg("--no-resolv")
@@ -424,15 +825,29 @@ impl WpaCtrlBackend {
             .spawn()?;
 
         *self.dnsmasq.lock().await = Some(dnsmasq_child);
+        self.ap_active.store(true, Ordering::SeqCst);
+
+        // 供显示程序读取的入网二维码
+        if let Some(path) = &self.ap_config.qr_output_path
+            && let Err(e) = qr::write_qr_file(&self.ap_config, std::path::Path::new(path))
+        {
+            tracing::warn!("Failed to write AP QR code: {:#}", e);
+        }
         tracing::info!(
             "AP started successfully on {}",
-            self.ap_config.interface_name
+            self.ap_config.ap_interface()
         );
+        self.events.publish(ProvisionEvent::ApStarted {
+            ssid: self.ap_config.ssid.clone(),
+        });
+        self.set_state(ProvisionerState::Provisioning);
         Ok(())
     }
 
     /// 停止 AP 模式
-    async fn stop_ap(&self) -> Result<()> {
+    pub async fn stop_ap(&self) -> Result<()> {
+        self.ap_active.store(false, Ordering::SeqCst);
+
         // 杀死我们启动的进程
         if let Some(mut child) = self.dnsmasq.lock().await.take() {
             let _ = child.kill().await;
@@ -440,6 +855,12 @@ impl WpaCtrlBackend {
         if let Some(mut child) = self.hostapd.lock().await.take() {
             let _ = child.kill().await;
         }
+        // 结束后台运行的 hostapd 守护进程
+        let pid_path = self.hostapd_pid_path();
+        if let Ok(pid) = fs::read_to_string(&pid_path).await {
+            let _ = Command::new("kill").arg(pid.trim()).status().await;
+            let _ = fs::remove_file(&pid_path).await;
+        }
 
         // 移除 IP 地址配置
         let output = Command::new("ip")
@@ -447,7 +868,7 @@ impl WpaCtrlBackend {
             .arg("del")
             .arg(&self.ap_config.gateway_cidr)
             .arg("dev")
-            .arg(&self.ap_config.interface_name)
+            .arg(self.ap_config.ap_interface())
             .output()
             .await?;
 
@@ -458,13 +879,25 @@ impl WpaCtrlBackend {
             }
         }
 
-        // 清理 hostapd 配置文件
+        // 清理 hostapd 配置文件和二维码
         let _ = fs::remove_file(&self.ap_config.hostapd_conf_path).await;
+        if let Some(path) = &self.ap_config.qr_output_path {
+            let _ = fs::remove_file(path).await;
+        }
 
-        tracing::info!("AP stopped on {}", self.ap_config.interface_name);
+        tracing::info!("AP stopped on {}", self.ap_config.ap_interface());
+        self.events.publish(ProvisionEvent::ApStopped);
         Ok(())
     }
 
+    /// 公共方法：停止由另一个进程（例如已退出的 provisioner）遗留的 AP
+    ///
+    /// 除了 `stop_ap` 的清理外，还会结束不受本进程管理的 dnsmasq。
+    pub async fn stop_orphan_ap(&self) -> Result<()> {
+        let _ = Command::new("killall").arg("dnsmasq").status().await;
+        self.stop_ap().await
+    }
+
     /// 公共方法：扫描并启动 AP（TDM 模式）
     pub async fn setup_and_scan(&self) -> Result<Vec<Network>> {
         let mut networks;
@@ -474,8 +907,15 @@ impl WpaCtrlBackend {
         loop {
             // 1. 尝试执行内部扫描
             // (scan_internal 内部已经包含了 10 秒的等待)
-            println!("Attempting to scan for networks (attempt {}/{})...", retry_count + 1, max_retries);
+            tracing::info!("Attempting to scan for networks (attempt {}/{})...", retry_count + 1, max_retries);
+            self.events.publish(ProvisionEvent::ScanStarted {
+                attempt: retry_count + 1,
+                max_attempts: max_retries,
+            });
             networks = self.scan_internal().await?;
+            self.events.publish(ProvisionEvent::ScanCompleted {
+                count: networks.len(),
+            });
             
             // 2. 检查结果
             if networks.is_empty() {
@@ -509,6 +949,8 @@ impl WpaCtrlBackend {
             break;
         }
 
+        *self.scan_cache.lock().unwrap() = networks.clone();
+
         // 5. 只有在成功扫描后，才启动 AP
         self.start_ap().await?;
         self.audio_notifier.play(AudioEvent::ApStarted).await;
@@ -516,48 +958,339 @@ impl WpaCtrlBackend {
     }
 
     /// 公共方法：连接到指定网络（轮询模式）
+    ///
+    /// 失败时会记录本次尝试的结果；如果连接前 AP 正在运行而连接过程中被关闭
+    /// （单网卡分时复用），则重启 AP，以便用户重新连上 AP 后能看到失败原因。
+    /// 双射频模式下 AP 在整个连接过程中保持运行。
+    /// 这里不设置 `connecting` 标记，由 `start_connect` 负责，保证同一时间只有一次连接。
     pub async fn connect(&self, req: &ConnectionRequest) -> Result<()> {
-        // 停止 AP
-        let _ = self.stop_ap().await;
-        self.audio_notifier.play(AudioEvent::ConnectionStarted).await;
-        tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+        let restore_ap = self.ap_active.load(Ordering::SeqCst);
+        let ssid = req.display_ssid();
 
-        tracing::debug!("Adding new network...");
-        let net_id_str = self.send_cmd("ADD_NETWORK".to_string()).await?;
-        let net_id = net_id_str.trim().parse::<u32>()
-            .context("Failed to parse ADD_NETWORK response")?;
+        // 新的尝试开始，旧的记录不再有意义
+        self.last_attempt.lock().unwrap().take();
+        let started_at = SystemTime::now();
+        let start_time = tokio::time::Instant::now();
 
-        tracing::debug!(net_id, "Configuring network...");
+        self.events.publish(ProvisionEvent::ConnectStarted {
+            ssid: ssid.clone(),
+        });
+
+        self.set_state(ProvisionerState::Connecting);
+        let result = self.connect_inner(req).await;
+        self.touch_activity();
+        let timestamp = started_at
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let duration_ms = start_time.elapsed().as_millis() as u64;
+        let err = match result {
+            Ok(()) => {
+                *self.last_result.lock().unwrap() = Some(ConnectResult {
+                    ssid,
+                    timestamp,
+                    success: true,
+                    reason: None,
+                    duration_ms,
+                });
+                self.set_state(ProvisionerState::Online);
+                // 通知配网会话结束（不在会话中时没有等待者，通知直接丢弃）
+                self.provisioned.notify_waiters();
+                return Ok(());
+            }
+            Err(e) => e,
+        };
+
+        let reason = err
+            .downcast_ref::<FailureReason>()
+            .copied()
+            .unwrap_or(FailureReason::Internal);
+        tracing::error!(ssid = %ssid, %reason, "Connection attempt failed: {}", err);
+
+        // 先记录结果，再恢复 AP，保证手机重连后一定能读到
+        *self.last_attempt.lock().unwrap() = Some(LastAttempt {
+            ssid: ssid.clone(),
+            timestamp,
+            reason,
+            duration_ms,
+        });
+        *self.last_result.lock().unwrap() = Some(ConnectResult {
+            ssid: ssid.clone(),
+            timestamp,
+            success: false,
+            reason: Some(reason),
+            duration_ms,
+        });
+        self.events.publish(ProvisionEvent::ConnectFailed {
+            ssid: ssid.clone(),
+            reason,
+        });
+        self.audio_notifier.play(AudioEvent::ConnectionFailed).await;
+        if restore_ap && !self.ap_active.load(Ordering::SeqCst) {
+            let _ = self.start_ap().await;
+        }
+        self.set_state(if self.is_ap_active() {
+            ProvisionerState::Provisioning
+        } else {
+            ProvisionerState::Offline
+        });
+        Err(err)
+    }
+
+    /// 在 STA 网卡上运行一次 DHCP 客户端（udhcpc），结果只记录日志
+    async fn run_dhcp(&self) {
+        let dhcp_status = tokio::process::Command::new("udhcpc")
+            .arg("-i")
+            .arg(self.ap_config.sta_interface())
+            .arg("-q") // 安静模式，减少日志
+            .arg("-n") // 获取 IP 后立即退出，不要作为守护进程
+            .status()
+            .await;
 
-        // 使用 Hex 编码 SSID，以支持所有特殊字符
-        let ssid_hex = hex::encode(&req.ssid);
+        if let Ok(status) = dhcp_status {
+            if status.success() {
+                tracing::info!("DHCP client (udhcpc) successfully obtained an IP.");
+            } else {
+                tracing::warn!("DHCP client (udhcpc) exited with an error.");
+            }
+        } else {
+            tracing::error!("Failed to execute 'udhcpc'. Is it installed on this board?");
+        }
+    }
+
+    /// 公共方法：重新启用 wpa_supplicant 中已保存的网络并等待连接
+    ///
+    /// 配网超时后用于回到之前的网络。没有已保存的网络时返回 `Ok(false)`；
+    /// 30 秒内连上时运行 DHCP 并返回 `Ok(true)`。
+    pub async fn restore_saved_networks(&self) -> Result<bool> {
+        let saved = self.saved_network_count().await?;
+        if saved == 0 {
+            return Ok(false);
+        }
+
+        tracing::info!("Re-enabling {} saved network(s)...", saved);
+        self.send_cmd("ENABLE_NETWORK all".to_string()).await?;
+        self.send_cmd("RECONNECT".to_string()).await?;
+
+        let start_time = tokio::time::Instant::now();
+        while start_time.elapsed() < Duration::from_secs(30) {
+            tokio::time::sleep(Duration::from_secs(2)).await;
+            let status = self.send_cmd("STATUS".to_string()).await.unwrap_or_default();
+            if Self::parse_status_field(&status, "wpa_state") == Some("COMPLETED") {
+                let ssid = Self::parse_status_field(&status, "ssid").unwrap_or("");
+                tracing::info!(ssid = %ssid, "Reconnected to a saved network.");
+                self.run_dhcp().await;
+                self.set_state(ProvisionerState::Online);
+                return Ok(true);
+            }
+        }
+        Err(anyhow!("no saved network connected within 30s"))
+    }
+
+    /// 公共方法：重新启用所有已保存的网络，不等待连接结果（退出前调用）
+    pub async fn enable_saved_networks(&self) -> Result<()> {
+        self.send_cmd("ENABLE_NETWORK all".to_string()).await?;
+        Ok(())
+    }
+
+    /// 公共方法：禁用所有已保存的网络（重新配网前调用，避免 wpa_supplicant 和 AP 争用网卡）
+    pub async fn disable_saved_networks(&self) -> Result<()> {
+        self.send_cmd("DISABLE_NETWORK all".to_string()).await?;
+        Ok(())
+    }
+
+    /// 公共方法：当前是否已连上网络（wpa_state 为 COMPLETED）
+    pub async fn is_connected(&self) -> bool {
+        let status = self.send_cmd("STATUS".to_string()).await.unwrap_or_default();
+        Self::parse_status_field(&status, "wpa_state") == Some("COMPLETED")
+    }
+
+    /// 公共方法：订阅 wpa_supplicant 主动推送的事件（例如 `<3>CTRL-EVENT-CONNECTED ...`）
+    ///
+    /// 单独打开一个控制套接字并 ATTACH，由后台线程转发消息；
+    /// wpa_supplicant 退出或接收端被丢弃时结束，接收端随之关闭。
+    pub fn subscribe_wpa_events(&self) -> Result<tokio::sync::mpsc::Receiver<String>> {
+        use wpa_ctrl::WpaControlReq;
+
+        let mut ctrl = WpaControllerBuilder::new()
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .open(self.ap_config.sta_interface())
+            .context("Failed to open wpa_supplicant event socket")?;
+        ctrl.request(WpaControlReq::raw("ATTACH"))
+            .context("Failed to send ATTACH")?;
+        match ctrl.recv_req_result() {
+            Some(Ok(Ok(()))) => {}
+            _ => return Err(anyhow!("wpa_supplicant did not accept ATTACH")),
+        }
+
+        let (sender, receiver) = tokio::sync::mpsc::channel(32);
+        std::thread::spawn(move || {
+            let mut quiet_secs = 0u32;
+            while !sender.is_closed() {
+                match ctrl.recv() {
+                    Ok(Some(msg)) => {
+                        quiet_secs = 0;
+                        if msg.is_unsolicited() && sender.blocking_send(msg.raw.to_string()).is_err() {
+                            break;
+                        }
+                    }
+                    Ok(None) => {
+                        // 长时间没有事件时 PING 一下，wpa_supplicant 退出后发送会失败
+                        quiet_secs += 1;
+                        if quiet_secs.is_multiple_of(30) && ctrl.request(WpaControlReq::ping()).is_err() {
+                            tracing::warn!("wpa_supplicant event socket closed");
+                            break;
+                        }
+                    }
+                    Err(e) => {
+                        tracing::warn!("wpa_supplicant event socket failed: {}", e);
+                        break;
+                    }
+                }
+            }
+            let _ = ctrl.request(WpaControlReq::raw("DETACH"));
+        });
+        Ok(receiver)
+    }
+
+    /// 公共方法：wpa_supplicant 中保存的网络
+    pub async fn saved_networks(&self) -> Result<Vec<SavedNetwork>> {
+        let list = self.send_cmd("LIST_NETWORKS".to_string()).await?;
+        // 第一行是表头: network id / ssid / bssid / flags
+        Ok(list
+            .lines()
+            .skip(1)
+            .filter_map(|line| {
+                let mut fields = line.split('\t');
+                let id = fields.next()?.trim().parse().ok()?;
+                let ssid = fields.next().unwrap_or("").to_string();
+                let flags = fields.nth(1).unwrap_or("").to_string();
+                Some(SavedNetwork { id, ssid, flags })
+            })
+            .collect())
+    }
+
+    /// 公共方法：wpa_supplicant 中保存的网络数量
+    pub async fn saved_network_count(&self) -> Result<usize> {
+        Ok(self.saved_networks().await?.len())
+    }
+
+    /// 公共方法：在 `grace` 时间内等待已保存的网络连上并拿到 IP
+    ///
+    /// 关联成功但迟迟没有 IP 时运行一次 DHCP 客户端。没有已保存的网络时立即返回 `Ok(false)`。
+    pub async fn wait_until_online(&self, grace: Duration) -> Result<bool> {
+        if self.saved_network_count().await? == 0 {
+            tracing::info!("No saved network in {}.", self.ap_config.wpa_conf_path);
+            self.set_state(ProvisionerState::Offline);
+            return Ok(false);
+        }
+
+        let start_time = tokio::time::Instant::now();
+        let mut dhcp_tried = false;
+        while start_time.elapsed() < grace {
+            tokio::time::sleep(Duration::from_secs(2)).await;
+            let status = self.send_cmd("STATUS".to_string()).await.unwrap_or_default();
+            if Self::parse_status_field(&status, "wpa_state") != Some("COMPLETED") {
+                continue;
+            }
+            if let Some(ip) = Self::parse_status_field(&status, "ip_address") {
+                let ssid = Self::parse_status_field(&status, "ssid").unwrap_or("");
+                tracing::info!(ssid = %ssid, ip = %ip, "Already online.");
+                self.set_state(ProvisionerState::Online);
+                return Ok(true);
+            }
+            if !dhcp_tried {
+                dhcp_tried = true;
+                self.run_dhcp().await;
+            }
+        }
+        self.set_state(ProvisionerState::Offline);
+        Ok(false)
+    }
+
+    /// 为 `ADD_NETWORK` 新建的条目设置 SSID 和密钥并启用它
+    async fn configure_network(&self, net_id: u32, req: &ConnectionRequest, ssid_bytes: &[u8]) -> Result<()> {
+        // 使用 Hex 编码 SSID 原始字节，保证配置的正是路由器广播的字节
+        let ssid_hex = hex::encode(ssid_bytes);
         self.send_cmd(format!("SET_NETWORK {} ssid {}", net_id, ssid_hex)).await?;
 
         // 设置密码或开放网络
+        let is_sae = self
+            .cached_networks()
+            .iter()
+            .any(|n| n.ssid_hex == ssid_hex && n.security == "WPA3");
         if req.password.is_empty() {
             self.send_cmd(format!("SET_NETWORK {} key_mgmt NONE", net_id)).await?;
+        } else if is_sae {
+            // SAE 需要明文密码，使用 P"..." 转义格式传递
+            self.send_cmd(format!("SET_NETWORK {} key_mgmt SAE", net_id)).await?;
+            self.send_cmd(format!("SET_NETWORK {} ieee80211w 2", net_id)).await?;
+            self.send_cmd(format!(
+                "SET_NETWORK {} sae_password {}",
+                net_id,
+                wpa_printf_quote(req.password.as_bytes())
+            ))
+            .await?;
         } else {
-            // PSK (密码) 仍然使用引号
-            self.send_cmd(format!("SET_NETWORK {} psk \"{}\"", net_id, req.password)).await?;
+            // 在本地派生 PSK，以 64 位十六进制发送，避免引号、反斜杠等字符破坏命令
+            let psk_hex = if is_hex_psk(&req.password) {
+                req.password.to_ascii_lowercase()
+            } else {
+                derive_psk_hex(ssid_bytes, &req.password)
+            };
+            self.send_cmd(format!("SET_NETWORK {} psk {}", net_id, psk_hex)).await?;
         }
 
         // 启用网络
         self.send_cmd(format!("ENABLE_NETWORK {}", net_id)).await?;
+        Ok(())
+    }
+
+    /// 实际的连接流程；失败时返回的错误携带 `FailureReason`
+    async fn connect_inner(&self, req: &ConnectionRequest) -> Result<()> {
+        let ssid = req.display_ssid();
+        let ssid_bytes = req.ssid_bytes().context("Invalid ssid_hex in request")?;
+
+        // 单网卡时 AP 和 STA 不能同时工作，先停止 AP；双射频模式下门户保持在线
+        if !self.is_dual_radio() {
+            let _ = self.stop_ap().await;
+        }
+        self.audio_notifier.play(AudioEvent::ConnectionStarted).await;
+        tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+        tracing::debug!("Adding new network...");
+        let net_id_str = self.send_cmd("ADD_NETWORK".to_string()).await?;
+        let net_id = net_id_str.trim().parse::<u32>()
+            .context("Failed to parse ADD_NETWORK response")?;
+
+        tracing::debug!(net_id, "Configuring network...");
+        // ADD_NETWORK 之后任何一步失败都要删掉这个条目，否则开启 wpa_update_config
+        // 时它会在下一次 SAVE_CONFIG 中被写进配置文件
+        if let Err(e) = self.configure_network(net_id, req, &ssid_bytes).await {
+            let _ = self.send_cmd(format!("REMOVE_NETWORK {}", net_id)).await;
+            return Err(e);
+        }
 
         // 轮询 STATUS 命令来检测连接状态
-        tracing::info!(ssid = %req.ssid, "Connecting... Polling status.");
+        tracing::info!(ssid = %ssid, "Connecting... Polling status.");
         let start_time = tokio::time::Instant::now();
         let timeout = Duration::from_secs(30);
+        // 只在子步骤变化时推送事件，避免轮询产生重复事件
+        let mut last_step: Option<ConnectStep> = None;
 
         loop {
             // 1. 检查总超时
             if start_time.elapsed() > timeout {
-                tracing::error!(ssid = %req.ssid, "Connection timed out after 30s");
-                self.audio_notifier.play(AudioEvent::ConnectionFailed).await;
-                // 超时：清理网络并尝试恢复 AP
+                tracing::error!(ssid = %ssid, "Connection timed out after 30s");
+                // 超时：清理网络（AP 由 connect 负责恢复）
                 let _ = self.send_cmd(format!("REMOVE_NETWORK {}", net_id)).await;
-                let _ = self.start_ap().await;
-                return Err(anyhow!("Connection timed out"));
+                // 卡在握手阶段直到超时，同样是密码错误的典型表现
+                let reason = if last_step == Some(ConnectStep::Handshake) {
+                    FailureReason::WrongPassword
+                } else {
+                    FailureReason::Timeout
+                };
+                return Err(reason.into());
             }
 
             // 2. 轮询间隔
@@ -573,20 +1306,17 @@ impl WpaCtrlBackend {
             };
 
             // 4. 解析状态，查找 wpa_state
-            let mut wpa_state = "";
-            for line in status_str.lines() {
-                if let Some((key, value)) = line.split_once('=') {
-                    if key == "wpa_state" {
-                        wpa_state = value;
-                        break;
-                    }
-                }
-            }
+            let wpa_state = Self::parse_status_field(&status_str, "wpa_state").unwrap_or("");
             
             // 5. 状态机处理
             match wpa_state {
+                // 可能仍连着另一个网络，只有当前网络是刚添加的条目时才算成功
+                "COMPLETED" if Self::parse_status_field(&status_str, "id") != Some(net_id.to_string().as_str()) => {
+                    tracing::debug!("Still associated with another network, waiting...");
+                    continue;
+                }
                 "COMPLETED" => {
-                    tracing::info!(ssid = %req.ssid, "Connection successful (state: COMPLETED)");
+                    tracing::info!(ssid = %ssid, "Connection successful (state: COMPLETED)");
                     // 成功后，可以选择保存配置
                     if self.ap_config.wpa_update_config {
                         let _ = self.send_cmd("SAVE_CONFIG".to_string()).await;
@@ -598,33 +1328,43 @@ impl WpaCtrlBackend {
 
                     // 自动运行 DHCP 客户端
                     tracing::info!("Connection complete. Attempting to run DHCP client (udhcpc)...");
-                    let dhcp_status = tokio::process::Command::new("udhcpc")
-                        .arg("-i")
-                        .arg(&self.ap_config.interface_name)
-                        .arg("-q") // 安静模式，减少日志
-                        .arg("-n") // 获取 IP 后立即退出，不要作为守护进程
-                        .status()
-                        .await;
-
-                    if let Ok(status) = dhcp_status {
-                        if status.success() {
-                            tracing::info!("DHCP client (udhcpc) successfully obtained an IP.");
-                        } else {
-                            tracing::warn!("DHCP client (udhcpc) exited with an error.");
-                        }
-                    } else {
-                        tracing::error!("Failed to execute 'udhcpc'. Is it installed on this board?");
+                    self.publish_step(ConnectStep::Dhcp);
+                    self.run_dhcp().await;
+
+                    // 校验最终状态，并通知订阅者
+                    self.publish_step(ConnectStep::Verifying);
+                    let status = self.send_cmd("STATUS".to_string()).await.unwrap_or_default();
+                    let ip_address = Self::parse_status_field(&status, "ip_address");
+                    tracing::info!(ip = ?ip_address, "Connection verified.");
+                    self.events.publish(ProvisionEvent::ConnectSucceeded {
+                        ssid: ssid.clone(),
+                    });
+                    // 给 SSE 连接留出把最终结果推送出去的时间
+                    tokio::time::sleep(Duration::from_millis(500)).await;
+
+                    // 双射频模式下 AP 仍在运行，退出前关闭它
+                    if self.ap_active.load(Ordering::SeqCst) {
+                        let _ = self.stop_ap().await;
                     }
 
-                    // 自动退出程序
-                    println!("Provisioning complete. Shutting down application.");
-                    // 成功退出 (状态码 0)
-                    std::process::exit(0);
+                    return Ok(());
                 }
-                "ASSOCIATING" | "ASSOCIATED" | "4WAY_HANDSHAKE" | "GROUP_HANDSHAKE" => {
+                "ASSOCIATING" | "ASSOCIATED" => {
                     tracing::debug!("Connection in progress (state: {})...", wpa_state);
+                    if last_step != Some(ConnectStep::Associating) {
+                        last_step = Some(ConnectStep::Associating);
+                        self.publish_step(ConnectStep::Associating);
+                    }
                     continue; // 还在连接中，继续轮询
                 }
+                "4WAY_HANDSHAKE" | "GROUP_HANDSHAKE" => {
+                    tracing::debug!("Connection in progress (state: {})...", wpa_state);
+                    if last_step != Some(ConnectStep::Handshake) {
+                        last_step = Some(ConnectStep::Handshake);
+                        self.publish_step(ConnectStep::Handshake);
+                    }
+                    continue;
+                }
                 "SCANNING" => {
                     tracing::debug!("wpa_supplicant is scanning...");
                     continue;
@@ -636,11 +1376,15 @@ impl WpaCtrlBackend {
                         continue;
                     }
                     // 5 秒后仍然是 DISCONNECTED，说明连接失败
-                    tracing::error!(ssid = %req.ssid, "Connection failed (state: {})", wpa_state);
-                    self.audio_notifier.play(AudioEvent::ConnectionFailed).await;
+                    tracing::error!(ssid = %ssid, "Connection failed (state: {})", wpa_state);
                     let _ = self.send_cmd(format!("REMOVE_NETWORK {}", net_id)).await;
-                    let _ = self.start_ap().await;
-                    return Err(anyhow!("Connection failed (state: {})", wpa_state));
+                    // 走到过握手阶段却被断开，基本可以认定是密码错误
+                    let reason = if last_step == Some(ConnectStep::Handshake) {
+                        FailureReason::WrongPassword
+                    } else {
+                        FailureReason::AssociationFailed
+                    };
+                    return Err(reason.into());
                 }
                 _ => {
                     tracing::warn!("Unknown wpa_state: '{}'", wpa_state);
@@ -650,3 +1394,58 @@ impl WpaCtrlBackend {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixed_channel_ignores_scan_results() {
+        let config = crate::config::test_app_config("[ap]\nhostapd_channel = 11\n");
+        let backend = WpaCtrlBackend::detached(&config);
+        let crowded = "bssid / frequency / signal level / flags / ssid\n\
+            00:11:22:33:44:55\t2462\t-40\t[ESS]\tCrowded\n";
+        *backend.scan_cache.lock().unwrap() =
+            WpaCtrlBackend::parse_scan_results(crowded, SsidEncoding::Utf8).unwrap();
+        assert_eq!(backend.ap_channel(), 11);
+    }
+
+    #[test]
+    fn auto_channel_is_chosen_once() {
+        let config = crate::config::test_app_config("[ap]\nhostapd_channel = \"auto\"\n");
+        let backend = WpaCtrlBackend::detached(&config);
+        let crowded = "bssid / frequency / signal level / flags / ssid\n\
+            00:11:22:33:44:55\t2437\t-40\t[ESS]\tCrowded\n";
+        *backend.scan_cache.lock().unwrap() =
+            WpaCtrlBackend::parse_scan_results(crowded, SsidEncoding::Utf8).unwrap();
+        assert_eq!(backend.ap_channel(), 1);
+        // 之后重启 AP 保持同一信道
+        backend.scan_cache.lock().unwrap().clear();
+        assert_eq!(backend.ap_channel(), 1);
+    }
+
+    #[test]
+    fn scan_results_decode_legacy_ssids() {
+        let output = "bssid / frequency / signal level / flags / ssid\n\
+            00:11:22:33:44:55\t2437\t-60\t[WPA2-PSK-CCMP][ESS]\t\\xce\\xd2\\xb5\\xc4\\xbc\\xd2\n\
+            00:11:22:33:44:66\t2412\t-70\t[ESS]\tCafe\n\
+            00:11:22:33:44:77\t5180\t-50\t[RSN-SAE-CCMP][ESS]\t\\xe5\\x92\\x96\\xe5\\x95\\xa1\n";
+        let networks = WpaCtrlBackend::parse_scan_results(output, SsidEncoding::Gb18030).unwrap();
+        assert_eq!(networks.len(), 3);
+
+        assert_eq!(networks[0].display_name, "我的家");
+        assert_eq!(networks[0].encoding, SsidEncoding::Gb18030);
+        assert_eq!(networks[0].ssid_hex, "ced2b5c4bcd2");
+        assert_eq!(networks[0].security, "WPA2");
+        assert_eq!(networks[0].signal, 80);
+
+        assert_eq!(networks[1].display_name, "Cafe");
+        assert_eq!(networks[1].encoding, SsidEncoding::Utf8);
+        assert_eq!(networks[1].security, "Open");
+
+        assert_eq!(networks[2].display_name, "咖啡");
+        assert_eq!(networks[2].encoding, SsidEncoding::Utf8);
+        assert_eq!(networks[2].security, "WPA3");
+        assert_eq!(networks[2].frequency, 5180);
+    }
+}
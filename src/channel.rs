@@ -0,0 +1,292 @@
+use crate::structs::Network;
+use serde::{Deserialize, Deserializer};
+use std::fmt;
+
+/// `hostapd_channel` 取这个值时根据扫描结果自动选择信道
+pub const AUTO_CHANNEL: &str = "auto";
+
+/// 合法的 5GHz 20MHz 信道（含 DFS 信道）
+pub const CHANNELS_5GHZ: &[u8] = &[
+    36, 40, 44, 48, 52, 56, 60, 64, 100, 104, 108, 112, 116, 120, 124, 128, 132, 136, 140, 144,
+    149, 153, 157, 161, 165,
+];
+
+/// 所有国家都允许的 5GHz 非 DFS 信道（UNII-1）
+const CHANNELS_5GHZ_UNII1: &[u8] = &[36, 40, 44, 48];
+/// 5GHz UNII-3 信道，仅部分国家允许用于 AP
+const CHANNELS_5GHZ_UNII3: &[u8] = &[149, 153, 157, 161, 165];
+
+/// 2.4GHz 只允许 1-11 信道的国家
+const COUNTRIES_2GHZ_11: &[&str] = &["US", "CA", "MX", "PR", "TW"];
+/// 允许在 UNII-3 (149-165) 上运行 AP 的国家
+const COUNTRIES_5GHZ_UNII3: &[&str] = &[
+    "US", "CA", "MX", "PR", "BR", "AU", "NZ", "CN", "HK", "IN", "KR", "SG", "TW",
+];
+
+/// 同分数时优先选择的 2.4GHz 信道（互不重叠的 6、1、11）
+const PREFERRED_2GHZ: &[u8] = &[6, 1, 11];
+
+/// 同信道的 BSS 计 5 份；2.4GHz 相邻信道中心频率相差 5MHz，
+/// 20MHz 宽的信号会波及两侧各 4 个信道，每远一个信道少计 1 份
+const FULL_OVERLAP: u32 = 5;
+
+/// `hostapd_channel` 的取值
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelSetting {
+    /// 固定信道
+    Fixed(u8),
+    /// 启动 AP 前根据最近一次扫描结果选择最空闲的信道
+    Auto,
+}
+
+impl ChannelSetting {
+    pub fn fixed(&self) -> Option<u8> {
+        match self {
+            ChannelSetting::Fixed(channel) => Some(*channel),
+            ChannelSetting::Auto => None,
+        }
+    }
+}
+
+impl fmt::Display for ChannelSetting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChannelSetting::Fixed(channel) => write!(f, "{}", channel),
+            ChannelSetting::Auto => f.write_str(AUTO_CHANNEL),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ChannelSetting {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Number(u8),
+            Text(String),
+        }
+        match Raw::deserialize(deserializer)? {
+            Raw::Number(channel) => Ok(ChannelSetting::Fixed(channel)),
+            Raw::Text(text) if text == AUTO_CHANNEL => Ok(ChannelSetting::Auto),
+            Raw::Text(text) => Err(serde::de::Error::custom(format!(
+                "expected a channel number or \"{}\", got '{}'",
+                AUTO_CHANNEL, text
+            ))),
+        }
+    }
+}
+
+/// 频段的默认信道（自动选择前或没有可用信道时使用）
+pub fn default_channel(hw_mode: &str) -> u8 {
+    if hw_mode == "a" { 36 } else { 6 }
+}
+
+/// 按 `hw_mode` 和国家码列出允许自动选择的信道
+///
+/// 没有国家码时只使用全球通用的信道（2.4GHz 1-11，5GHz 36-48）。
+/// 5GHz DFS 信道需要先做雷达检测（CAC），启动慢且不是所有驱动都支持，不参与自动选择。
+pub fn allowed_channels(hw_mode: &str, country: Option<&str>) -> Vec<u8> {
+    let in_list = |list: &[&str]| country.is_some_and(|c| list.contains(&c));
+    match hw_mode {
+        "b" | "g" => {
+            let last = match country {
+                None => 11,
+                Some(_) if in_list(COUNTRIES_2GHZ_11) => 11,
+                // 14 信道只在日本以 802.11b 使用
+                Some("JP") if hw_mode == "b" => 14,
+                Some(_) => 13,
+            };
+            (1..=last).collect()
+        }
+        "a" => {
+            let mut channels = CHANNELS_5GHZ_UNII1.to_vec();
+            if in_list(COUNTRIES_5GHZ_UNII3) {
+                channels.extend_from_slice(CHANNELS_5GHZ_UNII3);
+            }
+            channels
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// 把中心频率（MHz）换算成信道号
+pub fn frequency_to_channel(frequency: u32) -> Option<u8> {
+    match frequency {
+        2484 => Some(14),
+        2412..=2472 => Some(((frequency - 2407) / 5) as u8),
+        5160..=5885 => Some(((frequency - 5000) / 5) as u8),
+        _ => None,
+    }
+}
+
+/// 自动选择信道的结果
+#[derive(Debug, Clone)]
+pub struct ChannelChoice {
+    pub channel: u8,
+    /// 每个候选信道的拥挤度，越小越空闲
+    pub scores: Vec<(u8, u32)>,
+}
+
+impl ChannelChoice {
+    pub fn score(&self) -> u32 {
+        self.scores
+            .iter()
+            .find(|(channel, _)| *channel == self.channel)
+            .map_or(0, |(_, score)| *score)
+    }
+}
+
+/// 根据扫描结果为 AP 挑选最空闲的信道
+///
+/// 每个候选信道的分数是与它重叠的 BSS 按信号强度加权之和：
+/// 2.4GHz 上相差 k 个信道的 BSS 计 (5 - k) 份（k >= 5 不重叠），5GHz 只计同信道的 BSS；
+/// 每份的权重为 10 + 信号强度（0-100），弱信号的网络也会占用少量分数。
+/// 分数相同时 2.4GHz 优先 6、1、11，其余按信道号从小到大。
+/// 没有允许的信道（未知的 `hw_mode`）时返回 None。
+pub fn select_channel(hw_mode: &str, country: Option<&str>, networks: &[Network]) -> Option<ChannelChoice> {
+    let candidates = allowed_channels(hw_mode, country);
+    let is_5ghz = hw_mode == "a";
+    let seen: Vec<(u8, u32)> = networks
+        .iter()
+        .filter_map(|n| {
+            let channel = frequency_to_channel(n.frequency)?;
+            // 只统计同一频段的网络
+            ((channel >= 36) == is_5ghz).then_some((channel, 10 + n.signal as u32))
+        })
+        .collect();
+
+    let scores: Vec<(u8, u32)> = candidates
+        .iter()
+        .map(|&candidate| {
+            let score = seen
+                .iter()
+                .map(|&(channel, weight)| {
+                    let distance = candidate.abs_diff(channel) as u32;
+                    let overlap = if is_5ghz {
+                        if distance == 0 { FULL_OVERLAP } else { 0 }
+                    } else {
+                        FULL_OVERLAP.saturating_sub(distance)
+                    };
+                    overlap * weight
+                })
+                .sum();
+            (candidate, score)
+        })
+        .collect();
+
+    let preference = |channel: u8| {
+        let rank = if is_5ghz { None } else { PREFERRED_2GHZ.iter().position(|&c| c == channel) };
+        (rank.unwrap_or(PREFERRED_2GHZ.len()), channel)
+    };
+    let channel = scores
+        .iter()
+        .min_by_key(|(channel, score)| (*score, preference(*channel)))
+        .map(|(channel, _)| *channel)?;
+    Some(ChannelChoice { channel, scores })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::charset::SsidEncoding;
+
+    fn network(frequency: u32, signal: u8) -> Network {
+        Network {
+            ssid: "Neighbour".to_string(),
+            ssid_hex: hex::encode("Neighbour"),
+            display_name: "Neighbour".to_string(),
+            encoding: SsidEncoding::Utf8,
+            signal,
+            frequency,
+            security: "WPA2".to_string(),
+        }
+    }
+
+    /// 2.4GHz 信道的中心频率
+    fn channel_2ghz(channel: u32) -> u32 {
+        2407 + 5 * channel
+    }
+
+    #[test]
+    fn empty_scan_prefers_channel_6() {
+        let choice = select_channel("g", None, &[]).unwrap();
+        assert_eq!(choice.channel, 6);
+        assert_eq!(choice.score(), 0);
+        assert_eq!(choice.scores.len(), 11);
+        assert_eq!(select_channel("a", None, &[]).unwrap().channel, 36);
+        assert!(select_channel("n", None, &[]).is_none());
+    }
+
+    #[test]
+    fn avoids_crowded_channel() {
+        let networks = [network(channel_2ghz(6), 80), network(channel_2ghz(6), 60)];
+        let choice = select_channel("g", None, &networks).unwrap();
+        // 1 和 11 离 6 都有 5 个信道，不重叠；同分时 1 优先
+        assert_eq!(choice.channel, 1);
+        assert_eq!(choice.score(), 0);
+        let score = |channel: u8| choice.scores.iter().find(|(c, _)| *c == channel).unwrap().1;
+        assert_eq!(score(6), 5 * 90 + 5 * 70);
+        assert_eq!(score(4), 3 * 90 + 3 * 70);
+    }
+
+    #[test]
+    fn prefers_non_overlapping_channels() {
+        // 信道 3 的网络波及 1-7，8-11 都空闲，其中只有 11 是不重叠信道
+        let choice = select_channel("g", None, &[network(channel_2ghz(3), 50)]).unwrap();
+        assert_eq!(choice.channel, 11);
+
+        // 1、6、11 都被占用时选择最空闲的其它信道
+        let networks = [
+            network(channel_2ghz(1), 90),
+            network(channel_2ghz(6), 90),
+            network(channel_2ghz(11), 90),
+        ];
+        let choice = select_channel("g", Some("DE"), &networks).unwrap();
+        assert_eq!(choice.channel, 13);
+    }
+
+    #[test]
+    fn ignores_other_band() {
+        let networks_5ghz = [network(5180, 100), network(5200, 100)];
+        assert_eq!(select_channel("g", None, &networks_5ghz).unwrap().channel, 6);
+
+        let networks_2ghz = [network(channel_2ghz(6), 100)];
+        let choice = select_channel("a", None, &networks_2ghz).unwrap();
+        assert_eq!((choice.channel, choice.score()), (36, 0));
+
+        // 5GHz 只计同信道
+        let choice = select_channel("a", None, &networks_5ghz).unwrap();
+        assert_eq!(choice.channel, 44);
+    }
+
+    #[test]
+    fn allowed_channels_follow_country() {
+        assert_eq!(allowed_channels("g", None), (1..=11).collect::<Vec<_>>());
+        assert_eq!(allowed_channels("g", Some("US")).len(), 11);
+        assert_eq!(allowed_channels("g", Some("CN")).len(), 13);
+        assert_eq!(allowed_channels("b", Some("JP")).len(), 14);
+        assert_eq!(allowed_channels("a", Some("DE")), [36, 40, 44, 48]);
+        assert_eq!(allowed_channels("a", Some("CN")).len(), 9);
+    }
+
+    #[test]
+    fn frequencies_map_to_channels() {
+        assert_eq!(frequency_to_channel(2412), Some(1));
+        assert_eq!(frequency_to_channel(2472), Some(13));
+        assert_eq!(frequency_to_channel(2484), Some(14));
+        assert_eq!(frequency_to_channel(5745), Some(149));
+        assert_eq!(frequency_to_channel(60480), None);
+    }
+
+    #[test]
+    fn parses_channel_setting() {
+        let parse = |value: &str| toml::from_str::<toml::Table>(&format!("c = {}", value)).unwrap()["c"]
+            .clone()
+            .try_into::<ChannelSetting>();
+        assert_eq!(parse("11").unwrap(), ChannelSetting::Fixed(11));
+        assert_eq!(parse("\"auto\"").unwrap(), ChannelSetting::Auto);
+        assert!(parse("\"best\"").is_err());
+        assert_eq!(ChannelSetting::Fixed(11).fixed(), Some(11));
+        assert_eq!(ChannelSetting::Auto.fixed(), None);
+    }
+}
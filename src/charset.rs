@@ -0,0 +1,165 @@
+use encoding_rs::{BIG5, Encoding, GB18030};
+use serde::{Deserialize, Serialize};
+
+/// SSID 的字符编码
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SsidEncoding {
+    #[serde(rename = "utf-8", alias = "utf8")]
+    Utf8,
+    /// GB18030（兼容 GBK / GB2312）
+    #[serde(rename = "gb18030", alias = "gbk", alias = "gb2312")]
+    Gb18030,
+    #[serde(rename = "big5")]
+    Big5,
+    #[serde(rename = "latin-1", alias = "latin1", alias = "iso-8859-1")]
+    Latin1,
+}
+
+/// 首选旧字符集的加分，用于打破 GBK 和 Big5 之间的平局
+const PREFERRED_BONUS: f32 = 0.1;
+
+/// 将 SSID 原始字节解码为用于展示的名称，并返回检测到的编码
+///
+/// 合法的 UTF-8 直接采用；否则依次尝试 GB18030、Big5 和 Latin-1，
+/// 按解码结果"像不像正常文字"打分，`preferred` 指定的字符集会得到额外加分。
+pub fn decode_ssid(bytes: &[u8], preferred: SsidEncoding) -> (String, SsidEncoding) {
+    if let Ok(s) = std::str::from_utf8(bytes) {
+        return (s.to_string(), SsidEncoding::Utf8);
+    }
+
+    let mut best: Option<(f32, String, SsidEncoding)> = None;
+    for candidate in [SsidEncoding::Gb18030, SsidEncoding::Big5, SsidEncoding::Latin1] {
+        let Some(text) = decode_with(bytes, candidate) else {
+            continue;
+        };
+        let mut score = text_score(&text);
+        if candidate == preferred {
+            score += PREFERRED_BONUS;
+        }
+        // 分数相同时保留先出现的候选（GB18030 优先）
+        if best.as_ref().is_none_or(|(s, _, _)| score > *s) {
+            best = Some((score, text, candidate));
+        }
+    }
+
+    match best {
+        Some((_, text, encoding)) => (text, encoding),
+        // Latin-1 能解码任意字节，理论上不会走到这里
+        None => (
+            String::from_utf8_lossy(bytes).to_string(),
+            SsidEncoding::Utf8,
+        ),
+    }
+}
+
+/// 用指定编码严格解码，出现非法字节序列时返回 None
+fn decode_with(bytes: &[u8], encoding: SsidEncoding) -> Option<String> {
+    let decoder: &'static Encoding = match encoding {
+        SsidEncoding::Utf8 => return std::str::from_utf8(bytes).ok().map(str::to_string),
+        SsidEncoding::Latin1 => return Some(bytes.iter().map(|&b| b as char).collect()),
+        SsidEncoding::Gb18030 => GB18030,
+        SsidEncoding::Big5 => BIG5,
+    };
+    decoder
+        .decode_without_bom_handling_and_without_replacement(bytes)
+        .map(|s| s.into_owned())
+}
+
+/// 判断汉字是否属于常用字
+///
+/// GBK 和 Big5 的字节范围大量重叠，同一串字节往往两种编码都能解码出汉字。
+/// 错误解码得到的多是生僻字，所以用"是否落在常用字区"来区分：
+/// GB2312 一级汉字（首字节 0xB0–0xD7）或 Big5 常用字（首字节 0xA4–0xC6）。
+fn is_common_hanzi(c: char) -> bool {
+    let mut buf = [0u8; 4];
+    let s: &str = c.encode_utf8(&mut buf);
+
+    let (gb, _, gb_err) = GB18030.encode(s);
+    if !gb_err && gb.len() == 2 && (0xb0..=0xd7).contains(&gb[0]) && gb[1] >= 0xa1 {
+        return true;
+    }
+    let (big5, _, big5_err) = BIG5.encode(s);
+    !big5_err && big5.len() == 2 && (0xa4..=0xc6).contains(&big5[0])
+}
+
+/// 为解码后的文本打分（0.0–1.0），越接近常见文字分数越高
+fn text_score(text: &str) -> f32 {
+    let mut total = 0.0;
+    let mut count = 0usize;
+    for c in text.chars() {
+        count += 1;
+        total += match c as u32 {
+            // 可打印 ASCII
+            0x20..=0x7e => 1.0,
+            // CJK 统一汉字：常用字满分，其余减半
+            0x4e00..=0x9fff => {
+                if is_common_hanzi(c) { 1.0 } else { 0.5 }
+            }
+            // CJK 标点、全角字符
+            0x3000..=0x303f | 0xff00..=0xffef => 0.8,
+            // Latin-1 补充区的字母
+            0xc0..=0xff => 0.5,
+            // Latin-1 补充区的符号
+            0xa0..=0xbf => 0.3,
+            // 假名、注音等
+            0x3040..=0x31ff => 0.3,
+            // CJK 扩展 A 区（生僻字）
+            0x3400..=0x4dbf => 0.2,
+            // 控制字符、私用区、GB18030 四字节映射的罕见字符等
+            _ => 0.0,
+        };
+    }
+    if count == 0 { 0.0 } else { total / count as f32 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn utf8_is_used_as_is() {
+        assert_eq!(decode_ssid(b"Home", SsidEncoding::Gb18030), ("Home".to_string(), SsidEncoding::Utf8));
+        let bytes = "咖啡店".as_bytes();
+        assert_eq!(decode_ssid(bytes, SsidEncoding::Big5), ("咖啡店".to_string(), SsidEncoding::Utf8));
+    }
+
+    #[test]
+    fn detects_gbk() {
+        // "我的家"、"咖啡店" 的 GBK 编码
+        let home = hex::decode("ced2b5c4bcd2").unwrap();
+        assert_eq!(decode_ssid(&home, SsidEncoding::Gb18030), ("我的家".to_string(), SsidEncoding::Gb18030));
+        let cafe = hex::decode("bfa7b7c8b5ea").unwrap();
+        assert_eq!(decode_ssid(&cafe, SsidEncoding::Gb18030), ("咖啡店".to_string(), SsidEncoding::Gb18030));
+    }
+
+    #[test]
+    fn detects_big5() {
+        // "中華電信"、"台北" 的 Big5 编码
+        let telecom = hex::decode("a4a4b5d8b971ab48").unwrap();
+        assert_eq!(decode_ssid(&telecom, SsidEncoding::Big5), ("中華電信".to_string(), SsidEncoding::Big5));
+        let taipei = hex::decode("a578a55f").unwrap();
+        assert_eq!(decode_ssid(&taipei, SsidEncoding::Big5), ("台北".to_string(), SsidEncoding::Big5));
+    }
+
+    #[test]
+    fn mixed_ascii_and_gbk() {
+        let mut bytes = b"TP-LINK_".to_vec();
+        bytes.extend(hex::decode("cca8b1b1").unwrap());
+        assert_eq!(decode_ssid(&bytes, SsidEncoding::Gb18030), ("TP-LINK_台北".to_string(), SsidEncoding::Gb18030));
+    }
+
+    #[test]
+    fn binary_falls_back_to_latin1() {
+        let bytes = [0xff, 0xfe, 0x80, 0x01];
+        let (text, encoding) = decode_ssid(&bytes, SsidEncoding::Gb18030);
+        assert_eq!(encoding, SsidEncoding::Latin1);
+        assert_eq!(text, "\u{ff}\u{fe}\u{80}\u{1}");
+    }
+
+    #[test]
+    fn encoding_names() {
+        assert_eq!(serde_json::to_string(&SsidEncoding::Gb18030).unwrap(), "\"gb18030\"");
+        let gbk: SsidEncoding = serde_json::from_str("\"gbk\"").unwrap();
+        assert_eq!(gbk, SsidEncoding::Gb18030);
+    }
+}
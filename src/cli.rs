@@ -0,0 +1,285 @@
+use crate::backend::WpaCtrlBackend;
+use crate::channel::ChannelSetting;
+use crate::config::{self, ApConfig, AppConfig};
+use crate::hostapd::ApSecurity;
+use crate::qr::{self, QrFormat};
+use crate::doctor;
+use crate::shutdown;
+use crate::structs::{ConnectionRequest, Network};
+use crate::validation::validate_connection_request;
+use anyhow::{Context, Result, anyhow};
+use clap::{Parser, Subcommand};
+use std::io::Write;
+use std::path::PathBuf;
+
+/// Soft AP Wi-Fi 配网程序
+#[derive(Debug, Parser)]
+#[command(name = "provisioner", version)]
+pub struct Cli {
+    /// 主配置文件路径（覆盖 PROVISIONER_CONFIG 和默认搜索路径）
+    #[arg(long, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// 不指定子命令时的 `run` 参数（与其它子命令一起使用时报错）
+    #[command(flatten)]
+    pub run: RunArgs,
+
+    /// 不指定子命令时等同于 `run`
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// `run` 的参数
+#[derive(Debug, Clone, Default, clap::Args)]
+pub struct RunArgs {
+    /// 先用已保存的网络联网，连不上时才启动配网；已经在线时直接以 0 退出
+    #[arg(long)]
+    pub if_unconfigured: bool,
+    /// 无论是否已经联网都启动配网（用于恢复；覆盖配置中的 if_unconfigured）
+    #[arg(long, conflicts_with = "if_unconfigured")]
+    pub force: bool,
+}
+
+impl RunArgs {
+    /// 合并写在 `run` 前后的参数（`provisioner --force run` 与 `provisioner run --force` 等价）
+    pub fn merge(self, other: RunArgs) -> RunArgs {
+        RunArgs {
+            if_unconfigured: self.if_unconfigured || other.if_unconfigured,
+            force: self.force || other.force,
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// 扫描 -> 启动 AP -> 运行配网 Web 服务（默认）
+    Run(RunArgs),
+    /// 常驻监控连接状态，断线时间过长时自动重新配网
+    Monitor,
+    /// 扫描附近的 Wi-Fi 网络并输出
+    Scan {
+        /// 以 JSON 格式输出
+        #[arg(long)]
+        json: bool,
+        /// 先重启 wpa_supplicant（会断开当前连接并禁用已保存的网络）
+        #[arg(long)]
+        restart: bool,
+    },
+    /// 不通过手机，直接连接到指定网络
+    Connect {
+        /// 目标网络的 SSID
+        #[arg(long, required_unless_present = "ssid_hex")]
+        ssid: Option<String>,
+        /// 目标网络 SSID 原始字节的十六进制编码（优先于 --ssid）
+        #[arg(long)]
+        ssid_hex: Option<String>,
+        /// 网络密码，开放网络留空
+        #[arg(long, default_value = "")]
+        password: String,
+        /// 先重启 wpa_supplicant（会禁用已保存的网络）
+        #[arg(long)]
+        restart: bool,
+    },
+    /// 查询 wpa_supplicant 当前的连接状态
+    Status,
+    /// 手动启动或停止配网 AP
+    Ap {
+        #[command(subcommand)]
+        action: ApAction,
+    },
+    /// 删除 wpa_supplicant 中保存的所有网络
+    Forget,
+    /// 输出合并后的配置以及每个值的来源（密码会被隐藏）
+    PrintConfig,
+    /// 只校验配置，不做任何操作
+    CheckConfig,
+    /// 检查依赖程序、网卡能力、权限和端口占用，并给出修复建议
+    Doctor,
+    /// 输出本设备的 AP 名称和密码
+    Identity,
+    /// 输出加入配网 AP 的二维码
+    Qr {
+        /// 输出格式（默认为 unicode；写入文件时按扩展名推断）
+        #[arg(long, value_enum)]
+        format: Option<QrFormat>,
+        /// 写入文件而不是标准输出（未指定 --format 时按扩展名推断）
+        #[arg(long, value_name = "PATH")]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ApAction {
+    /// 启动 AP，直到按下 Ctrl-C 或收到 SIGTERM
+    Start,
+    /// 停止 AP（包括其他进程遗留的 hostapd/dnsmasq）
+    Stop,
+}
+
+/// `scan` 和 `connect` 默认连接到正在运行的 wpa_supplicant，不打断当前的连接；
+/// 指定 `--restart` 时才像 `run` 一样重启它
+async fn open_backend(app_config: &AppConfig, restart: bool) -> Result<WpaCtrlBackend> {
+    if restart {
+        WpaCtrlBackend::new(app_config).await
+    } else {
+        WpaCtrlBackend::attach(app_config)
+            .context("wpa_supplicant is not reachable; use --restart to start a fresh one")
+    }
+}
+
+/// `scan`：扫描并以表格或 JSON 输出
+pub async fn scan(app_config: &AppConfig, json: bool, restart: bool) -> Result<()> {
+    let backend = open_backend(app_config, restart).await?;
+    let networks = backend.scan().await?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&networks)?);
+    } else {
+        print_network_table(&networks);
+    }
+    Ok(())
+}
+
+fn print_network_table(networks: &[Network]) {
+    println!("{:<32}  {:>6}  {:<8}  {:<8}  SSID_HEX", "SSID", "SIGNAL", "SECURITY", "ENCODING");
+    for n in networks {
+        let encoding = serde_json::to_value(n.encoding)
+            .ok()
+            .and_then(|v| v.as_str().map(str::to_string))
+            .unwrap_or_default();
+        println!(
+            "{:<32}  {:>5}%  {:<8}  {:<8}  {}",
+            n.display_name, n.signal, n.security, encoding, n.ssid_hex
+        );
+    }
+}
+
+/// `connect`：先扫描以便校验请求和识别加密方式，然后连接
+///
+/// 与门户一样先禁用已保存的网络，失败后重新启用；等到连上（或失败）才返回：成功时输出提示，
+/// 失败时把原因作为错误返回，进程以非零状态码退出。
+pub async fn connect(
+    app_config: &AppConfig,
+    ssid: Option<String>,
+    ssid_hex: Option<String>,
+    password: String,
+    restart: bool,
+) -> Result<()> {
+    let req = ConnectionRequest {
+        ssid: ssid.unwrap_or_default(),
+        ssid_hex,
+        password,
+    };
+
+    let backend = open_backend(app_config, restart).await?;
+    let networks = backend.scan().await?;
+    validate_connection_request(&req, &networks)?;
+
+    backend.connect_replacing_saved(&req).await?;
+    println!("Connected to {}.", req.display_ssid());
+    Ok(())
+}
+
+/// `status`：输出 wpa_supplicant 的 STATUS
+pub async fn status(app_config: &AppConfig) -> Result<()> {
+    let backend = WpaCtrlBackend::attach(app_config)?;
+    for (key, value) in backend.status().await? {
+        println!("{}={}", key, value);
+    }
+    Ok(())
+}
+
+/// `ap start` / `ap stop`
+pub async fn ap(app_config: &AppConfig, action: ApAction) -> Result<()> {
+    match action {
+        ApAction::Start => {
+            // 在启动 AP 之前接管信号，Ctrl-C 和 systemctl stop 都会先停止 hostapd/dnsmasq
+            shutdown::listen()?;
+            let backend = WpaCtrlBackend::new(app_config).await?;
+            // 自动选择信道需要先扫描一次
+            if app_config.ap.hostapd_channel == ChannelSetting::Auto {
+                backend.scan().await?;
+            }
+            backend.start_ap().await?;
+            print_identity(&app_config.ap);
+            println!("AP is up on {}. Press Ctrl-C to stop.", app_config.ap.ap_interface());
+            let signal = shutdown::requested().await;
+            backend.stop_ap().await?;
+            std::process::exit(signal.exit_code());
+        }
+        ApAction::Stop => WpaCtrlBackend::detached(app_config).stop_orphan_ap().await,
+    }
+}
+
+/// `forget`：删除所有已保存的网络
+pub async fn forget(app_config: &AppConfig) -> Result<()> {
+    WpaCtrlBackend::attach(app_config)?.forget_networks().await
+}
+
+/// `print-config`：即使校验失败也先输出，方便排查是哪一层写错了
+pub fn print_config(config_path: Option<&std::path::Path>) -> Result<()> {
+    let (layers, _) = config::load_config_layers(config_path)?;
+    print!("{}", layers.render());
+    config::build_app_config(&layers)?;
+    Ok(())
+}
+
+/// `check-config`：校验配置并输出结果
+pub fn check_config(config_path: Option<&std::path::Path>) -> Result<()> {
+    let (layers, source) = config::load_config_layers(config_path)?;
+    match config::build_app_config(&layers) {
+        Ok(_) => {
+            println!("Configuration OK (main file: {})", source);
+            Ok(())
+        }
+        Err(errors) => Err(anyhow!(errors)),
+    }
+}
+
+/// `doctor`：输出预检报告，有失败项时以状态码 1 退出
+pub fn doctor(app_config: &AppConfig) -> Result<()> {
+    let report = doctor::run_checks(app_config);
+    println!("{}", report);
+    if report.has_failures() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// `identity`：输出 AP 名称和密码
+pub fn identity(app_config: &AppConfig) -> Result<()> {
+    print_identity(&app_config.ap);
+    Ok(())
+}
+
+/// 在控制台输出 AP 名称、密码和入网二维码，方便通过串口或屏幕告诉用户
+pub fn print_identity(ap: &ApConfig) {
+    println!("AP SSID:     {}", ap.ssid);
+    if ap.security == ApSecurity::Open {
+        println!("AP password: (open network)");
+    } else {
+        println!("AP password: {}", ap.psk);
+    }
+    match qr::ap_qr_code(ap) {
+        Ok(code) => println!("{}", qr::render_unicode(&code)),
+        Err(e) => tracing::warn!("{:#}", e),
+    }
+}
+
+/// `qr`：在终端显示入网二维码，或写入文件
+pub fn qr(app_config: &AppConfig, format: Option<QrFormat>, output: Option<PathBuf>) -> Result<()> {
+    let code = qr::ap_qr_code(&app_config.ap)?;
+    match output {
+        Some(path) => {
+            let format = format
+                .or_else(|| QrFormat::from_path(&path))
+                .unwrap_or(QrFormat::Unicode);
+            std::fs::write(&path, qr::render(&code, format)?)?;
+            println!("QR code written to {}", path.display());
+        }
+        None => {
+            let format = format.unwrap_or(QrFormat::Unicode);
+            std::io::stdout().write_all(&qr::render(&code, format)?)?;
+        }
+    }
+    Ok(())
+}
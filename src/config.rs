@@ -1,11 +1,49 @@
+use crate::channel::{self, ChannelSetting};
+use crate::charset::SsidEncoding;
+use crate::config_layers::{ConfigLayers, ValueOrigin};
+use crate::hostapd::{ApSecurity, HostapdConfig};
+use crate::identity;
+use crate::qr::QrFormat;
+use anyhow::{Context, Result};
 use serde::Deserialize;
-use std::net::SocketAddr;
+use std::fmt;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::time::Duration;
+
+/// 编译时嵌入的默认配置，仅在找不到任何配置文件时使用
+const EMBEDDED_CONFIG: &str = include_str!("../configs.toml");
+
+/// 指定配置文件路径的环境变量
+pub const CONFIG_ENV_VAR: &str = "PROVISIONER_CONFIG";
+
+/// 默认的配置文件搜索路径
+pub const DEFAULT_CONFIG_PATH: &str = "/etc/provisioner/config.toml";
+
+/// 没有主配置文件时使用的 drop-in 目录
+pub const DEFAULT_DROPIN_DIR: &str = "/etc/provisioner/conf.d";
 
 /// 顶层应用配置
 #[derive(Debug, Clone)]
 pub struct AppConfig {
     pub ap: ApConfig,
+
+    /// 配网会话的超时设置
+    pub provisioning: ProvisioningConfig,
+
+    /// `monitor` 模式的断线判定
+    pub monitor: MonitorConfig,
+
+    /// `monitor` 模式下让设备重新进入配网的外部触发
+    pub trigger: TriggerConfig,
+
+    /// 本地 JSON-RPC 控制 socket
+    pub control: ControlConfig,
+
+    /// D-Bus 服务配置（仅在 dbus feature 开启时有意义）
+    #[cfg(feature = "dbus")]
+    pub dbus: DbusConfig,
     
     /// 音频配置（仅在 audio feature 开启时有意义）
     #[cfg(feature = "audio")]
@@ -17,6 +55,27 @@ pub struct AppConfig {
 struct AppConfigFile {
     /// [ap] 表
     ap: ApConfigToml,
+
+    /// [provisioning] 表（可选）
+    #[serde(default)]
+    provisioning: ProvisioningConfigToml,
+
+    /// [monitor] 表（可选）
+    #[serde(default)]
+    monitor: MonitorConfigToml,
+
+    /// [trigger] 表（可选）
+    #[serde(default)]
+    trigger: TriggerConfigToml,
+
+    /// [control] 表（可选）
+    #[serde(default)]
+    control: ControlConfigToml,
+
+    /// [dbus] 表（可选）
+    #[cfg(feature = "dbus")]
+    #[serde(default)]
+    dbus: DbusConfigToml,
     
     /// [audio] 表（可选）
     #[cfg(feature = "audio")]
@@ -30,13 +89,32 @@ struct AppConfigFile {
 #[derive(Debug, Clone)]
 pub struct ApConfig {
     // === AP 基本配置 ===
+    /// SSID，可以包含 `{mac4}`、`{hostname}` 等占位符（启动时替换）
     pub ssid: String,
+    /// 密码，`"random"` / `"derived"` 表示启动时生成
     pub psk: String,
+    /// `ap_psk = "derived"` 时使用的设备密钥文件
+    pub psk_secret_path: String,
+    /// 生成的 SSID 和随机密码的持久化文件
+    pub identity_state_path: String,
+    /// AP 运行期间把入网二维码写到这个文件（.png/.svg/.txt），None 表示不写
+    pub qr_output_path: Option<String>,
     pub bind_addr: SocketAddr,
     pub gateway_cidr: String,
 
     // === 网络接口配置 ===
+    /// 网卡名；为 `"auto"` 时在启动时替换为自动检测到的网卡
     pub interface_name: String,
+    /// 运行 AP (hostapd/dnsmasq) 的网卡，未设置时使用 `interface_name`
+    pub ap_interface: Option<String>,
+    /// 运行 STA (wpa_supplicant/udhcpc) 的网卡，未设置时使用 `interface_name`
+    pub sta_interface: Option<String>,
+    /// 自动检测时要求的 MAC 地址前缀
+    pub interface_match_mac: Option<String>,
+    /// 自动检测时要求的驱动名
+    pub interface_match_driver: Option<String>,
+    /// sysfs 挂载点，自动检测和预检从这里读取网卡信息
+    pub sysfs_root: String,
 
     // === DHCP 配置 ===
     pub dhcp_range: String,
@@ -52,21 +130,76 @@ pub struct ApConfig {
 
     // === hostapd 无线配置 ===
     pub hostapd_hw_mode: String,
-    pub hostapd_channel: u8,
+    /// 固定信道，或 `"auto"` 根据扫描结果选择
+    pub hostapd_channel: ChannelSetting,
     pub hostapd_wpa: u8,
     pub hostapd_wpa_key_mgmt: String,
     pub hostapd_wpa_pairwise: String,
     pub hostapd_rsn_pairwise: String,
+    /// AP 加密方式
+    pub security: ApSecurity,
+    /// 国家码，None 表示不设置
+    pub hostapd_country_code: Option<String>,
+    pub hostapd_ieee80211d: bool,
+    pub hostapd_ieee80211n: bool,
+    pub hostapd_ht_capab: Option<String>,
+    pub hostapd_ieee80211ac: bool,
+    pub hostapd_vht_capab: Option<String>,
+    /// 隐藏 SSID
+    pub hostapd_hidden: bool,
+    /// 最大客户端数量，None 表示使用 hostapd 默认值
+    pub hostapd_max_num_sta: Option<u32>,
+    pub hostapd_ap_isolate: bool,
+    /// 原样追加到 hostapd 配置末尾的行
+    pub hostapd_extra_lines: Vec<String>,
+
+    // === 扫描结果显示配置 ===
+    /// SSID 不是合法 UTF-8 时优先尝试的旧字符集
+    pub ssid_legacy_charset: SsidEncoding,
+}
+
+impl ApConfig {
+    /// AP 使用的网卡
+    pub fn ap_interface(&self) -> &str {
+        self.ap_interface.as_deref().unwrap_or(&self.interface_name)
+    }
+
+    /// STA 使用的网卡
+    pub fn sta_interface(&self) -> &str {
+        self.sta_interface.as_deref().unwrap_or(&self.interface_name)
+    }
+
+    /// AP 和 STA 是否在不同的网卡上（双射频模式，不需要分时复用）
+    pub fn is_dual_radio(&self) -> bool {
+        self.ap_interface() != self.sta_interface()
+    }
 }
 
 #[derive(Deserialize)]
 struct ApConfigToml {
     ap_ssid: String,
     ap_psk: String,
+    #[serde(default = "default_psk_secret_path")]
+    ap_psk_secret_path: String,
+    #[serde(default = "default_identity_state_path")]
+    identity_state_path: String,
+    #[serde(default)]
+    qr_output_path: String,
     ap_gateway_cidr: String,
     ap_bind_addr: String,
 
     interface_name: String,
+    #[serde(default)]
+    ap_interface: String,
+    #[serde(default)]
+    sta_interface: String,
+    #[serde(default)]
+    interface_match_mac: String,
+    #[serde(default)]
+    interface_match_driver: String,
+    #[serde(default = "default_sysfs_root")]
+    sysfs_root: String,
+
     dhcp_range: String,
     hostapd_conf_path: String,
     wpa_conf_path: String,
@@ -76,42 +209,418 @@ struct ApConfigToml {
     wpa_update_config: bool,
     
     hostapd_hw_mode: String,
-    hostapd_channel: u8,
+    hostapd_channel: ChannelSetting,
     hostapd_wpa: u8,
     hostapd_wpa_key_mgmt: String,
     hostapd_wpa_pairwise: String,
     hostapd_rsn_pairwise: String,
+    #[serde(default)]
+    ap_security: ApSecurity,
+    #[serde(default)]
+    hostapd_country_code: String,
+    #[serde(default = "default_true")]
+    hostapd_ieee80211d: bool,
+    #[serde(default)]
+    hostapd_ieee80211n: bool,
+    #[serde(default)]
+    hostapd_ht_capab: String,
+    #[serde(default)]
+    hostapd_ieee80211ac: bool,
+    #[serde(default)]
+    hostapd_vht_capab: String,
+    #[serde(default)]
+    hostapd_hidden: bool,
+    #[serde(default)]
+    hostapd_max_num_sta: u32,
+    #[serde(default)]
+    hostapd_ap_isolate: bool,
+    #[serde(default)]
+    hostapd_extra_lines: Vec<String>,
+
+    #[serde(default = "default_ssid_legacy_charset")]
+    ssid_legacy_charset: SsidEncoding,
+}
+
+fn default_ssid_legacy_charset() -> SsidEncoding {
+    SsidEncoding::Gb18030
+}
+
+fn default_psk_secret_path() -> String {
+    "/etc/provisioner/device.secret".to_string()
+}
+
+fn default_identity_state_path() -> String {
+    "/var/lib/provisioner/identity.toml".to_string()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_sysfs_root() -> String {
+    "/sys".to_string()
+}
+
+/// 空字符串表示未设置
+fn non_empty(s: String) -> Option<String> {
+    let s = s.trim().to_string();
+    (!s.is_empty()).then_some(s)
 }
 
-impl From<ApConfigToml> for ApConfig {
-    fn from(t: ApConfigToml) -> Self {
-        let bind_addr =
-            SocketAddr::from_str(&t.ap_bind_addr).expect("Invalid ap_bind_addr in TOML");
-        ApConfig {
-            ssid: t.ap_ssid,
-            psk: t.ap_psk,
+impl ApConfigToml {
+    /// 转换为运行时配置；`bind_addr` 无法解析时记录错误并返回 None
+    fn into_ap_config(self, errors: &mut Vec<ConfigError>, layers: &ConfigLayers) -> Option<ApConfig> {
+        let bind_addr = match SocketAddr::from_str(&self.ap_bind_addr) {
+            Ok(addr) => addr,
+            Err(e) => {
+                errors.push(ConfigError::new(
+                    layers,
+                    "ap",
+                    "ap_bind_addr",
+                    format!("'{}' is not a valid socket address: {}", self.ap_bind_addr, e),
+                ));
+                return None;
+            }
+        };
+
+        Some(ApConfig {
+            ssid: self.ap_ssid,
+            psk: self.ap_psk,
+            psk_secret_path: self.ap_psk_secret_path,
+            identity_state_path: self.identity_state_path,
+            qr_output_path: non_empty(self.qr_output_path),
             bind_addr,
-            gateway_cidr: t.ap_gateway_cidr,
+            gateway_cidr: self.ap_gateway_cidr,
+
+            interface_name: self.interface_name,
+            ap_interface: non_empty(self.ap_interface),
+            sta_interface: non_empty(self.sta_interface),
+            interface_match_mac: non_empty(self.interface_match_mac),
+            interface_match_driver: non_empty(self.interface_match_driver),
+            sysfs_root: self.sysfs_root,
+            dhcp_range: self.dhcp_range,
+            hostapd_conf_path: self.hostapd_conf_path,
+            wpa_conf_path: self.wpa_conf_path,
+
+            wpa_ctrl_interface: self.wpa_ctrl_interface,
+            wpa_group: self.wpa_group,
+            wpa_update_config: self.wpa_update_config,
+
+            hostapd_hw_mode: self.hostapd_hw_mode,
+            hostapd_channel: self.hostapd_channel,
+            hostapd_wpa: self.hostapd_wpa,
+            hostapd_wpa_key_mgmt: self.hostapd_wpa_key_mgmt,
+            hostapd_wpa_pairwise: self.hostapd_wpa_pairwise,
+            hostapd_rsn_pairwise: self.hostapd_rsn_pairwise,
+            security: self.ap_security,
+            hostapd_country_code: non_empty(self.hostapd_country_code),
+            hostapd_ieee80211d: self.hostapd_ieee80211d,
+            hostapd_ieee80211n: self.hostapd_ieee80211n,
+            hostapd_ht_capab: non_empty(self.hostapd_ht_capab),
+            hostapd_ieee80211ac: self.hostapd_ieee80211ac,
+            hostapd_vht_capab: non_empty(self.hostapd_vht_capab),
+            hostapd_hidden: self.hostapd_hidden,
+            hostapd_max_num_sta: (self.hostapd_max_num_sta > 0).then_some(self.hostapd_max_num_sta),
+            hostapd_ap_isolate: self.hostapd_ap_isolate,
+            hostapd_extra_lines: self.hostapd_extra_lines,
+
+            ssid_legacy_charset: self.ssid_legacy_charset,
+        })
+    }
+}
+
+// ============= 配网会话配置 =============
+
+/// 配网会话的启动条件和超时设置
+#[derive(Debug, Clone, Default)]
+pub struct ProvisioningConfig {
+    /// 只有用已保存的网络连不上时才启动配网（等同于 `run --if-unconfigured`）
+    pub if_unconfigured: bool,
+    /// 启动配网前等待已保存的网络连上并拿到 IP 的时间
+    pub grace_period: Duration,
+    /// AP 启动后最多运行多久，None 表示不限制
+    pub timeout: Option<Duration>,
+    /// 没有客户端连接 AP、也没有 HTTP 请求的时间超过该值后结束
+    pub idle_timeout: Option<Duration>,
+}
+
+/// [provisioning] 表的 TOML 结构（时间单位为秒，超时为 0 表示不限制）
+#[derive(Deserialize, Debug)]
+struct ProvisioningConfigToml {
+    #[serde(default)]
+    if_unconfigured: bool,
+    #[serde(default = "default_grace_period")]
+    grace_period: u64,
+    #[serde(default)]
+    timeout: u64,
+    #[serde(default)]
+    idle_timeout: u64,
+}
+
+impl Default for ProvisioningConfigToml {
+    fn default() -> Self {
+        Self {
+            if_unconfigured: false,
+            grace_period: default_grace_period(),
+            timeout: 0,
+            idle_timeout: 0,
+        }
+    }
+}
+
+fn default_grace_period() -> u64 {
+    30
+}
+
+impl From<ProvisioningConfigToml> for ProvisioningConfig {
+    fn from(toml: ProvisioningConfigToml) -> Self {
+        let secs = |s: u64| (s > 0).then(|| Duration::from_secs(s));
+        Self {
+            if_unconfigured: toml.if_unconfigured,
+            grace_period: Duration::from_secs(toml.grace_period),
+            timeout: secs(toml.timeout),
+            idle_timeout: secs(toml.idle_timeout),
+        }
+    }
+}
+
+// ============= 断线监控配置 =============
+
+/// `monitor` 模式的断线判定
+#[derive(Debug, Clone)]
+pub struct MonitorConfig {
+    /// 持续断线（或无法关联）超过该时间后重新进入配网
+    pub fallback_after: Duration,
+    /// 断线期间重新连上后，需要稳定保持该时间才清除断线计时（滞回）
+    pub stable_after: Duration,
+}
+
+/// [monitor] 表的 TOML 结构（单位为秒）
+#[derive(Deserialize, Debug)]
+struct MonitorConfigToml {
+    #[serde(default = "default_fallback_after")]
+    fallback_after: u64,
+    #[serde(default = "default_stable_after")]
+    stable_after: u64,
+}
+
+impl Default for MonitorConfigToml {
+    fn default() -> Self {
+        Self {
+            fallback_after: default_fallback_after(),
+            stable_after: default_stable_after(),
+        }
+    }
+}
+
+fn default_fallback_after() -> u64 {
+    300
+}
+
+fn default_stable_after() -> u64 {
+    60
+}
+
+impl From<MonitorConfigToml> for MonitorConfig {
+    fn from(toml: MonitorConfigToml) -> Self {
+        Self {
+            fallback_after: Duration::from_secs(toml.fallback_after),
+            stable_after: Duration::from_secs(toml.stable_after),
+        }
+    }
+}
+
+// ============= 配网触发配置 =============
+
+/// 外部触发源（例如由其他守护进程处理的实体"配网"按键）
+#[derive(Debug, Clone, Default)]
+pub struct TriggerConfig {
+    /// 接收 `provision` 命令的 Unix socket，None 表示不启用
+    pub socket_path: Option<PathBuf>,
+    /// socket 文件的权限
+    pub socket_mode: u32,
+    /// socket 文件所属的组，None 表示不修改
+    pub socket_group: Option<String>,
+    /// 出现即触发的标志文件（触发后删除），None 表示不启用
+    pub flag_file: Option<PathBuf>,
+    /// 收到 SIGUSR1 时触发
+    pub sigusr1: bool,
+    /// evdev 输入设备（例如 gpio-keys），None 表示不启用
+    pub input_device: Option<PathBuf>,
+    /// 触发的按键码，None 表示任意按键
+    pub input_key_code: Option<u16>,
+    /// 按键需要保持按下的时间，零表示按下即触发
+    pub input_hold: Duration,
+}
+
+/// [trigger] 表的 TOML 结构（空字符串表示不启用，权限为八进制字符串）
+#[derive(Deserialize, Debug)]
+struct TriggerConfigToml {
+    #[serde(default)]
+    socket_path: String,
+    #[serde(default = "default_socket_mode")]
+    socket_mode: String,
+    #[serde(default)]
+    socket_group: String,
+    #[serde(default)]
+    flag_file: String,
+    #[serde(default)]
+    sigusr1: bool,
+    #[serde(default)]
+    input_device: String,
+    #[serde(default)]
+    input_key_code: u16,
+    #[serde(default)]
+    input_hold_ms: u64,
+}
 
-            interface_name: t.interface_name,
-            dhcp_range: t.dhcp_range,
-            hostapd_conf_path: t.hostapd_conf_path,
-            wpa_conf_path: t.wpa_conf_path,
-            
-            wpa_ctrl_interface: t.wpa_ctrl_interface,
-            wpa_group: t.wpa_group,
-            wpa_update_config: t.wpa_update_config,
-            
-            hostapd_hw_mode: t.hostapd_hw_mode,
-            hostapd_channel: t.hostapd_channel,
-            hostapd_wpa: t.hostapd_wpa,
-            hostapd_wpa_key_mgmt: t.hostapd_wpa_key_mgmt,
-            hostapd_wpa_pairwise: t.hostapd_wpa_pairwise,
-            hostapd_rsn_pairwise: t.hostapd_rsn_pairwise,
+impl Default for TriggerConfigToml {
+    fn default() -> Self {
+        Self {
+            socket_path: String::new(),
+            socket_mode: default_socket_mode(),
+            socket_group: String::new(),
+            flag_file: String::new(),
+            sigusr1: false,
+            input_device: String::new(),
+            input_key_code: 0,
+            input_hold_ms: 0,
         }
     }
 }
 
+impl From<TriggerConfigToml> for TriggerConfig {
+    fn from(toml: TriggerConfigToml) -> Self {
+        Self {
+            socket_path: non_empty(toml.socket_path).map(PathBuf::from),
+            // 取值已在 build_app_config 中校验
+            socket_mode: parse_socket_mode(&toml.socket_mode).unwrap_or(0o660),
+            socket_group: non_empty(toml.socket_group),
+            flag_file: non_empty(toml.flag_file).map(PathBuf::from),
+            sigusr1: toml.sigusr1,
+            input_device: non_empty(toml.input_device).map(PathBuf::from),
+            input_key_code: (toml.input_key_code > 0).then_some(toml.input_key_code),
+            input_hold: Duration::from_millis(toml.input_hold_ms),
+        }
+    }
+}
+
+// ============= 控制 socket 配置 =============
+
+/// 本地应用使用的 JSON-RPC 控制 socket
+#[derive(Debug, Clone, Default)]
+pub struct ControlConfig {
+    /// socket 路径，None 表示不启用
+    pub socket_path: Option<PathBuf>,
+    /// socket 文件的权限
+    pub socket_mode: u32,
+    /// socket 文件所属的组，None 表示不修改
+    pub socket_group: Option<String>,
+}
+
+/// [control] 表的 TOML 结构（权限为八进制字符串）
+#[derive(Deserialize, Debug)]
+struct ControlConfigToml {
+    #[serde(default)]
+    socket_path: String,
+    #[serde(default = "default_socket_mode")]
+    socket_mode: String,
+    #[serde(default)]
+    socket_group: String,
+}
+
+impl Default for ControlConfigToml {
+    fn default() -> Self {
+        Self {
+            socket_path: String::new(),
+            socket_mode: default_socket_mode(),
+            socket_group: String::new(),
+        }
+    }
+}
+
+fn default_socket_mode() -> String {
+    "0660".to_string()
+}
+
+/// 解析八进制的文件权限，例如 `"0660"`
+fn parse_socket_mode(mode: &str) -> Result<u32, String> {
+    u32::from_str_radix(mode.trim(), 8)
+        .ok()
+        .filter(|m| *m <= 0o777)
+        .ok_or_else(|| format!("'{}' is not an octal file mode (e.g. \"0660\")", mode))
+}
+
+impl From<ControlConfigToml> for ControlConfig {
+    fn from(toml: ControlConfigToml) -> Self {
+        Self {
+            socket_path: non_empty(toml.socket_path).map(PathBuf::from),
+            // 取值已在 build_app_config 中校验
+            socket_mode: parse_socket_mode(&toml.socket_mode).unwrap_or(0o660),
+            socket_group: non_empty(toml.socket_group),
+        }
+    }
+}
+
+// ============= D-Bus 配置 (仅当 dbus feature 开启时编译) =============
+
+/// 注册 D-Bus 服务的总线
+#[cfg(feature = "dbus")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DbusBus {
+    System,
+    Session,
+    /// 指定的总线地址，例如 `unix:path=/tmp/test-bus`（测试时连接私有的 dbus-daemon）
+    Address(String),
+}
+
+/// D-Bus 服务配置
+#[cfg(feature = "dbus")]
+#[derive(Debug, Clone, Default)]
+pub struct DbusConfig {
+    /// None 表示不启用
+    pub bus: Option<DbusBus>,
+    /// 注册的服务名
+    pub name: String,
+}
+
+/// [dbus] 表的 TOML 结构
+#[cfg(feature = "dbus")]
+#[derive(Deserialize, Debug)]
+struct DbusConfigToml {
+    #[serde(default)]
+    bus: String,
+    #[serde(default = "default_dbus_name")]
+    name: String,
+}
+
+#[cfg(feature = "dbus")]
+impl Default for DbusConfigToml {
+    fn default() -> Self {
+        Self {
+            bus: String::new(),
+            name: default_dbus_name(),
+        }
+    }
+}
+
+#[cfg(feature = "dbus")]
+fn default_dbus_name() -> String {
+    "org.example.Provisioner1".to_string()
+}
+
+#[cfg(feature = "dbus")]
+impl From<DbusConfigToml> for DbusConfig {
+    fn from(toml: DbusConfigToml) -> Self {
+        let bus = non_empty(toml.bus).map(|bus| match bus.as_str() {
+            "system" => DbusBus::System,
+            "session" => DbusBus::Session,
+            _ => DbusBus::Address(bus),
+        });
+        Self { bus, name: toml.name }
+    }
+}
+
 // ============= 音频配置 (仅当 audio feature 开启时编译) =============
 
 /// 音频播放的文件映射
@@ -122,6 +631,9 @@ pub struct AudioFilesConfig {
     pub connection_started: String,
     pub connection_success: String,
     pub connection_failed: String,
+    /// 配网超时（可选）
+    #[serde(default)]
+    pub provisioning_timeout: Option<String>,
 }
 
 /// 音频配置
@@ -134,20 +646,635 @@ pub struct AudioConfig {
 
 // ============= 配置加载函数 =============
 
-/// 从 TOML 字符串加载应用配置
-pub fn load_config_from_toml_str(s: &str) -> AppConfig {
-    let parsed: AppConfigFile = toml::from_str(s).expect("Failed to parse config TOML");
+/// 从合并后的配置层构建并校验应用配置
+///
+/// 解析或校验失败时返回所有发现的问题，而不是在第一个错误处 panic。
+pub fn build_app_config(layers: &ConfigLayers) -> Result<AppConfig, ConfigErrors> {
+    // 记录反序列化时被忽略的键（拼错的键名），并在类型错误时得到出错的键路径
+    let mut ignored = Vec::new();
+    let mut record_ignored = |path: serde_ignored::Path| ignored.push(ignored_key(&path));
+    let deserializer = serde_ignored::Deserializer::new(toml::Value::Table(layers.merged().clone()), &mut record_ignored);
+    let parsed: AppConfigFile = serde_path_to_error::deserialize(deserializer)
+        .map_err(|e| ConfigErrors(vec![deserialize_error(layers, e)]))?;
+
+    let mut errors = Vec::new();
+    for key in ignored {
+        if is_disabled_feature_table(&key) {
+            continue;
+        }
+        match layers.origin(&key) {
+            // 环境变量是明确的覆盖，拼错时不能悄悄不生效
+            Some(origin) if origin.is_env() => errors.push(ConfigError {
+                key,
+                origin: Some(origin.clone()),
+                message: "unknown configuration key".to_string(),
+            }),
+            Some(origin) => tracing::warn!("Ignoring unknown configuration key {} ({})", key, origin),
+            None => tracing::warn!("Ignoring unknown configuration key {}", key),
+        }
+    }
+    let ap = parsed.ap.into_ap_config(&mut errors, layers);
+    if let Some(ap) = &ap {
+        validate_ap_config(ap, layers, &mut errors);
+    }
+
+    if let Err(message) = parse_socket_mode(&parsed.trigger.socket_mode) {
+        errors.push(ConfigError::new(layers, "trigger", "socket_mode", message));
+    }
+    if let Err(message) = parse_socket_mode(&parsed.control.socket_mode) {
+        errors.push(ConfigError::new(layers, "control", "socket_mode", message));
+    }
+
+    if parsed.monitor.fallback_after == 0 {
+        errors.push(ConfigError::new(layers, "monitor", "fallback_after", "must be greater than 0"));
+    }
+
+    #[cfg(feature = "audio")]
+    if let Some(audio) = &parsed.audio {
+        validate_audio_config(audio, layers, &mut errors);
+    }
+
+    match ap {
+        Some(ap) if errors.is_empty() => Ok(AppConfig {
+            ap,
+            provisioning: parsed.provisioning.into(),
+            monitor: parsed.monitor.into(),
+            trigger: parsed.trigger.into(),
+            control: parsed.control.into(),
+
+            #[cfg(feature = "dbus")]
+            dbus: parsed.dbus.into(),
+
+            #[cfg(feature = "audio")]
+            audio: parsed.audio,
+        }),
+        _ => Err(ConfigErrors(errors)),
+    }
+}
+
+/// 把反序列化错误定位到具体的键，并查出该键来自哪个文件的哪一行（或哪个环境变量）
+fn deserialize_error(layers: &ConfigLayers, e: serde_path_to_error::Error<toml::de::Error>) -> ConfigError {
+    let path = e.path().to_string();
+    let message = e.inner().message().trim().to_string();
+    // 缺少字段时路径指向所在的表，补上字段名
+    let key = match message.strip_prefix("missing field `").and_then(|m| m.split_once('`')) {
+        Some((field, _)) if path != "." => format!("{}.{}", path, field),
+        Some((field, _)) => field.to_string(),
+        None => path,
+    };
+    ConfigError {
+        origin: layers.origin(&key).cloned(),
+        key,
+        message,
+    }
+}
+
+/// `serde_ignored` 的路径转换为 `ap.ap_ssid` 形式的键路径
+fn ignored_key(path: &serde_ignored::Path) -> String {
+    fn collect(path: &serde_ignored::Path, segments: &mut Vec<String>) {
+        match path {
+            serde_ignored::Path::Root => {}
+            serde_ignored::Path::Seq { parent, index } => {
+                collect(parent, segments);
+                segments.push(index.to_string());
+            }
+            serde_ignored::Path::Map { parent, key } => {
+                collect(parent, segments);
+                segments.push(key.clone());
+            }
+            serde_ignored::Path::Some { parent }
+            | serde_ignored::Path::NewtypeStruct { parent }
+            | serde_ignored::Path::NewtypeVariant { parent } => collect(parent, segments),
+        }
+    }
+    let mut segments = Vec::new();
+    collect(path, &mut segments);
+    segments.join(".")
+}
+
+/// 内嵌的默认配置包含可选 feature 的表，没有启用对应 feature 时忽略它们是正常的
+fn is_disabled_feature_table(key: &str) -> bool {
+    let table = key.split('.').next().unwrap_or(key);
+    (cfg!(not(feature = "dbus")) && table == "dbus") || (cfg!(not(feature = "audio")) && table == "audio")
+}
+
+// ============= 配置校验 =============
+
+/// 单个配置错误，包含出错的 TOML 键和它的来源（文件与行号）
+#[derive(Debug, Clone)]
+pub struct ConfigError {
+    /// 完整的键路径，例如 `ap.dhcp_range`
+    pub key: String,
+    /// 该键最终生效的值来自哪里
+    pub origin: Option<ValueOrigin>,
+    pub message: String,
+}
+
+impl ConfigError {
+    fn new(layers: &ConfigLayers, table: &str, key: &str, message: impl Into<String>) -> Self {
+        let key = format!("{}.{}", table, key);
+        Self {
+            origin: layers.origin(&key).cloned(),
+            key,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.origin {
+            Some(origin) => write!(f, "{} ({}): {}", self.key, origin, self.message),
+            None => write!(f, "{}: {}", self.key, self.message),
+        }
+    }
+}
+
+/// 配置校验发现的全部错误
+#[derive(Debug, Clone)]
+pub struct ConfigErrors(pub Vec<ConfigError>);
+
+impl fmt::Display for ConfigErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} configuration error(s):", self.0.len())?;
+        for e in &self.0 {
+            write!(f, "\n  - {}", e)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigErrors {}
+
+/// IPv4 CIDR，例如 `192.168.4.1/24`
+#[derive(Debug, Clone, Copy)]
+pub struct Ipv4Cidr {
+    pub addr: Ipv4Addr,
+    pub prefix: u8,
+}
+
+impl Ipv4Cidr {
+    pub fn parse(s: &str) -> Option<Self> {
+        let (addr, prefix) = s.split_once('/')?;
+        let addr = Ipv4Addr::from_str(addr).ok()?;
+        let prefix = prefix.parse::<u8>().ok().filter(|p| *p <= 32)?;
+        Some(Self { addr, prefix })
+    }
+
+    /// 判断地址是否与本网段相同
+    pub fn contains(&self, ip: Ipv4Addr) -> bool {
+        let mask = if self.prefix == 0 { 0 } else { u32::MAX << (32 - self.prefix) };
+        (u32::from(self.addr) & mask) == (u32::from(ip) & mask)
+    }
+}
+
+/// 本程序的 AP 支持的密钥管理方式（不含需要 RADIUS 的 WPA-EAP）
+const SUPPORTED_KEY_MGMT: &[&str] = &["WPA-PSK", "WPA-PSK-SHA256", "SAE", "FT-PSK", "FT-SAE"];
+
+/// 检查 [ap] 中各配置项的取值以及它们之间的一致性
+fn validate_ap_config(ap: &ApConfig, layers: &ConfigLayers, errors: &mut Vec<ConfigError>) {
+    let mut err = |key: &str, message: String| errors.push(ConfigError::new(layers, "ap", key, message));
+
+    // 网关 CIDR 与 DHCP 地址池
+    match Ipv4Cidr::parse(&ap.gateway_cidr) {
+        None => err(
+            "ap_gateway_cidr",
+            format!("'{}' is not a valid IPv4 CIDR (e.g. 192.168.4.1/24)", ap.gateway_cidr),
+        ),
+        Some(gateway) => {
+            if let Some(message) = check_dhcp_range(&ap.dhcp_range, &gateway) {
+                err("dhcp_range", message);
+            }
+            if ap.bind_addr.ip() != gateway.addr {
+                err(
+                    "ap_bind_addr",
+                    format!(
+                        "bind address {} does not match the gateway IP {}",
+                        ap.bind_addr.ip(),
+                        gateway.addr
+                    ),
+                );
+            }
+        }
+    }
+
+    // SSID 模板
+    if let Err(message) = identity::check_ssid_template(&ap.ssid) {
+        err("ap_ssid", message);
+    }
+
+    // AP 密码（开放网络不需要；random/derived 在启动时生成）
+    let psk_generated = identity::is_generated_psk(&ap.psk);
+    let psk_len = ap.psk.chars().count();
+    let psk_len_ok = psk_generated || (8..=63).contains(&psk_len);
+    if ap.security.needs_passphrase() && !psk_len_ok {
+        err("ap_psk", format!("must be 8-63 characters, 'random' or 'derived', got {}", psk_len));
+    }
+
+    // 频段与信道
+    let channel = ap.hostapd_channel.fixed();
+    match ap.hostapd_hw_mode.as_str() {
+        "b" | "g" => {
+            if let Some(channel) = channel
+                && !(1..=14).contains(&channel)
+            {
+                err(
+                    "hostapd_channel",
+                    format!("channel {} is not valid for hw_mode '{}' (1-14)", channel, ap.hostapd_hw_mode),
+                );
+            }
+        }
+        "a" => {
+            if let Some(channel) = channel
+                && !channel::CHANNELS_5GHZ.contains(&channel)
+            {
+                err(
+                    "hostapd_channel",
+                    format!("channel {} is not a valid 5GHz channel for hw_mode 'a'", channel),
+                );
+            }
+        }
+        other => err(
+            "hostapd_hw_mode",
+            format!("unknown hw_mode '{}', expected one of a, b, g", other),
+        ),
+    }
+
+    // 二维码输出文件
+    if let Some(path) = &ap.qr_output_path
+        && QrFormat::from_path(Path::new(path)).is_none()
+    {
+        err("qr_output_path", format!("'{}' must end in .png, .svg or .txt", path));
+    }
+
+    // 其余 hostapd 取值（国家码、802.11n/ac、换行注入等）交给 HostapdConfig 检查，
+    // 上面已经报告过的项不再重复
+    if let Err(e) = HostapdConfig::from_ap_config(ap).render() {
+        let key = match e.directive {
+            "ssid" | "ssid2" => "ap_ssid".to_string(),
+            "wpa_passphrase" | "sae_password" => "ap_psk".to_string(),
+            "interface" => "interface_name".to_string(),
+            "wpa" => "hostapd_wpa".to_string(),
+            other => format!("hostapd_{}", other),
+        };
+        // 模板和待生成的密码在启动时才有最终值，这里跳过
+        let reported = key == "hostapd_hw_mode"
+            || (key == "ap_psk" && (psk_generated || !psk_len_ok))
+            || (key == "ap_ssid" && ap.ssid.contains('{'));
+        if !reported {
+            err(&key, e.message);
+        }
+    }
+
+    // 只有 psk 模式才使用下面这些字段
+    if ap.security != ApSecurity::Psk {
+        return;
+    }
+
+    // WPA 版本与密钥管理
+    let key_mgmt: Vec<&str> = ap.hostapd_wpa_key_mgmt.split_whitespace().collect();
+    if key_mgmt.is_empty() {
+        err("hostapd_wpa_key_mgmt", "must not be empty".to_string());
+    }
+    for km in &key_mgmt {
+        if !SUPPORTED_KEY_MGMT.contains(km) {
+            err(
+                "hostapd_wpa_key_mgmt",
+                format!("'{}' is not supported, expected one of {}", km, SUPPORTED_KEY_MGMT.join(", ")),
+            );
+        }
+    }
+    if !(1..=3).contains(&ap.hostapd_wpa) {
+        err(
+            "hostapd_wpa",
+            format!("must be 1 (WPA), 2 (WPA2/RSN) or 3 (both), got {}", ap.hostapd_wpa),
+        );
+    } else if ap.hostapd_wpa & 2 == 0 && key_mgmt.iter().any(|km| km.contains("SAE") || km.ends_with("SHA256") || km.starts_with("FT-")) {
+        // SAE (WPA3)、SHA256 和 FT 都只能在 RSN (wpa=2) 下使用
+        err(
+            "hostapd_wpa",
+            format!(
+                "wpa={} (WPA1 only) cannot be used with key management '{}', use wpa=2",
+                ap.hostapd_wpa, ap.hostapd_wpa_key_mgmt
+            ),
+        );
+    }
+}
+
+/// 检查 DHCP 地址池是否位于网关所在网段，且不包含网关地址
+fn check_dhcp_range(range: &str, gateway: &Ipv4Cidr) -> Option<String> {
+    let mut parts = range.split(',').map(str::trim);
+    let (Some(start), Some(end)) = (parts.next(), parts.next()) else {
+        return Some(format!("'{}' must look like '<start>,<end>[,<lease>]'", range));
+    };
+    let (Ok(start), Ok(end)) = (Ipv4Addr::from_str(start), Ipv4Addr::from_str(end)) else {
+        return Some(format!("'{}' does not contain valid IPv4 start/end addresses", range));
+    };
 
-    AppConfig {
-        ap: ApConfig::from(parsed.ap),
-        
-        #[cfg(feature = "audio")]
-        audio: parsed.audio,
+    if !gateway.contains(start) || !gateway.contains(end) {
+        return Some(format!(
+            "range {}-{} is not inside the gateway subnet {}/{}",
+            start, end, gateway.addr, gateway.prefix
+        ));
     }
+    if u32::from(start) > u32::from(end) {
+        return Some(format!("range start {} is after range end {}", start, end));
+    }
+    let gw = u32::from(gateway.addr);
+    if (u32::from(start)..=u32::from(end)).contains(&gw) {
+        return Some(format!("range {}-{} includes the gateway {}", start, end, gateway.addr));
+    }
+    None
+}
+
+/// 检查音频文件是否都存在于嵌入的资源中
+#[cfg(feature = "audio")]
+fn validate_audio_config(audio: &AudioConfig, layers: &ConfigLayers, errors: &mut Vec<ConfigError>) {
+    let files = [
+        ("ap_started", &audio.files.ap_started),
+        ("connection_started", &audio.files.connection_started),
+        ("connection_success", &audio.files.connection_success),
+        ("connection_failed", &audio.files.connection_failed),
+    ];
+    let optional = [("provisioning_timeout", &audio.files.provisioning_timeout)];
+    let optional = optional.into_iter().filter_map(|(key, file)| Some((key, file.as_ref()?)));
+    for (key, file) in files.into_iter().chain(optional) {
+        if !crate::audio::has_audio_asset(file) {
+            errors.push(ConfigError::new(
+                layers,
+                "audio.files",
+                key,
+                format!("'{}' is not an embedded audio file", file),
+            ));
+        }
+    }
+}
+
+/// 配置的来源
+#[derive(Debug, Clone)]
+pub enum ConfigSource {
+    /// 命令行 `--config <path>`
+    CommandLine(PathBuf),
+    /// 环境变量 `PROVISIONER_CONFIG`
+    Environment(PathBuf),
+    /// 默认搜索路径
+    DefaultPath(PathBuf),
+    /// 编译时嵌入的配置
+    Embedded,
+}
+
+impl fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigSource::CommandLine(p) => write!(f, "{} (--config)", p.display()),
+            ConfigSource::Environment(p) => write!(f, "{} (${})", p.display(), CONFIG_ENV_VAR),
+            ConfigSource::DefaultPath(p) => write!(f, "{}", p.display()),
+            ConfigSource::Embedded => write!(f, "<embedded configs.toml>"),
+        }
+    }
+}
+
+/// 在运行时加载并校验应用配置（见 [`load_config_layers`]）
+pub fn load_app_config(cli_path: Option<&Path>) -> Result<(AppConfig, ConfigLayers)> {
+    let (layers, source) = load_config_layers(cli_path)?;
+    let config = build_app_config(&layers)
+        .with_context(|| format!("Invalid configuration (main file: {})", source))?;
+    Ok((config, layers))
+}
+
+/// 在运行时加载并合并所有配置层（不做校验）
+///
+/// 主配置文件的查找顺序：`--config <path>` > `PROVISIONER_CONFIG` > `/etc/provisioner/config.toml`。
+/// 显式指定的路径（前两种）读取失败时直接报错，而不是悄悄回退到默认配置。
+///
+/// 各层按以下顺序合并，后者覆盖前者：
+/// 1. 编译时嵌入的默认配置
+/// 2. 主配置文件
+/// 3. 主配置文件所在目录下 `conf.d/*.toml`（按文件名字典序）
+/// 4. `PROVISIONER__AP__SSID` 形式的环境变量
+pub fn load_config_layers(cli_path: Option<&Path>) -> Result<(ConfigLayers, ConfigSource)> {
+    let source = if let Some(path) = cli_path {
+        ConfigSource::CommandLine(path.to_path_buf())
+    } else if let Some(path) = std::env::var_os(CONFIG_ENV_VAR).filter(|p| !p.is_empty()) {
+        ConfigSource::Environment(PathBuf::from(path))
+    } else if Path::new(DEFAULT_CONFIG_PATH).exists() {
+        ConfigSource::DefaultPath(PathBuf::from(DEFAULT_CONFIG_PATH))
+    } else {
+        ConfigSource::Embedded
+    };
+
+    let mut layers = ConfigLayers::new();
+    layers.add_toml("<embedded>", EMBEDDED_CONFIG)?;
+
+    let dropin_dir = match &source {
+        ConfigSource::CommandLine(path)
+        | ConfigSource::Environment(path)
+        | ConfigSource::DefaultPath(path) => {
+            let content = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read config file {}", path.display()))?;
+            layers.add_toml(&path.display().to_string(), &content)?;
+            path.parent().unwrap_or(Path::new(".")).join("conf.d")
+        }
+        ConfigSource::Embedded => PathBuf::from(DEFAULT_DROPIN_DIR),
+    };
+
+    for path in list_dropins(&dropin_dir)? {
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read drop-in {}", path.display()))?;
+        layers.add_toml(&path.display().to_string(), &content)?;
+    }
+
+    layers.add_env(std::env::vars())?;
+    Ok((layers, source))
+}
+
+/// 只由内置配置和 `overlay` 组成的配置，供其它模块的测试使用
+#[cfg(test)]
+pub(crate) fn test_app_config(overlay: &str) -> AppConfig {
+    let mut layers = ConfigLayers::new();
+    layers.add_toml("<embedded>", EMBEDDED_CONFIG).unwrap();
+    layers.add_toml("test.toml", overlay).unwrap();
+    build_app_config(&layers).unwrap()
+}
+
+/// 列出 drop-in 目录中的 `*.toml` 文件，按文件名字典序排列；目录不存在时返回空列表
+fn list_dropins(dir: &Path) -> Result<Vec<PathBuf>> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", dir.display())),
+    };
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|p| p.is_file() && p.extension().is_some_and(|ext| ext == "toml"))
+        .collect();
+    files.sort();
+    Ok(files)
 }
 
-/// 为向后兼容保留的函数（已弃用）
-pub fn ap_config_from_toml_str(s: &str) -> ApConfig {
-    let app_config = load_config_from_toml_str(s);
-    app_config.ap
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layers(overlay: &str, env: &[(&str, &str)]) -> ConfigLayers {
+        let mut layers = ConfigLayers::new();
+        layers.add_toml("<embedded>", EMBEDDED_CONFIG).unwrap();
+        layers.add_toml("site.toml", overlay).unwrap();
+        layers
+            .add_env(env.iter().map(|(k, v)| (k.to_string(), v.to_string())))
+            .unwrap();
+        layers
+    }
+
+    fn errors(layers: &ConfigLayers) -> Vec<String> {
+        build_app_config(layers).unwrap_err().0.iter().map(|e| e.to_string()).collect()
+    }
+
+    #[test]
+    fn embedded_config_is_valid() {
+        build_app_config(&layers("", &[])).unwrap();
+    }
+
+    #[test]
+    fn type_errors_name_key_and_line() {
+        let layers = layers("[monitor]\n\nfallback_after = \"soon\"\n", &[]);
+        let errors = errors(&layers);
+        assert_eq!(errors.len(), 1);
+        assert!(
+            errors[0].starts_with("monitor.fallback_after (site.toml:3): invalid type"),
+            "{}",
+            errors[0]
+        );
+    }
+
+    #[test]
+    fn type_errors_from_env_name_variable() {
+        let layers = layers("", &[("PROVISIONER__MONITOR__FALLBACK_AFTER", "-5")]);
+        let errors = errors(&layers);
+        assert!(
+            errors[0].starts_with("monitor.fallback_after ($PROVISIONER__MONITOR__FALLBACK_AFTER): invalid value"),
+            "{}",
+            errors[0]
+        );
+    }
+
+    #[test]
+    fn missing_fields_name_key() {
+        let mut layers = ConfigLayers::new();
+        layers.add_toml("site.toml", "[ap]\nap_psk = \"password\"\n").unwrap();
+        let errors = build_app_config(&layers).unwrap_err();
+        assert_eq!(errors.0[0].key, "ap.ap_ssid");
+        assert!(errors.0[0].message.contains("missing field"));
+    }
+
+    #[test]
+    fn unknown_env_keys_are_rejected() {
+        let layers = layers("", &[("PROVISIONER__AP__SSDI", "Typo")]);
+        assert_eq!(errors(&layers), ["ap.ssdi ($PROVISIONER__AP__SSDI): unknown configuration key"]);
+    }
+
+    #[test]
+    fn unknown_file_keys_are_ignored() {
+        build_app_config(&layers("[ap]\nunused_key = 1\n", &[])).unwrap();
+    }
+
+    #[test]
+    fn rejects_invalid_gateway_cidr() {
+        let layers = layers("[ap]\nap_gateway_cidr = \"192.168.4.1/33\"\n", &[]);
+        assert_eq!(
+            errors(&layers),
+            ["ap.ap_gateway_cidr (site.toml:2): '192.168.4.1/33' is not a valid IPv4 CIDR (e.g. 192.168.4.1/24)"]
+        );
+    }
+
+    #[test]
+    fn rejects_dhcp_range_including_gateway() {
+        let layers = layers("[ap]\ndhcp_range = \"192.168.4.1,192.168.4.50,12h\"\n", &[]);
+        assert_eq!(
+            errors(&layers),
+            ["ap.dhcp_range (site.toml:2): range 192.168.4.1-192.168.4.50 includes the gateway 192.168.4.1"]
+        );
+    }
+
+    #[test]
+    fn rejects_dhcp_range_outside_subnet() {
+        let layers = layers("[ap]\ndhcp_range = \"192.168.4.100,192.168.5.10,12h\"\n", &[]);
+        assert_eq!(
+            errors(&layers),
+            ["ap.dhcp_range (site.toml:2): range 192.168.4.100-192.168.5.10 is not inside the gateway subnet 192.168.4.1/24"]
+        );
+    }
+
+    #[test]
+    fn rejects_bind_addr_off_gateway() {
+        let layers = layers("[ap]\nap_bind_addr = \"192.168.4.2:80\"\n", &[]);
+        assert_eq!(
+            errors(&layers),
+            ["ap.ap_bind_addr (site.toml:2): bind address 192.168.4.2 does not match the gateway IP 192.168.4.1"]
+        );
+    }
+
+    #[test]
+    fn rejects_psk_outside_8_to_63_characters() {
+        let short = layers("[ap]\nap_psk = \"1234567\"\n", &[]);
+        assert_eq!(
+            errors(&short),
+            ["ap.ap_psk (site.toml:2): must be 8-63 characters, 'random' or 'derived', got 7"]
+        );
+        let long = layers(&format!("[ap]\nap_psk = \"{}\"\n", "a".repeat(64)), &[]);
+        assert_eq!(
+            errors(&long),
+            ["ap.ap_psk (site.toml:2): must be 8-63 characters, 'random' or 'derived', got 64"]
+        );
+        build_app_config(&layers(&format!("[ap]\nap_psk = \"{}\"\n", "a".repeat(63)), &[])).unwrap();
+    }
+
+    #[test]
+    fn rejects_channel_outside_hw_mode() {
+        let band_g = layers("[ap]\nhostapd_channel = 36\n", &[]);
+        assert_eq!(
+            errors(&band_g),
+            ["ap.hostapd_channel (site.toml:2): channel 36 is not valid for hw_mode 'g' (1-14)"]
+        );
+        let band_a = layers("[ap]\nhostapd_hw_mode = \"a\"\nhostapd_channel = 6\n", &[]);
+        assert_eq!(
+            errors(&band_a),
+            ["ap.hostapd_channel (site.toml:3): channel 6 is not a valid 5GHz channel for hw_mode 'a'"]
+        );
+    }
+
+    #[test]
+    fn rejects_wpa1_with_sae() {
+        let layers = layers("[ap]\nhostapd_wpa = 1\nhostapd_wpa_key_mgmt = \"SAE\"\n", &[]);
+        assert_eq!(
+            errors(&layers),
+            ["ap.hostapd_wpa (site.toml:2): wpa=1 (WPA1 only) cannot be used with key management 'SAE', use wpa=2"]
+        );
+    }
+
+    #[cfg(feature = "audio")]
+    #[test]
+    fn rejects_missing_audio_assets() {
+        let layers = layers("[audio.files]\nap_started = \"missing.wav\"\n", &[]);
+        assert_eq!(
+            errors(&layers),
+            ["audio.files.ap_started (site.toml:2): 'missing.wav' is not an embedded audio file"]
+        );
+    }
+
+    #[test]
+    fn dropins_are_sorted_by_name() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["20-site.toml", "10-base.toml", "99-local.toml", "README", "30-disabled.toml.bak"] {
+            std::fs::write(dir.path().join(name), "").unwrap();
+        }
+        std::fs::create_dir(dir.path().join("50-dir.toml")).unwrap();
+        let names: Vec<String> = list_dropins(dir.path())
+            .unwrap()
+            .iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(names, ["10-base.toml", "20-site.toml", "99-local.toml"]);
+        assert!(list_dropins(&dir.path().join("missing")).unwrap().is_empty());
+    }
 }
@@ -0,0 +1,411 @@
+use crate::config::{ConfigError, ConfigErrors};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::Write as _;
+use toml::{Table, Value};
+
+/// 环境变量覆盖的前缀，例如 `PROVISIONER__AP__SSID`
+pub const ENV_PREFIX: &str = "PROVISIONER__";
+
+/// 配置项的来源位置
+#[derive(Debug, Clone)]
+pub struct ValueOrigin {
+    /// 来源层的名称（文件路径、`<embedded>` 或环境变量名）
+    pub source: String,
+    /// 在来源文件中的行号（从 1 开始），环境变量没有行号
+    pub line: Option<usize>,
+}
+
+impl ValueOrigin {
+    /// 是否来自环境变量
+    pub fn is_env(&self) -> bool {
+        self.source.starts_with('$')
+    }
+}
+
+impl fmt::Display for ValueOrigin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}", self.source, line),
+            None => write!(f, "{}", self.source),
+        }
+    }
+}
+
+/// 分层合并的配置
+///
+/// 后加入的层覆盖先加入的层，同时记录每个键最终来自哪一层。
+#[derive(Debug, Clone, Default)]
+pub struct ConfigLayers {
+    merged: Table,
+    /// 键路径（如 `ap.ap_ssid`） -> 来源
+    origins: BTreeMap<String, ValueOrigin>,
+    /// 按加入顺序排列的层名称
+    sources: Vec<String>,
+}
+
+impl ConfigLayers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 合并后的 TOML 表
+    pub fn merged(&self) -> &Table {
+        &self.merged
+    }
+
+    /// 已加入的层名称
+    pub fn sources(&self) -> &[String] {
+        &self.sources
+    }
+
+    /// 查询某个键（如 `ap.dhcp_range`）的来源
+    pub fn origin(&self, key: &str) -> Option<&ValueOrigin> {
+        self.origins.get(key)
+    }
+
+    /// 合并一个 TOML 文本层
+    pub fn add_toml(&mut self, name: &str, text: &str) -> Result<(), ConfigErrors> {
+        let table: Table = toml::from_str(text).map_err(|e| {
+            let line = e.span().map(|span| line_of_offset(text, span.start));
+            ConfigErrors(vec![ConfigError {
+                key: "<toml>".to_string(),
+                origin: Some(ValueOrigin {
+                    source: name.to_string(),
+                    line,
+                }),
+                message: e.message().trim().to_string(),
+            }])
+        })?;
+
+        let mut origins = Vec::new();
+        merge_table(&mut self.merged, table, "", &mut origins);
+        for path in origins {
+            let line = find_key_line(text, &path);
+            self.origins.insert(
+                path,
+                ValueOrigin {
+                    source: name.to_string(),
+                    line,
+                },
+            );
+        }
+        self.sources.push(name.to_string());
+        Ok(())
+    }
+
+    /// 合并 `PROVISIONER__<TABLE>__<KEY>` 形式的环境变量
+    ///
+    /// 键名不区分大小写；如果 `[ap]` 中没有 `ssid` 但有 `ap_ssid`，
+    /// `PROVISIONER__AP__SSID` 会覆盖 `ap_ssid`。
+    /// 值的类型按被覆盖的值推断（字符串、整数或布尔值）。
+    /// 表不存在时直接报错；键名拼错的变量在 `build_app_config` 反序列化时被拒绝。
+    pub fn add_env<I>(&mut self, vars: I) -> Result<(), ConfigErrors>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let mut errors = Vec::new();
+        let mut applied = false;
+
+        for (name, raw) in vars {
+            let Some(rest) = name.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            let segments: Vec<String> = rest.split("__").map(|s| s.to_ascii_lowercase()).collect();
+            let Some((key, tables)) = segments.split_last() else {
+                continue;
+            };
+
+            let table = match lookup_table_mut(&mut self.merged, tables) {
+                Some(t) => t,
+                None => {
+                    errors.push(env_error(
+                        &name,
+                        &name,
+                        format!("unknown table '{}'", tables.join(".")),
+                    ));
+                    continue;
+                }
+            };
+
+            let key = resolve_env_key(table, tables, key);
+            let path = join_path(&tables.join("."), &key);
+            let value = match parse_env_value(table.get(&key), &raw) {
+                Ok(v) => v,
+                Err(message) => {
+                    errors.push(env_error(&path, &name, message));
+                    continue;
+                }
+            };
+            table.insert(key, value);
+            self.origins.insert(
+                path,
+                ValueOrigin {
+                    source: format!("${}", name),
+                    line: None,
+                },
+            );
+            applied = true;
+        }
+
+        if applied {
+            self.sources.push("environment".to_string());
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigErrors(errors))
+        }
+    }
+
+    /// 以 TOML 格式输出合并后的配置，每个值后面注明来源，敏感字段会被隐藏
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "# Layers (lowest to highest precedence):");
+        for source in &self.sources {
+            let _ = writeln!(out, "#   {}", source);
+        }
+        self.render_table(&self.merged, "", &mut out);
+        out
+    }
+
+    fn render_table(&self, table: &Table, prefix: &str, out: &mut String) {
+        // 先输出当前表中的普通值，再输出子表
+        for (key, value) in table {
+            if value.is_table() {
+                continue;
+            }
+            let path = join_path(prefix, key);
+            let shown = if is_secret_key(key) {
+                "\"<redacted>\"".to_string()
+            } else {
+                value.to_string()
+            };
+            let origin = self
+                .origins
+                .get(&path)
+                .map(|o| o.to_string())
+                .unwrap_or_else(|| "?".to_string());
+            let _ = writeln!(out, "{} = {}  # {}", key, shown, origin);
+        }
+        for (key, value) in table {
+            if let Value::Table(sub) = value {
+                let path = join_path(prefix, key);
+                let _ = writeln!(out, "\n[{}]", path);
+                self.render_table(sub, &path, out);
+            }
+        }
+    }
+}
+
+/// 递归合并，返回被写入的叶子键路径
+fn merge_table(base: &mut Table, overlay: Table, prefix: &str, written: &mut Vec<String>) {
+    for (key, value) in overlay {
+        let path = join_path(prefix, &key);
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(existing)), Value::Table(sub)) => {
+                merge_table(existing, sub, &path, written);
+            }
+            (_, Value::Table(sub)) => {
+                let mut fresh = Table::new();
+                merge_table(&mut fresh, sub, &path, written);
+                base.insert(key, Value::Table(fresh));
+            }
+            (_, value) => {
+                base.insert(key, value);
+                written.push(path);
+            }
+        }
+    }
+}
+
+fn lookup_table_mut<'a>(root: &'a mut Table, tables: &[String]) -> Option<&'a mut Table> {
+    let mut current = root;
+    for name in tables {
+        current = current.get_mut(name)?.as_table_mut()?;
+    }
+    Some(current)
+}
+
+/// 允许省略与表名相同的键前缀（`ap.ssid` -> `ap.ap_ssid`）
+fn resolve_env_key(table: &Table, tables: &[String], key: &str) -> String {
+    if table.contains_key(key) {
+        return key.to_string();
+    }
+    if let Some(last) = tables.last() {
+        let prefixed = format!("{}_{}", last, key);
+        if table.contains_key(&prefixed) {
+            return prefixed;
+        }
+    }
+    key.to_string()
+}
+
+/// 按已有值的类型解析环境变量；没有已有值时按 TOML 字面量解析，失败则视为字符串
+fn parse_env_value(existing: Option<&Value>, raw: &str) -> Result<Value, String> {
+    match existing {
+        Some(Value::String(_)) => Ok(Value::String(raw.to_string())),
+        // hostapd_channel 等整数键也接受 "auto"
+        Some(Value::Integer(_)) if raw.trim() == "auto" => Ok(Value::String("auto".to_string())),
+        Some(Value::Integer(_)) => raw
+            .trim()
+            .parse::<i64>()
+            .map(Value::Integer)
+            .map_err(|_| format!("expected an integer, got '{}'", raw)),
+        Some(Value::Boolean(_)) => raw
+            .trim()
+            .parse::<bool>()
+            .map(Value::Boolean)
+            .map_err(|_| format!("expected true or false, got '{}'", raw)),
+        Some(Value::Float(_)) => raw
+            .trim()
+            .parse::<f64>()
+            .map(Value::Float)
+            .map_err(|_| format!("expected a number, got '{}'", raw)),
+        Some(Value::Table(_)) => Err("cannot override a whole table".to_string()),
+        _ => Ok(toml::from_str::<Table>(&format!("v = {}", raw))
+            .ok()
+            .and_then(|mut t| t.remove("v"))
+            .unwrap_or_else(|| Value::String(raw.to_string()))),
+    }
+}
+
+fn env_error(key: &str, name: &str, message: String) -> ConfigError {
+    ConfigError {
+        key: key.to_string(),
+        origin: Some(ValueOrigin {
+            source: format!("${}", name),
+            line: None,
+        }),
+        message,
+    }
+}
+
+fn join_path(prefix: &str, key: &str) -> String {
+    if prefix.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", prefix, key)
+    }
+}
+
+/// 需要在输出中隐藏的字段
+fn is_secret_key(key: &str) -> bool {
+    let key = key.to_ascii_lowercase();
+    ["psk", "password", "secret", "passphrase"]
+        .iter()
+        .any(|s| key.contains(s))
+}
+
+/// 查找 `a.b.key` 在 TOML 文本中所在的行号（从 1 开始）
+fn find_key_line(source: &str, path: &str) -> Option<usize> {
+    let (table, key) = path.rsplit_once('.').unwrap_or(("", path));
+    let mut current_table = String::new();
+    for (idx, line) in source.lines().enumerate() {
+        let trimmed = line.trim();
+        if let Some(header) = trimmed.strip_prefix('[').and_then(|t| t.strip_suffix(']')) {
+            current_table = header.trim().to_string();
+            continue;
+        }
+        if current_table != table {
+            continue;
+        }
+        if let Some((k, _)) = trimmed.split_once('=')
+            && k.trim() == key
+        {
+            return Some(idx + 1);
+        }
+    }
+    None
+}
+
+/// 将字节偏移量转换为行号（从 1 开始）
+fn line_of_offset(source: &str, offset: usize) -> usize {
+    source[..offset.min(source.len())].matches('\n').count() + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    fn base() -> ConfigLayers {
+        let mut layers = ConfigLayers::new();
+        layers
+            .add_toml("base.toml", "[ap]\nap_ssid = \"Base\"\nap_psk = \"secret123\"\n\n[monitor]\nfallback_after = 60\n")
+            .unwrap();
+        layers
+    }
+
+    #[test]
+    fn later_layers_win() {
+        let mut layers = base();
+        layers.add_toml("conf.d/10-a.toml", "[ap]\nap_ssid = \"A\"\n").unwrap();
+        layers.add_toml("conf.d/20-b.toml", "\n[ap]\nap_ssid = \"B\"\n").unwrap();
+
+        assert_eq!(layers.merged()["ap"]["ap_ssid"].as_str(), Some("B"));
+        // 没有被覆盖的键保留原来的来源
+        assert_eq!(layers.merged()["ap"]["ap_psk"].as_str(), Some("secret123"));
+        assert_eq!(layers.origin("ap.ap_ssid").unwrap().to_string(), "conf.d/20-b.toml:3");
+        assert_eq!(layers.origin("ap.ap_psk").unwrap().to_string(), "base.toml:3");
+        assert_eq!(layers.sources(), ["base.toml", "conf.d/10-a.toml", "conf.d/20-b.toml"]);
+    }
+
+    #[test]
+    fn env_overrides_files() {
+        let mut layers = base();
+        layers.add_toml("conf.d/10-a.toml", "[ap]\nap_ssid = \"A\"\n").unwrap();
+        layers
+            .add_env(env(&[
+                ("PROVISIONER__AP__SSID", "FromEnv"),
+                ("PROVISIONER__MONITOR__FALLBACK_AFTER", "30"),
+                ("OTHER__AP__SSID", "ignored"),
+            ]))
+            .unwrap();
+
+        let merged = layers.merged();
+        // `SSID` 省略了表名前缀，覆盖的是 `ap_ssid`
+        assert_eq!(merged["ap"]["ap_ssid"].as_str(), Some("FromEnv"));
+        assert_eq!(merged["monitor"]["fallback_after"].as_integer(), Some(30));
+        let origin = layers.origin("ap.ap_ssid").unwrap();
+        assert!(origin.is_env());
+        assert_eq!(origin.to_string(), "$PROVISIONER__AP__SSID");
+        assert_eq!(layers.sources().last().map(String::as_str), Some("environment"));
+    }
+
+    #[test]
+    fn env_errors() {
+        let mut layers = base();
+        let errors = layers
+            .add_env(env(&[
+                ("PROVISIONER__NOPE__KEY", "1"),
+                ("PROVISIONER__MONITOR__FALLBACK_AFTER", "soon"),
+            ]))
+            .unwrap_err();
+        let keys: Vec<&str> = errors.0.iter().map(|e| e.key.as_str()).collect();
+        assert_eq!(keys, ["PROVISIONER__NOPE__KEY", "monitor.fallback_after"]);
+        // 出错的覆盖不会写入
+        assert_eq!(layers.merged()["monitor"]["fallback_after"].as_integer(), Some(60));
+    }
+
+    #[test]
+    fn toml_syntax_errors_have_line() {
+        let mut layers = ConfigLayers::new();
+        let errors = layers.add_toml("bad.toml", "[ap]\nap_ssid = \"ok\"\nap_psk = \n").unwrap_err();
+        assert_eq!(errors.0[0].origin.as_ref().unwrap().to_string(), "bad.toml:3");
+    }
+
+    #[test]
+    fn render_redacts_secrets() {
+        let mut layers = base();
+        layers.add_env(env(&[("PROVISIONER__AP__PSK", "hunter22")])).unwrap();
+        let rendered = layers.render();
+        assert!(rendered.contains("ap_ssid = \"Base\"  # base.toml:2"));
+        assert!(rendered.contains("ap_psk = \"<redacted>\"  # $PROVISIONER__AP__PSK"));
+        assert!(!rendered.contains("hunter22"));
+        assert!(!rendered.contains("secret123"));
+    }
+}
@@ -0,0 +1,353 @@
+use crate::backend::WpaCtrlBackend;
+use crate::config::{DbusBus, DbusConfig};
+use crate::events::{ProvisionEvent, SequencedEvent};
+use crate::structs::{ConnectionRequest, Network};
+use crate::trigger::{self, TriggerHandle, TriggerSource};
+use crate::validation::ValidationCode;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use tokio_stream::StreamExt;
+use tokio_stream::wrappers::BroadcastStream;
+use zbus::fdo;
+use zbus::object_server::{InterfaceRef, SignalEmitter};
+use zbus::zvariant::{Type, Value};
+
+/// 服务对象的路径
+pub const OBJECT_PATH: &str = "/org/example/Provisioner1";
+
+/// `Scan` 返回的网络，签名 `(ssysu)`
+#[derive(Debug, Serialize, Type)]
+struct DbusNetwork {
+    ssid: String,
+    ssid_hex: String,
+    signal: u8,
+    security: String,
+    frequency: u32,
+}
+
+impl From<Network> for DbusNetwork {
+    fn from(n: Network) -> Self {
+        Self {
+            ssid: n.display_name,
+            ssid_hex: n.ssid_hex,
+            signal: n.signal,
+            security: n.security,
+            frequency: n.frequency,
+        }
+    }
+}
+
+/// `org.example.Provisioner1` 接口，方法与 JSON-RPC 控制 socket 使用同一套后端逻辑
+struct Provisioner {
+    backend: Arc<WpaCtrlBackend>,
+    trigger: Option<TriggerHandle>,
+}
+
+impl Provisioner {
+    fn start_connect(&self, request: ConnectionRequest) -> fdo::Result<()> {
+        self.backend.start_connect(request).map_err(|e| match e.code {
+            ValidationCode::Busy => fdo::Error::Failed(e.message),
+            _ => fdo::Error::InvalidArgs(e.to_string()),
+        })
+    }
+}
+
+#[zbus::interface(name = "org.example.Provisioner1")]
+impl Provisioner {
+    /// 扫描结果；AP 运行时只有双射频模式会重新扫描
+    async fn scan(&self, refresh: bool) -> fdo::Result<Vec<DbusNetwork>> {
+        let networks = self
+            .backend
+            .networks(refresh)
+            .await
+            .map_err(|e| fdo::Error::Failed(format!("Scan failed: {:#}", e)))?;
+        Ok(networks.into_iter().map(DbusNetwork::from).collect())
+    }
+
+    /// 在后台连接，结果通过 `State`、`LastResult` 的变化获取
+    async fn connect(&self, ssid: String, password: String) -> fdo::Result<()> {
+        self.start_connect(ConnectionRequest {
+            ssid,
+            ssid_hex: None,
+            password,
+        })
+    }
+
+    /// 与 `Connect` 相同，SSID 以十六进制给出（用于不是 UTF-8 的 SSID）
+    async fn connect_hex(&self, ssid_hex: String, password: String) -> fdo::Result<()> {
+        self.start_connect(ConnectionRequest {
+            ssid: String::new(),
+            ssid_hex: Some(ssid_hex),
+            password,
+        })
+    }
+
+    /// 进入配网（仅 `monitor` 模式）
+    async fn start_provisioning(&self) -> fdo::Result<()> {
+        trigger::start_provisioning(&self.backend, self.trigger.as_ref(), TriggerSource::Dbus)
+            .map_err(|e| fdo::Error::Failed(e.to_string()))
+    }
+
+    /// 结束配网，关闭 AP 并回到已保存的网络
+    async fn stop_provisioning(&self) -> fdo::Result<()> {
+        self.backend
+            .request_stop()
+            .map_err(|e| fdo::Error::Failed(e.to_string()))
+    }
+
+    /// 删除所有已保存的网络
+    async fn forget(&self) -> fdo::Result<()> {
+        self.backend
+            .forget_networks()
+            .await
+            .map_err(|e| fdo::Error::Failed(format!("{:#}", e)))
+    }
+
+    #[zbus(property)]
+    async fn state(&self) -> String {
+        self.backend.state().as_str().to_string()
+    }
+
+    /// 最近一次连接尝试：`ssid`、`success`、`timestamp`、`duration_ms`，失败时还有 `reason`；
+    /// 还没有尝试过时为空字典
+    #[zbus(property)]
+    async fn last_result(&self) -> HashMap<String, Value<'static>> {
+        let mut result = HashMap::new();
+        if let Some(last) = self.backend.last_result() {
+            result.insert("ssid".to_string(), Value::from(last.ssid));
+            result.insert("success".to_string(), Value::from(last.success));
+            result.insert("timestamp".to_string(), Value::from(last.timestamp));
+            result.insert("duration_ms".to_string(), Value::from(last.duration_ms));
+            if let Some(reason) = last.reason {
+                let reason = serde_json::to_value(reason)
+                    .ok()
+                    .and_then(|v| v.as_str().map(str::to_string))
+                    .unwrap_or_default();
+                result.insert("reason".to_string(), Value::from(reason));
+            }
+        }
+        result
+    }
+
+    #[zbus(property)]
+    async fn ap_ssid(&self) -> String {
+        self.backend.ap_config().ssid.clone()
+    }
+
+    /// 每次状态变化时发出（与 `State` 属性的变化通知同时发出）
+    #[zbus(signal, name = "StateChanged")]
+    async fn emit_state_changed(emitter: &SignalEmitter<'_>, state: &str) -> zbus::Result<()>;
+}
+
+/// 按配置注册 D-Bus 服务（未配置 `bus` 时返回 None）
+///
+/// 返回的连接需要在服务期间一直持有，丢弃后服务随之注销。
+pub async fn serve(
+    config: &DbusConfig,
+    backend: Arc<WpaCtrlBackend>,
+    trigger: Option<TriggerHandle>,
+) -> Result<Option<zbus::Connection>> {
+    let Some(bus) = &config.bus else {
+        return Ok(None);
+    };
+    let builder = match bus {
+        DbusBus::System => zbus::connection::Builder::system(),
+        DbusBus::Session => zbus::connection::Builder::session(),
+        DbusBus::Address(address) => zbus::connection::Builder::address(address.as_str()),
+    }
+    .context("Invalid D-Bus address")?;
+
+    let interface = Provisioner {
+        backend: backend.clone(),
+        trigger,
+    };
+    let connection = builder
+        .name(config.name.as_str())
+        .with_context(|| format!("Invalid D-Bus service name '{}'", config.name))?
+        .serve_at(OBJECT_PATH, interface)?
+        .build()
+        .await
+        .with_context(|| format!("Failed to register {} on the D-Bus {:?} bus", config.name, bus))?;
+    tracing::info!("🚌 D-Bus service {} registered at {}", config.name, OBJECT_PATH);
+
+    let interface = connection
+        .object_server()
+        .interface::<_, Provisioner>(OBJECT_PATH)
+        .await?;
+    // 在这里订阅而不是在任务里，否则任务第一次运行前发布的事件会丢失
+    let (_, receiver) = backend.events().subscribe(None);
+    tokio::spawn(emit_changes(backend, interface, receiver));
+    Ok(Some(connection))
+}
+
+/// 把后端的状态变化转换成 `StateChanged` 信号和属性变化通知
+async fn emit_changes(
+    backend: Arc<WpaCtrlBackend>,
+    interface: InterfaceRef<Provisioner>,
+    receiver: broadcast::Receiver<SequencedEvent>,
+) {
+    // 订阅者处理过慢导致的 Lagged 错误直接跳过
+    let mut events = BroadcastStream::new(receiver).filter_map(|res| res.ok());
+    let emitter = interface.signal_emitter();
+    let mut last_result = backend.last_result();
+
+    while let Some(seq) = events.next().await {
+        let ProvisionEvent::StateChanged { state } = seq.event else {
+            continue;
+        };
+        let provisioner = interface.get().await;
+        let emitted = async {
+            Provisioner::emit_state_changed(emitter, state.as_str()).await?;
+            provisioner.state_changed(emitter).await?;
+            // 连接结果总是在对应的状态变化之前记录
+            let current = backend.last_result();
+            if current != last_result {
+                last_result = current;
+                provisioner.last_result_changed(emitter).await?;
+            }
+            zbus::Result::Ok(())
+        };
+        if let Err(e) = emitted.await {
+            tracing::warn!("Failed to emit D-Bus signal: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_app_config;
+    use crate::events::ProvisionerState;
+    use std::process::Stdio;
+    use std::time::Duration;
+    use tokio::io::{AsyncBufReadExt, BufReader};
+
+    const NAME: &str = "org.example.Provisioner1";
+
+    /// 启动一个私有的 dbus-daemon，返回进程和总线地址；没有安装 dbus-daemon 时返回 None
+    async fn private_bus(dir: &std::path::Path) -> Option<(tokio::process::Child, String)> {
+        let mut daemon = match tokio::process::Command::new("dbus-daemon")
+            .arg("--session")
+            .arg("--nofork")
+            .arg("--print-address=1")
+            .arg(format!("--address=unix:path={}", dir.join("bus").display()))
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+        {
+            Ok(daemon) => daemon,
+            Err(e) => {
+                eprintln!("skipping D-Bus test, cannot start dbus-daemon: {}", e);
+                return None;
+            }
+        };
+        let stdout = daemon.stdout.take().unwrap();
+        let mut address = String::new();
+        BufReader::new(stdout).read_line(&mut address).await.unwrap();
+        Some((daemon, address.trim().to_string()))
+    }
+
+    #[tokio::test]
+    async fn serves_state_scan_and_signals() {
+        let dir = tempfile::tempdir().unwrap();
+        let Some((_daemon, address)) = private_bus(dir.path()).await else {
+            return;
+        };
+
+        let backend = Arc::new(WpaCtrlBackend::detached(&test_app_config("")));
+        let config = DbusConfig {
+            bus: Some(DbusBus::Address(address.clone())),
+            name: NAME.to_string(),
+        };
+        let _service = serve(&config, backend.clone(), None).await.unwrap().unwrap();
+
+        let client = zbus::connection::Builder::address(address.as_str())
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let proxy = zbus::Proxy::new(&client, NAME, OBJECT_PATH, NAME).await.unwrap();
+
+        assert_eq!(proxy.get_property::<String>("State").await.unwrap(), "starting");
+        assert_eq!(proxy.get_property::<String>("ApSsid").await.unwrap(), "Echo-mate");
+        let last_result: HashMap<String, zbus::zvariant::OwnedValue> = proxy.get_property("LastResult").await.unwrap();
+        assert!(last_result.is_empty());
+
+        // AP 没有运行、也没有扫描过：不刷新时返回空的缓存
+        let networks: Vec<(String, String, u8, String, u32)> = proxy.call("Scan", &(false,)).await.unwrap();
+        assert!(networks.is_empty());
+
+        // 十六进制 SSID 走同一套校验
+        let err = proxy.call_method("ConnectHex", &("zz", "password123")).await.unwrap_err();
+        assert!(
+            matches!(&err, zbus::Error::MethodError(name, _, _) if name.as_str() == "org.freedesktop.DBus.Error.InvalidArgs"),
+            "{}",
+            err
+        );
+
+        // 不在 monitor 模式时没有触发入口
+        let err = proxy.call_method("StartProvisioning", &()).await.unwrap_err();
+        assert!(err.to_string().contains("only available in monitor mode"), "{}", err);
+
+        let mut signals = proxy.receive_signal("StateChanged").await.unwrap();
+        let mut changes = proxy.receive_property_changed::<String>("State").await;
+        // 第一次总是先给出当前值
+        assert_eq!(changes.next().await.unwrap().get().await.unwrap(), "starting");
+
+        backend.set_state(ProvisionerState::Provisioning);
+        let signal = tokio::time::timeout(Duration::from_secs(5), signals.next())
+            .await
+            .expect("no StateChanged signal")
+            .unwrap();
+        let (state,): (String,) = signal.body().deserialize().unwrap();
+        assert_eq!(state, "provisioning");
+        let changed = tokio::time::timeout(Duration::from_secs(5), changes.next())
+            .await
+            .expect("no PropertiesChanged for State")
+            .unwrap();
+        assert_eq!(changed.get().await.unwrap(), "provisioning");
+    }
+
+    #[tokio::test]
+    async fn emits_changes_published_right_after_serve() {
+        let dir = tempfile::tempdir().unwrap();
+        let Some((_daemon, address)) = private_bus(dir.path()).await else {
+            return;
+        };
+
+        // 先在总线上监听信号，服务注册后立即改变状态，中间不让出执行权
+        let client = zbus::connection::Builder::address(address.as_str())
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let rule = zbus::MatchRule::builder()
+            .msg_type(zbus::message::Type::Signal)
+            .interface(NAME)
+            .unwrap()
+            .member("StateChanged")
+            .unwrap()
+            .build();
+        let mut signals = zbus::MessageStream::for_match_rule(rule, &client, None).await.unwrap();
+
+        let backend = Arc::new(WpaCtrlBackend::detached(&test_app_config("")));
+        let config = DbusConfig {
+            bus: Some(DbusBus::Address(address)),
+            name: NAME.to_string(),
+        };
+        let _service = serve(&config, backend.clone(), None).await.unwrap().unwrap();
+        backend.set_state(ProvisionerState::Offline);
+
+        let signal = tokio::time::timeout(Duration::from_secs(5), signals.next())
+            .await
+            .expect("no StateChanged signal")
+            .unwrap()
+            .unwrap();
+        let (state,): (String,) = signal.body().deserialize().unwrap();
+        assert_eq!(state, "offline");
+    }
+}
@@ -0,0 +1,425 @@
+use crate::config::{AppConfig, ApConfig};
+use crate::identity;
+use crate::interface::{self, AUTO_INTERFACE};
+use std::fmt;
+use std::io::ErrorKind;
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, UdpSocket};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+/// Linux capability 编号：CAP_NET_ADMIN
+const CAP_NET_ADMIN: u32 = 12;
+
+/// 单项检查的结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+    Pass,
+    /// 无法确定，或不一定会导致失败
+    Warn,
+    Fail,
+}
+
+impl fmt::Display for CheckStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            CheckStatus::Pass => "PASS",
+            CheckStatus::Warn => "WARN",
+            CheckStatus::Fail => "FAIL",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// 一项预检结果
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub name: String,
+    pub status: CheckStatus,
+    pub detail: String,
+    /// 失败或警告时的修复建议
+    pub hint: Option<String>,
+}
+
+impl Check {
+    fn pass(name: impl Into<String>, detail: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            status: CheckStatus::Pass,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    fn warn(name: impl Into<String>, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            status: CheckStatus::Warn,
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+
+    fn fail(name: impl Into<String>, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            status: CheckStatus::Fail,
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+}
+
+/// 全部预检结果
+#[derive(Debug, Clone, Default)]
+pub struct DoctorReport {
+    pub checks: Vec<Check>,
+}
+
+impl DoctorReport {
+    pub fn has_failures(&self) -> bool {
+        self.checks.iter().any(|c| c.status == CheckStatus::Fail)
+    }
+
+    /// 把结果写入日志（启动时使用），通过的项只在 debug 级别输出
+    pub fn log(&self) {
+        for check in &self.checks {
+            let hint = check.hint.as_deref().unwrap_or("");
+            match check.status {
+                CheckStatus::Pass => tracing::debug!("doctor: {}: {}", check.name, check.detail),
+                CheckStatus::Warn => {
+                    tracing::warn!("doctor: {}: {} (hint: {})", check.name, check.detail, hint)
+                }
+                CheckStatus::Fail => {
+                    tracing::error!("doctor: {}: {} (hint: {})", check.name, check.detail, hint)
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for DoctorReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for check in &self.checks {
+            writeln!(f, "[{}] {}: {}", check.status, check.name, check.detail)?;
+            if let Some(hint) = &check.hint {
+                writeln!(f, "       hint: {}", hint)?;
+            }
+        }
+        let failed = self.checks.iter().filter(|c| c.status == CheckStatus::Fail).count();
+        let warned = self.checks.iter().filter(|c| c.status == CheckStatus::Warn).count();
+        write!(
+            f,
+            "\n{} checks, {} failed, {} warnings",
+            self.checks.len(),
+            failed,
+            warned
+        )
+    }
+}
+
+/// 执行全部预检
+///
+/// 只做只读的探测，不会启动或杀死任何进程，可以在程序运行时单独执行。
+pub fn run_checks(app_config: &AppConfig) -> DoctorReport {
+    let ap = &app_config.ap;
+    let mut checks = Vec::new();
+
+    for binary in required_binaries(app_config) {
+        checks.push(check_binary(binary));
+    }
+
+    let sysfs_root = Path::new(&ap.sysfs_root);
+    checks.extend(check_interface(sysfs_root, ap.ap_interface(), true));
+    if ap.is_dual_radio() {
+        checks.extend(check_interface(sysfs_root, ap.sta_interface(), false));
+    }
+    checks.push(check_rfkill(sysfs_root));
+    checks.push(check_net_admin());
+
+    checks.push(check_port("DNS port 53/udp", Protocol::Udp, 53));
+    checks.push(check_port("DHCP port 67/udp", Protocol::Udp, 67));
+    checks.push(check_port(
+        &format!("web port {}/tcp", ap.bind_addr.port()),
+        Protocol::Tcp,
+        ap.bind_addr.port(),
+    ));
+
+    checks.push(check_ctrl_dir(Path::new(&ap.wpa_ctrl_interface)));
+    checks.push(check_identity(ap));
+
+    DoctorReport { checks }
+}
+
+fn required_binaries(app_config: &AppConfig) -> Vec<&'static str> {
+    #[cfg(feature = "audio")]
+    let audio = app_config.audio.is_some();
+    #[cfg(not(feature = "audio"))]
+    let audio = {
+        let _ = app_config;
+        false
+    };
+
+    let mut binaries = vec!["wpa_supplicant", "hostapd", "dnsmasq", "udhcpc", "ip"];
+    if audio {
+        binaries.push("aplay");
+    }
+    binaries
+}
+
+/// 在 PATH 中查找可执行文件
+pub fn find_in_path(binary: &str) -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(binary))
+        .find(|candidate| {
+            candidate
+                .metadata()
+                .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+                .unwrap_or(false)
+        })
+}
+
+fn check_binary(binary: &str) -> Check {
+    let name = format!("binary {}", binary);
+    match find_in_path(binary) {
+        Some(path) => Check::pass(name, path.display().to_string()),
+        None => {
+            let package = match binary {
+                "wpa_supplicant" => "wpa_supplicant (wpasupplicant)",
+                "udhcpc" => "busybox (udhcpc applet)",
+                "aplay" => "alsa-utils",
+                "ip" => "iproute2",
+                other => other,
+            };
+            Check::fail(
+                name,
+                "not found in PATH",
+                format!("install {} in the rootfs, or add its directory to PATH", package),
+            )
+        }
+    }
+}
+
+/// 检查网卡是否存在、是否为无线网卡，`needs_ap` 时还检查是否支持 AP 模式
+fn check_interface(sysfs_root: &Path, interface: &str, needs_ap: bool) -> Vec<Check> {
+    let name = format!("interface {}", interface);
+    if interface == AUTO_INTERFACE {
+        return vec![Check::fail(
+            name,
+            "automatic detection found no matching wireless interface",
+            "check `interface_match_mac` / `interface_match_driver`, or set the interface explicitly",
+        )];
+    }
+
+    let sys = sysfs_root.join("class/net").join(interface);
+    if !sys.exists() {
+        return vec![Check::fail(
+            name,
+            "does not exist",
+            "check `interface_name` / `ap_interface` / `sta_interface` in the config; `ip link` lists available interfaces",
+        )];
+    }
+
+    let phy = interface::read_phy_name(&sys);
+    let is_wireless = sys.join("wireless").exists() || phy.is_some();
+    if !is_wireless {
+        return vec![Check::fail(
+            name,
+            "is not a wireless interface",
+            "point `interface_name` at the Wi-Fi interface (usually wlan0)",
+        )];
+    }
+
+    let mut checks = vec![Check::pass(
+        name,
+        format!("wireless ({})", phy.as_deref().unwrap_or("unknown phy")),
+    )];
+    if !needs_ap {
+        return checks;
+    }
+
+    let ap_name = format!("interface {} AP mode", interface);
+    checks.push(match phy.as_deref().map(interface::phy_supports_ap) {
+        Some(Some(true)) => Check::pass(ap_name, "supported"),
+        Some(Some(false)) => Check::fail(
+            ap_name,
+            "the driver does not list AP in its supported interface modes",
+            "use a Wi-Fi chip/driver with AP support, or a firmware that enables it",
+        ),
+        _ => Check::warn(
+            ap_name,
+            "could not be determined",
+            "install `iw` so `iw phy <phy> info` can report supported modes",
+        ),
+    });
+    checks
+}
+
+/// 检查 Wi-Fi 是否被 rfkill 屏蔽
+fn check_rfkill(sysfs_root: &Path) -> Check {
+    let name = "rfkill";
+    let Ok(entries) = std::fs::read_dir(sysfs_root.join("class/rfkill")) else {
+        return Check::pass(name, "no rfkill switches");
+    };
+
+    let mut soft = Vec::new();
+    let mut hard = Vec::new();
+    for entry in entries.flatten() {
+        let dir = entry.path();
+        let read = |file: &str| {
+            std::fs::read_to_string(dir.join(file))
+                .map(|s| s.trim().to_string())
+                .unwrap_or_default()
+        };
+        if read("type") != "wlan" {
+            continue;
+        }
+        let label = read("name");
+        if read("hard") == "1" {
+            hard.push(label.clone());
+        }
+        if read("soft") == "1" {
+            soft.push(label);
+        }
+    }
+
+    if !hard.is_empty() {
+        Check::fail(
+            name,
+            format!("hard blocked: {}", hard.join(", ")),
+            "a hardware switch or the firmware is blocking Wi-Fi; check the board's radio enable line",
+        )
+    } else if !soft.is_empty() {
+        Check::fail(
+            name,
+            format!("soft blocked: {}", soft.join(", ")),
+            "run `rfkill unblock wifi`",
+        )
+    } else {
+        Check::pass(name, "not blocked")
+    }
+}
+
+/// 检查当前进程是否拥有 CAP_NET_ADMIN
+fn check_net_admin() -> Check {
+    let name = "CAP_NET_ADMIN";
+    let cap_eff = std::fs::read_to_string("/proc/self/status").ok().and_then(|status| {
+        status
+            .lines()
+            .find_map(|l| l.strip_prefix("CapEff:"))
+            .and_then(|v| u64::from_str_radix(v.trim(), 16).ok())
+    });
+    match cap_eff {
+        Some(caps) if caps & (1 << CAP_NET_ADMIN) != 0 => Check::pass(name, "present"),
+        Some(_) => Check::fail(
+            name,
+            "missing",
+            "run as root, or grant it with `setcap cap_net_admin,cap_net_raw,cap_net_bind_service+ep <binary>`",
+        ),
+        None => Check::warn(name, "could not read /proc/self/status", "run as root"),
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Protocol {
+    Tcp,
+    Udp,
+}
+
+/// 尝试在通配地址上绑定端口，判断是否已被占用
+fn check_port(name: &str, protocol: Protocol, port: u16) -> Check {
+    let addr = SocketAddr::from((Ipv4Addr::UNSPECIFIED, port));
+    let result = match protocol {
+        Protocol::Tcp => TcpListener::bind(addr).map(drop),
+        Protocol::Udp => UdpSocket::bind(addr).map(drop),
+    };
+    match result {
+        Ok(()) => Check::pass(name, "free"),
+        Err(e) if e.kind() == ErrorKind::AddrInUse => {
+            let hint = match port {
+                53 => "another DNS server is listening (often systemd-resolved: set DNSStubListener=no in /etc/systemd/resolved.conf), or a dnsmasq left over from a previous run",
+                67 => "another DHCP server is running (dnsmasq, udhcpd, isc-dhcp-server); stop it while provisioning",
+                _ => "another web server is listening on this port; stop it or change `ap_bind_addr`",
+            };
+            Check::fail(name, "already in use", hint)
+        }
+        Err(e) if e.kind() == ErrorKind::PermissionDenied => Check::warn(
+            name,
+            "could not test: permission denied",
+            "run as root or with CAP_NET_BIND_SERVICE",
+        ),
+        Err(e) => Check::warn(name, format!("could not test: {}", e), "check the network stack"),
+    }
+}
+
+/// 检查能否生成 AP 名称和密码（模板占位符、设备密钥、序列号），不写入 `identity_state_path`
+fn check_identity(ap: &ApConfig) -> Check {
+    match identity::preview_identity(ap) {
+        Ok(resolved) => Check::pass("AP identity", format!("SSID '{}'", resolved.ssid)),
+        Err(e) => Check::fail(
+            "AP identity",
+            format!("{:#}", e),
+            "fix `ap_ssid` / `ap_psk`, or provide the device secret at `ap_psk_secret_path`",
+        ),
+    }
+}
+
+/// 检查 wpa_supplicant 控制接口目录是否可写
+///
+/// 预检不修改系统：目录不存在时只报告，由 wpa_supplicant 启动时自己创建。
+fn check_ctrl_dir(dir: &Path) -> Check {
+    let name = format!("wpa_ctrl_interface {}", dir.display());
+    let hint = "run as root, or point `wpa_ctrl_interface` at a writable directory such as /run/wpa_supplicant";
+
+    if !dir.exists() {
+        // wpa_supplicant 只创建最后一级目录
+        return match dir.parent() {
+            Some(parent) if parent.is_dir() => Check::warn(
+                name,
+                "does not exist yet",
+                "wpa_supplicant creates it on start; make sure it runs as root or can write to the parent directory",
+            ),
+            _ => Check::fail(name, "does not exist, and neither does its parent directory", hint),
+        };
+    }
+    if !dir.is_dir() {
+        return Check::fail(name, "exists but is not a directory", hint);
+    }
+    let probe = dir.join(format!(".provisioner-doctor-{}", std::process::id()));
+    match std::fs::write(&probe, b"") {
+        Ok(()) => {
+            let _ = std::fs::remove_file(&probe);
+            Check::pass(name, "writable")
+        }
+        Err(e) => Check::fail(name, format!("not writable: {}", e), hint),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_ctrl_dir_is_reported_not_created() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("wpa_supplicant");
+        let check = check_ctrl_dir(&dir);
+        assert_eq!(check.status, CheckStatus::Warn);
+        assert!(!dir.exists());
+
+        let check = check_ctrl_dir(&tmp.path().join("missing/wpa_supplicant"));
+        assert_eq!(check.status, CheckStatus::Fail);
+        assert!(!tmp.path().join("missing").exists());
+    }
+
+    #[test]
+    fn existing_ctrl_dir_is_checked() {
+        let tmp = tempfile::tempdir().unwrap();
+        assert_eq!(check_ctrl_dir(tmp.path()).status, CheckStatus::Pass);
+        assert_eq!(std::fs::read_dir(tmp.path()).unwrap().count(), 0);
+
+        let file = tmp.path().join("file");
+        std::fs::write(&file, b"").unwrap();
+        assert_eq!(check_ctrl_dir(&file).status, CheckStatus::Fail);
+    }
+}
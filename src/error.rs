@@ -0,0 +1,168 @@
+use crate::validation::{ValidationCode, ValidationError};
+use axum::{
+    extract::rejection::JsonRejection,
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+};
+use serde::Serialize;
+
+/// API 错误码（机器可读）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiErrorCode {
+    /// 请求体不是合法的 JSON，或缺少字段
+    InvalidRequest,
+    /// 请求字段未通过校验
+    Validation(ValidationCode),
+    /// 资源不存在
+    NotFound,
+    /// 服务器内部错误
+    Internal,
+}
+
+impl ApiErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiErrorCode::InvalidRequest => "invalid_request",
+            ApiErrorCode::Validation(code) => code.as_str(),
+            ApiErrorCode::NotFound => "not_found",
+            ApiErrorCode::Internal => "internal",
+        }
+    }
+}
+
+/// 所有 HTTP 处理器统一使用的错误类型
+///
+/// 响应体格式：
+/// `{"status": "error", "code": "...", "field": "...", "message": "..."}`
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub code: ApiErrorCode,
+    pub field: Option<&'static str>,
+    pub message: String,
+}
+
+#[derive(Serialize)]
+struct ApiErrorBody<'a> {
+    status: &'static str,
+    code: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    field: Option<&'static str>,
+    message: &'a str,
+}
+
+impl ApiError {
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::NOT_FOUND,
+            code: ApiErrorCode::NotFound,
+            field: None,
+            message: message.into(),
+        }
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            code: ApiErrorCode::Internal,
+            field: None,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<ValidationError> for ApiError {
+    fn from(e: ValidationError) -> Self {
+        // 正在连接时的请求并没有写错，返回 409 让前端稍后重试
+        if e.code == ValidationCode::Busy {
+            return Self {
+                status: StatusCode::CONFLICT,
+                code: ApiErrorCode::Validation(e.code),
+                field: None,
+                message: e.message,
+            };
+        }
+        Self {
+            status: StatusCode::UNPROCESSABLE_ENTITY,
+            code: ApiErrorCode::Validation(e.code),
+            field: Some(e.field),
+            message: e.message,
+        }
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(e: JsonRejection) -> Self {
+        Self {
+            status: StatusCode::BAD_REQUEST,
+            code: ApiErrorCode::InvalidRequest,
+            field: None,
+            message: e.body_text(),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = ApiErrorBody {
+            status: "error",
+            code: self.code.as_str(),
+            field: self.field,
+            message: &self.message,
+        };
+        (self.status, Json(body)).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structs::ConnectionRequest;
+    use axum::body::{Body, to_bytes};
+    use axum::extract::FromRequest;
+    use axum::http::{Request, header};
+
+    async fn body_json(response: Response) -> serde_json::Value {
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    #[tokio::test]
+    async fn validation_errors_are_422() {
+        let error = ValidationError {
+            code: ValidationCode::PasswordLength,
+            field: "password",
+            message: "too short".to_string(),
+        };
+        let response = ApiError::from(error).into_response();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body = body_json(response).await;
+        assert_eq!(body["status"], "error");
+        assert_eq!(body["code"], "password_length");
+        assert_eq!(body["field"], "password");
+    }
+
+    #[tokio::test]
+    async fn busy_is_409() {
+        let response = ApiError::from(ValidationError::busy()).into_response();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let body = body_json(response).await;
+        assert_eq!(body["code"], "busy");
+        assert!(body.get("field").is_none());
+    }
+
+    #[tokio::test]
+    async fn malformed_json_is_400() {
+        let request = Request::builder()
+            .method("POST")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from("{\"ssid\": \"Home\""))
+            .unwrap();
+        let rejection = Json::<ConnectionRequest>::from_request(request, &()).await.unwrap_err();
+        let response = ApiError::from(rejection).into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = body_json(response).await;
+        assert_eq!(body["code"], "invalid_request");
+        assert!(body.get("field").is_none());
+    }
+}
@@ -0,0 +1,224 @@
+use crate::structs::FailureReason;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+
+/// 重放缓冲区保留的最大事件数
+const REPLAY_CAPACITY: usize = 64;
+
+/// 配网过程中推送给前端的事件
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ProvisionEvent {
+    /// 开始扫描（attempt 从 1 开始计数）
+    ScanStarted { attempt: u32, max_attempts: u32 },
+    /// 扫描完成
+    ScanCompleted { count: usize },
+    /// AP 已启动
+    ApStarted { ssid: String },
+    /// AP 已停止
+    ApStopped,
+    /// 开始连接目标网络
+    ConnectStarted { ssid: String },
+    /// 连接子步骤
+    ConnectStep { step: ConnectStep },
+    /// 连接成功
+    ConnectSucceeded { ssid: String },
+    /// 连接失败
+    ConnectFailed { ssid: String, reason: FailureReason },
+    /// 配网超时（`idle` 表示因长时间无人使用而结束）
+    ProvisioningTimedOut { idle: bool },
+    /// 配网被本地应用主动结束
+    ProvisioningStopped,
+    /// 设备状态变化
+    StateChanged { state: ProvisionerState },
+}
+
+/// 设备所处的阶段（控制 socket 的 `status` 和事件中使用）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProvisionerState {
+    /// 正在启动 wpa_supplicant，或等待已保存的网络连上
+    Starting,
+    /// 已连上网络
+    Online,
+    /// 没有连上网络，也没有在配网
+    Offline,
+    /// AP 正在运行，等待手机提交网络
+    Provisioning,
+    /// 正在连接手机提交的网络
+    Connecting,
+}
+
+impl ProvisionerState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProvisionerState::Starting => "starting",
+            ProvisionerState::Online => "online",
+            ProvisionerState::Offline => "offline",
+            ProvisionerState::Provisioning => "provisioning",
+            ProvisionerState::Connecting => "connecting",
+        }
+    }
+
+    /// 是否处于配网会话中（AP 运行或连接进行中）
+    pub fn is_provisioning(&self) -> bool {
+        matches!(self, ProvisionerState::Provisioning | ProvisionerState::Connecting)
+    }
+}
+
+/// 连接过程中的子步骤
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectStep {
+    /// 正在关联 (ASSOCIATING / ASSOCIATED)
+    Associating,
+    /// 四次握手 (4WAY_HANDSHAKE / GROUP_HANDSHAKE)
+    Handshake,
+    /// 正在通过 DHCP 获取 IP
+    Dhcp,
+    /// 正在校验连接结果
+    Verifying,
+}
+
+impl ProvisionEvent {
+    /// SSE 的 `event:` 字段
+    pub fn name(&self) -> &'static str {
+        match self {
+            ProvisionEvent::ScanStarted { .. } => "scan_started",
+            ProvisionEvent::ScanCompleted { .. } => "scan_completed",
+            ProvisionEvent::ApStarted { .. } => "ap_started",
+            ProvisionEvent::ApStopped => "ap_stopped",
+            ProvisionEvent::ConnectStarted { .. } => "connect_started",
+            ProvisionEvent::ConnectStep { .. } => "connect_step",
+            ProvisionEvent::ConnectSucceeded { .. } => "connect_succeeded",
+            ProvisionEvent::ConnectFailed { .. } => "connect_failed",
+            ProvisionEvent::ProvisioningTimedOut { .. } => "provisioning_timed_out",
+            ProvisionEvent::ProvisioningStopped => "provisioning_stopped",
+            ProvisionEvent::StateChanged { .. } => "state_changed",
+        }
+    }
+}
+
+/// 带序号的事件，序号用作 SSE 的 `id:` 字段
+#[derive(Debug, Clone)]
+pub struct SequencedEvent {
+    pub id: u64,
+    pub event: ProvisionEvent,
+}
+
+/// 事件总线：广播实时事件，并保留最近的事件用于 `Last-Event-ID` 重放
+pub struct EventBus {
+    sender: broadcast::Sender<SequencedEvent>,
+    // 序号和重放缓冲区放在同一把锁下，保证两者顺序一致
+    history: Mutex<(u64, VecDeque<SequencedEvent>)>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(REPLAY_CAPACITY);
+        Self {
+            sender,
+            history: Mutex::new((0, VecDeque::with_capacity(REPLAY_CAPACITY))),
+        }
+    }
+
+    /// 发布一个事件（没有订阅者时也会进入重放缓冲区）
+    pub fn publish(&self, event: ProvisionEvent) {
+        let mut history = self.history.lock().unwrap();
+        history.0 += 1;
+        let seq = SequencedEvent { id: history.0, event };
+
+        if history.1.len() == REPLAY_CAPACITY {
+            history.1.pop_front();
+        }
+        history.1.push_back(seq.clone());
+
+        tracing::debug!(id = seq.id, "Event: {:?}", seq.event);
+        // 没有订阅者时 send 会返回错误，可以忽略
+        let _ = self.sender.send(seq);
+    }
+
+    /// 订阅事件流
+    ///
+    /// 返回 `last_event_id` 之后仍在缓冲区中的历史事件，以及一个实时接收器。
+    /// 两者在同一把锁下获取，因此不会丢失或重复事件。
+    pub fn subscribe(
+        &self,
+        last_event_id: Option<u64>,
+    ) -> (Vec<SequencedEvent>, broadcast::Receiver<SequencedEvent>) {
+        let history = self.history.lock().unwrap();
+        let receiver = self.sender.subscribe();
+        let replay = match last_event_id {
+            Some(last) => history.1.iter().filter(|e| e.id > last).cloned().collect(),
+            None => Vec::new(),
+        };
+        (replay, receiver)
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn publish_scans(bus: &EventBus, count: usize) {
+        for count in 0..count {
+            bus.publish(ProvisionEvent::ScanCompleted { count });
+        }
+    }
+
+    fn ids(events: &[SequencedEvent]) -> Vec<u64> {
+        events.iter().map(|e| e.id).collect()
+    }
+
+    #[test]
+    fn replays_events_after_last_id() {
+        let bus = EventBus::new();
+        publish_scans(&bus, 5);
+        assert_eq!(ids(&bus.subscribe(Some(2)).0), [3, 4, 5]);
+        assert!(bus.subscribe(Some(5)).0.is_empty());
+        // 没有 Last-Event-ID 的新订阅者不重放
+        assert!(bus.subscribe(None).0.is_empty());
+    }
+
+    #[test]
+    fn keeps_only_the_latest_events() {
+        let bus = EventBus::new();
+        publish_scans(&bus, REPLAY_CAPACITY + 6);
+        let replay = bus.subscribe(Some(0)).0;
+        assert_eq!(replay.len(), REPLAY_CAPACITY);
+        assert_eq!(replay.first().unwrap().id, 7);
+        assert_eq!(replay.last().unwrap().id, REPLAY_CAPACITY as u64 + 6);
+    }
+
+    #[test]
+    fn stale_last_id_replays_whole_buffer() {
+        let bus = EventBus::new();
+        publish_scans(&bus, REPLAY_CAPACITY + 10);
+        // id 3 早已被挤出缓冲区，只能补发仍保留的部分
+        let replay = bus.subscribe(Some(3)).0;
+        assert_eq!(replay.len(), REPLAY_CAPACITY);
+        assert_eq!(replay.first().unwrap().id, 11);
+    }
+
+    #[test]
+    fn live_events_continue_after_replay() {
+        let bus = EventBus::new();
+        publish_scans(&bus, 3);
+        let (replay, mut receiver) = bus.subscribe(Some(1));
+        publish_scans(&bus, 2);
+
+        let mut seen = ids(&replay);
+        while let Ok(event) = receiver.try_recv() {
+            seen.push(event.id);
+        }
+        assert_eq!(seen, [2, 3, 4, 5]);
+    }
+}
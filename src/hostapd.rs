@@ -0,0 +1,511 @@
+use crate::channel;
+use crate::config::ApConfig;
+use crate::psk::wpa_printf_quote;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fmt::Write as _;
+
+/// AP 的加密方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ApSecurity {
+    /// 按 `hostapd_wpa` / `hostapd_wpa_key_mgmt` 等字段生成 WPA/WPA2 配置
+    #[default]
+    #[serde(rename = "psk")]
+    Psk,
+    /// 开放网络，不需要密码
+    #[serde(rename = "open")]
+    Open,
+    /// 仅 WPA3-SAE
+    #[serde(rename = "sae")]
+    Sae,
+    /// WPA2/WPA3 过渡模式（同时接受 PSK 和 SAE）
+    #[serde(rename = "psk-sae")]
+    PskSae,
+}
+
+impl ApSecurity {
+    /// 是否需要密码
+    pub fn needs_passphrase(&self) -> bool {
+        *self != ApSecurity::Open
+    }
+}
+
+/// 生成 hostapd 配置时发现的非法取值
+#[derive(Debug, Clone)]
+pub struct HostapdConfigError {
+    /// 出错的 hostapd 配置项
+    pub directive: &'static str,
+    pub message: String,
+}
+
+impl HostapdConfigError {
+    fn new(directive: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            directive,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for HostapdConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.directive, self.message)
+    }
+}
+
+impl std::error::Error for HostapdConfigError {}
+
+/// hostapd 配置文件的类型化描述
+///
+/// 用 `HostapdConfig::new` 创建后链式设置各项，最后调用 `render` 生成配置文本。
+/// 所有值在输出前都会检查，含换行等控制字符的值要么被转义（SSID），
+/// 要么被拒绝（其它字段），避免注入额外的配置项。
+#[derive(Debug, Clone)]
+pub struct HostapdConfig {
+    interface: String,
+    ssid: Vec<u8>,
+    hw_mode: String,
+    channel: u8,
+    security: ApSecurity,
+    passphrase: String,
+    wpa: u8,
+    wpa_key_mgmt: String,
+    wpa_pairwise: String,
+    rsn_pairwise: String,
+    country_code: Option<String>,
+    ieee80211d: bool,
+    ieee80211n: bool,
+    ht_capab: Option<String>,
+    ieee80211ac: bool,
+    vht_capab: Option<String>,
+    hidden: bool,
+    max_num_sta: Option<u32>,
+    ap_isolate: bool,
+    extra_lines: Vec<String>,
+}
+
+impl HostapdConfig {
+    /// 默认：2.4GHz 信道 6，开放网络
+    pub fn new(interface: impl Into<String>, ssid: impl Into<Vec<u8>>) -> Self {
+        Self {
+            interface: interface.into(),
+            ssid: ssid.into(),
+            hw_mode: "g".to_string(),
+            channel: 6,
+            security: ApSecurity::Open,
+            passphrase: String::new(),
+            wpa: 2,
+            wpa_key_mgmt: "WPA-PSK".to_string(),
+            wpa_pairwise: "CCMP".to_string(),
+            rsn_pairwise: "CCMP".to_string(),
+            country_code: None,
+            ieee80211d: false,
+            ieee80211n: false,
+            ht_capab: None,
+            ieee80211ac: false,
+            vht_capab: None,
+            hidden: false,
+            max_num_sta: None,
+            ap_isolate: false,
+            extra_lines: Vec::new(),
+        }
+    }
+
+    /// 按 `[ap]` 配置生成
+    ///
+    /// `hostapd_channel = "auto"` 时先使用频段的默认信道，由调用方通过 `channel` 换成选择结果。
+    pub fn from_ap_config(ap: &ApConfig) -> Self {
+        let mut config = Self::new(ap.ap_interface(), ap.ssid.as_bytes())
+            .hw_mode(
+                &ap.hostapd_hw_mode,
+                ap.hostapd_channel
+                    .fixed()
+                    .unwrap_or_else(|| channel::default_channel(&ap.hostapd_hw_mode)),
+            )
+            .hidden(ap.hostapd_hidden)
+            .max_num_sta(ap.hostapd_max_num_sta)
+            .ap_isolate(ap.hostapd_ap_isolate)
+            .extra_lines(ap.hostapd_extra_lines.clone());
+
+        config = match ap.security {
+            ApSecurity::Open => config.open(),
+            ApSecurity::Psk => config.wpa_psk(
+                &ap.psk,
+                ap.hostapd_wpa,
+                &ap.hostapd_wpa_key_mgmt,
+                &ap.hostapd_wpa_pairwise,
+                &ap.hostapd_rsn_pairwise,
+            ),
+            ApSecurity::Sae => config.sae(&ap.psk),
+            ApSecurity::PskSae => config.psk_sae(&ap.psk),
+        };
+        if let Some(country) = &ap.hostapd_country_code {
+            config = config.country(country, ap.hostapd_ieee80211d);
+        }
+        if ap.hostapd_ieee80211n {
+            config = config.ht(ap.hostapd_ht_capab.clone());
+        }
+        if ap.hostapd_ieee80211ac {
+            config = config.vht(ap.hostapd_vht_capab.clone());
+        }
+        config
+    }
+
+    /// 频段 (a/b/g) 和信道
+    pub fn hw_mode(mut self, hw_mode: &str, channel: u8) -> Self {
+        self.hw_mode = hw_mode.to_string();
+        self.channel = channel;
+        self
+    }
+
+    /// 只修改信道
+    pub fn channel(mut self, channel: u8) -> Self {
+        self.channel = channel;
+        self
+    }
+
+    /// 开放网络
+    pub fn open(mut self) -> Self {
+        self.security = ApSecurity::Open;
+        self.passphrase.clear();
+        self
+    }
+
+    /// WPA/WPA2-PSK，所有参数原样写入
+    pub fn wpa_psk(mut self, passphrase: &str, wpa: u8, key_mgmt: &str, pairwise: &str, rsn_pairwise: &str) -> Self {
+        self.security = ApSecurity::Psk;
+        self.passphrase = passphrase.to_string();
+        self.wpa = wpa;
+        self.wpa_key_mgmt = key_mgmt.to_string();
+        self.wpa_pairwise = pairwise.to_string();
+        self.rsn_pairwise = rsn_pairwise.to_string();
+        self
+    }
+
+    /// 仅 WPA3-SAE（强制管理帧保护）
+    pub fn sae(mut self, password: &str) -> Self {
+        self.security = ApSecurity::Sae;
+        self.passphrase = password.to_string();
+        self
+    }
+
+    /// WPA2/WPA3 过渡模式（管理帧保护可选）
+    pub fn psk_sae(mut self, passphrase: &str) -> Self {
+        self.security = ApSecurity::PskSae;
+        self.passphrase = passphrase.to_string();
+        self
+    }
+
+    /// 国家码（ISO 3166-1 两位字母），`ieee80211d` 表示是否在 Beacon 中广播
+    pub fn country(mut self, code: &str, ieee80211d: bool) -> Self {
+        self.country_code = Some(code.to_string());
+        self.ieee80211d = ieee80211d;
+        self
+    }
+
+    /// 启用 802.11n，可选 HT 能力（如 `[HT40+][SHORT-GI-20]`）
+    pub fn ht(mut self, ht_capab: Option<String>) -> Self {
+        self.ieee80211n = true;
+        self.ht_capab = ht_capab;
+        self
+    }
+
+    /// 启用 802.11ac（需要 5GHz 和 802.11n），可选 VHT 能力
+    pub fn vht(mut self, vht_capab: Option<String>) -> Self {
+        self.ieee80211ac = true;
+        self.vht_capab = vht_capab;
+        self
+    }
+
+    /// 隐藏 SSID（Beacon 中 SSID 为空）
+    pub fn hidden(mut self, hidden: bool) -> Self {
+        self.hidden = hidden;
+        self
+    }
+
+    /// 最大客户端数量，None 表示使用 hostapd 默认值
+    pub fn max_num_sta(mut self, max_num_sta: Option<u32>) -> Self {
+        self.max_num_sta = max_num_sta;
+        self
+    }
+
+    /// 禁止客户端之间互相通信
+    pub fn ap_isolate(mut self, ap_isolate: bool) -> Self {
+        self.ap_isolate = ap_isolate;
+        self
+    }
+
+    /// 原样追加到配置末尾的 `key=value` 行
+    pub fn extra_lines(mut self, lines: Vec<String>) -> Self {
+        self.extra_lines = lines;
+        self
+    }
+
+    /// 检查所有取值并生成配置文本
+    pub fn render(&self) -> Result<String, HostapdConfigError> {
+        let mut out = String::new();
+        let mut line = |key: &'static str, value: &str| -> Result<(), HostapdConfigError> {
+            check_single_line(key, value)?;
+            let _ = writeln!(out, "{}={}", key, value);
+            Ok(())
+        };
+
+        line("interface", &self.interface)?;
+        if self.ssid.is_empty() || self.ssid.len() > 32 {
+            return Err(HostapdConfigError::new(
+                "ssid",
+                format!("must be 1-32 bytes, got {}", self.ssid.len()),
+            ));
+        }
+        // 只含可打印字符的 SSID 原样写入；否则用 ssid2 的 P"..." 转义格式，换行无法逃逸出这一行
+        match std::str::from_utf8(&self.ssid) {
+            Ok(s) if !s.chars().any(char::is_control) => {
+                line("ssid", s)?;
+                if !s.is_ascii() {
+                    line("utf8_ssid", "1")?;
+                }
+            }
+            _ => line("ssid2", &wpa_printf_quote(&self.ssid))?,
+        }
+        if self.hidden {
+            line("ignore_broadcast_ssid", "1")?;
+        }
+
+        // 频段、国家码与 802.11n/ac
+        if !matches!(self.hw_mode.as_str(), "a" | "b" | "g") {
+            return Err(HostapdConfigError::new(
+                "hw_mode",
+                format!("unknown hw_mode '{}', expected one of a, b, g", self.hw_mode),
+            ));
+        }
+        line("hw_mode", &self.hw_mode)?;
+        line("channel", &self.channel.to_string())?;
+        if let Some(code) = &self.country_code {
+            if code.len() != 2 || !code.bytes().all(|b| b.is_ascii_uppercase()) {
+                return Err(HostapdConfigError::new(
+                    "country_code",
+                    format!("'{}' is not a two-letter uppercase ISO 3166-1 code", code),
+                ));
+            }
+            line("country_code", code)?;
+            if self.ieee80211d {
+                line("ieee80211d", "1")?;
+            }
+        }
+        if self.ieee80211ac && !self.ieee80211n {
+            return Err(HostapdConfigError::new("ieee80211ac", "requires ieee80211n"));
+        }
+        if self.ieee80211ac && self.hw_mode != "a" {
+            return Err(HostapdConfigError::new(
+                "ieee80211ac",
+                format!("requires hw_mode 'a' (5GHz), got '{}'", self.hw_mode),
+            ));
+        }
+        if self.ieee80211n {
+            line("ieee80211n", "1")?;
+            line("wmm_enabled", "1")?;
+            if let Some(ht_capab) = &self.ht_capab {
+                line("ht_capab", ht_capab)?;
+            }
+        }
+        if self.ieee80211ac {
+            line("ieee80211ac", "1")?;
+            if let Some(vht_capab) = &self.vht_capab {
+                line("vht_capab", vht_capab)?;
+            }
+        }
+
+        // 客户端
+        if let Some(max) = self.max_num_sta {
+            line("max_num_sta", &max.to_string())?;
+        }
+        if self.ap_isolate {
+            line("ap_isolate", "1")?;
+        }
+
+        // 加密
+        if self.security.needs_passphrase() {
+            check_passphrase(self.security, &self.passphrase)?;
+        }
+        match self.security {
+            ApSecurity::Open => {}
+            ApSecurity::Psk => {
+                line("wpa", &self.wpa.to_string())?;
+                line("wpa_passphrase", &self.passphrase)?;
+                line("wpa_key_mgmt", &self.wpa_key_mgmt)?;
+                line("wpa_pairwise", &self.wpa_pairwise)?;
+                line("rsn_pairwise", &self.rsn_pairwise)?;
+            }
+            ApSecurity::Sae => {
+                line("wpa", "2")?;
+                line("wpa_key_mgmt", "SAE")?;
+                line("sae_password", &self.passphrase)?;
+                line("rsn_pairwise", "CCMP")?;
+                line("ieee80211w", "2")?;
+            }
+            ApSecurity::PskSae => {
+                line("wpa", "2")?;
+                line("wpa_key_mgmt", "WPA-PSK SAE")?;
+                line("wpa_passphrase", &self.passphrase)?;
+                line("sae_password", &self.passphrase)?;
+                line("rsn_pairwise", "CCMP")?;
+                line("ieee80211w", "1")?;
+            }
+        }
+
+        // 额外配置项
+        if !self.extra_lines.is_empty() {
+            let _ = writeln!(out, "# extra lines");
+        }
+        for extra in &self.extra_lines {
+            check_single_line("extra_lines", extra)?;
+            match extra.split_once('=') {
+                Some((key, _)) if !key.trim().is_empty() && !key.trim_start().starts_with('#') => {
+                    let _ = writeln!(out, "{}", extra.trim());
+                }
+                _ => {
+                    return Err(HostapdConfigError::new(
+                        "extra_lines",
+                        format!("'{}' is not a key=value line", extra),
+                    ));
+                }
+            }
+        }
+
+        Ok(out)
+    }
+}
+
+/// 拒绝含有换行等控制字符的值，防止注入额外的配置行
+fn check_single_line(directive: &'static str, value: &str) -> Result<(), HostapdConfigError> {
+    if value.chars().any(char::is_control) {
+        return Err(HostapdConfigError::new(
+            directive,
+            format!("{:?} contains a newline or control character", value),
+        ));
+    }
+    Ok(())
+}
+
+/// hostapd 把 `wpa_passphrase` / `sae_password` 的值原样读到行尾，不支持转义
+fn check_passphrase(security: ApSecurity, passphrase: &str) -> Result<(), HostapdConfigError> {
+    let directive = if security == ApSecurity::Sae { "sae_password" } else { "wpa_passphrase" };
+    let len = passphrase.chars().count();
+    if !(8..=63).contains(&len) {
+        return Err(HostapdConfigError::new(
+            directive,
+            format!("must be 8-63 characters, got {}", len),
+        ));
+    }
+    if !passphrase.bytes().all(|b| (0x20..=0x7e).contains(&b)) {
+        return Err(HostapdConfigError::new(
+            directive,
+            "must contain only printable ASCII characters",
+        ));
+    }
+    // sae_password 中的 `|` 会被当作 `|mac=`、`|id=` 等参数的分隔符
+    if security != ApSecurity::Psk && passphrase.contains('|') {
+        return Err(HostapdConfigError::new(
+            "sae_password",
+            "must not contain '|' (reserved by hostapd for password parameters)",
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 期望输出放在 tests/golden 下，修改渲染逻辑后需要同步更新这些文件
+
+    #[test]
+    fn renders_default_config() {
+        let config = HostapdConfig::from_ap_config(&crate::config::test_app_config("").ap);
+        assert_eq!(config.render().unwrap(), include_str!("../tests/golden/hostapd_wpa2.conf"));
+    }
+
+    #[test]
+    fn renders_open_network() {
+        let config = HostapdConfig::new("wlan0", "Echo-mate").hidden(true);
+        assert_eq!(config.render().unwrap(), include_str!("../tests/golden/hostapd_open.conf"));
+    }
+
+    #[test]
+    fn renders_wpa2_network() {
+        let config = HostapdConfig::new("wlan0", "回声配网")
+            .hw_mode("g", 11)
+            .wpa_psk("password123", 2, "WPA-PSK", "CCMP", "CCMP")
+            .country("CN", true)
+            .ht(Some("[SHORT-GI-20]".to_string()))
+            .max_num_sta(Some(4))
+            .ap_isolate(true)
+            .extra_lines(vec!["beacon_int=100".to_string()]);
+        assert_eq!(config.render().unwrap(), include_str!("../tests/golden/hostapd_wpa2_options.conf"));
+    }
+
+    #[test]
+    fn renders_sae_and_transition_networks() {
+        let sae = HostapdConfig::new("wlan1", "Echo-mate").hw_mode("a", 36).sae("password123");
+        assert_eq!(sae.render().unwrap(), include_str!("../tests/golden/hostapd_sae.conf"));
+
+        let transition = HostapdConfig::new("wlan0", "Echo-mate").psk_sae("password123");
+        assert_eq!(transition.render().unwrap(), include_str!("../tests/golden/hostapd_psk_sae.conf"));
+    }
+
+    #[test]
+    fn renders_hidden_80211ac_network() {
+        let config = crate::config::test_app_config(
+            "[ap]\n\
+             hostapd_hidden = true\n\
+             hostapd_hw_mode = \"a\"\n\
+             hostapd_channel = 36\n\
+             hostapd_country_code = \"US\"\n\
+             hostapd_ieee80211n = true\n\
+             hostapd_ht_capab = \"[HT40+][SHORT-GI-40]\"\n\
+             hostapd_ieee80211ac = true\n\
+             hostapd_vht_capab = \"[SHORT-GI-80]\"\n",
+        );
+        let rendered = HostapdConfig::from_ap_config(&config.ap).render().unwrap();
+        assert_eq!(rendered, include_str!("../tests/golden/hostapd_hidden_80211ac.conf"));
+    }
+
+    #[test]
+    fn escapes_ssid_with_control_bytes() {
+        let config = HostapdConfig::new("wlan0", b"Echo\nwpa=1\xff".to_vec());
+        let rendered = config.render().unwrap();
+        assert!(rendered.starts_with("interface=wlan0\nssid2=P\"Echo\\nwpa=1\\xff\"\nhw_mode=g\n"), "{}", rendered);
+        assert!(!rendered.lines().any(|l| l.starts_with("wpa=")));
+    }
+
+    #[test]
+    fn rejects_values_with_newlines() {
+        let err = HostapdConfig::new("wlan0\nssid=evil", "Echo-mate").render().unwrap_err();
+        assert_eq!(err.directive, "interface");
+
+        let err = HostapdConfig::new("wlan0", "Echo-mate")
+            .wpa_psk("password123", 2, "WPA-PSK\nwpa=1", "CCMP", "CCMP")
+            .render()
+            .unwrap_err();
+        assert_eq!(err.directive, "wpa_key_mgmt");
+
+        let err = HostapdConfig::new("wlan0", "Echo-mate")
+            .extra_lines(vec!["beacon_int=100\nssid=evil".to_string()])
+            .render()
+            .unwrap_err();
+        assert_eq!(err.directive, "extra_lines");
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        let base = || HostapdConfig::new("wlan0", "Echo-mate");
+        let directive = |config: HostapdConfig| config.render().unwrap_err().directive;
+        assert_eq!(directive(HostapdConfig::new("wlan0", "")), "ssid");
+        assert_eq!(directive(base().hw_mode("n", 6)), "hw_mode");
+        assert_eq!(directive(base().country("cn", false)), "country_code");
+        assert_eq!(directive(base().vht(None)), "ieee80211ac");
+        assert_eq!(directive(base().wpa_psk("short", 2, "WPA-PSK", "CCMP", "CCMP")), "wpa_passphrase");
+        assert_eq!(directive(base().sae("pass|word123")), "sae_password");
+        assert_eq!(directive(base().extra_lines(vec!["# comment=1".to_string()])), "extra_lines");
+    }
+}
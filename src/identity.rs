@@ -0,0 +1,420 @@
+use crate::config::ApConfig;
+use anyhow::{Context, Result, anyhow};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::io::Read;
+use std::path::Path;
+
+/// `ap_psk` 取这个值时生成随机密码并持久化
+pub const PSK_RANDOM: &str = "random";
+/// `ap_psk` 取这个值时由设备密钥和序列号派生密码
+pub const PSK_DERIVED: &str = "derived";
+
+/// `ap_ssid` 模板中支持的占位符
+const PLACEHOLDERS: &[&str] = &["mac4", "mac6", "mac", "hostname", "serial"];
+
+/// 生成的密码长度和字符集（去掉了容易混淆的 0/o、1/l/i）
+const GENERATED_PSK_LEN: usize = 12;
+const PSK_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+
+/// SSID 的最大字节数
+const SSID_MAX_LEN: usize = 32;
+
+/// `ap_psk` 是否为需要生成的密码
+pub fn is_generated_psk(psk: &str) -> bool {
+    psk == PSK_RANDOM || psk == PSK_DERIVED
+}
+
+/// 检查 `ap_ssid` 模板中的占位符是否都受支持
+pub fn check_ssid_template(template: &str) -> Result<(), String> {
+    for name in placeholders(template)? {
+        if !PLACEHOLDERS.contains(&name) {
+            return Err(format!(
+                "unknown placeholder '{{{}}}', expected one of {}",
+                name,
+                PLACEHOLDERS.iter().map(|p| format!("{{{}}}", p)).collect::<Vec<_>>().join(", ")
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// 持久化的设备身份，保证模板和随机密码在重启后保持不变
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct IdentityState {
+    /// 生成 `ssid` 时使用的模板；模板改变后重新生成
+    #[serde(default)]
+    ssid_template: String,
+    #[serde(default)]
+    ssid: String,
+    /// `ap_psk = "random"` 时生成的密码
+    #[serde(default)]
+    random_psk: String,
+}
+
+/// 把 `ap_ssid` 模板和 `ap_psk = "random"/"derived"` 替换为本设备的实际值
+///
+/// 首次生成的 SSID 和随机密码写入 `identity_state_path`，之后启动时直接复用，
+/// 这样即使 MAC 地址被驱动随机化或主机名被修改，设备名也不会变。
+pub fn resolve_identity(ap: &mut ApConfig) -> Result<()> {
+    resolve(ap, true)
+}
+
+/// 和 `resolve_identity` 一样生成 SSID 和密码，但不写入 `identity_state_path`（供 `doctor` 使用）
+pub fn preview_identity(ap: &ApConfig) -> Result<ApConfig> {
+    let mut ap = ap.clone();
+    resolve(&mut ap, false)?;
+    Ok(ap)
+}
+
+fn resolve(ap: &mut ApConfig, persist: bool) -> Result<()> {
+    let templated = ap.ssid.contains('{');
+    let psk_mode = ap.psk.clone();
+    if !templated && !is_generated_psk(&psk_mode) {
+        return Ok(());
+    }
+
+    let state_path = Path::new(&ap.identity_state_path);
+    let mut state = load_state(state_path);
+    let mut dirty = false;
+
+    if templated {
+        if state.ssid_template == ap.ssid && !state.ssid.is_empty() {
+            tracing::debug!("Reusing persisted SSID '{}' for template '{}'", state.ssid, ap.ssid);
+        } else {
+            let ssid = render_ssid_template(&ap.ssid, &DeviceFacts::collect(ap))?;
+            state.ssid_template = ap.ssid.clone();
+            state.ssid = ssid;
+            dirty = true;
+        }
+        ap.ssid = state.ssid.clone();
+    }
+
+    match psk_mode.as_str() {
+        PSK_RANDOM => {
+            if state.random_psk.is_empty() {
+                state.random_psk = random_psk()?;
+                dirty = true;
+            }
+            ap.psk = state.random_psk.clone();
+        }
+        PSK_DERIVED => {
+            let secret = std::fs::read(&ap.psk_secret_path).with_context(|| {
+                format!("ap_psk = \"derived\" needs a device secret at {}", ap.psk_secret_path)
+            })?;
+            let serial = device_serial().ok_or_else(|| anyhow!("cannot determine the device serial number"))?;
+            ap.psk = derive_psk(&secret, &serial);
+        }
+        _ => {}
+    }
+
+    if dirty
+        && persist
+        && let Err(e) = save_state(state_path, &state)
+    {
+        tracing::warn!(
+            "⚠️ Failed to persist AP identity to {}: {:#}. It may change after a reboot.",
+            state_path.display(),
+            e
+        );
+    }
+    Ok(())
+}
+
+/// 用于填充 SSID 模板的设备信息
+#[derive(Debug, Default)]
+struct DeviceFacts {
+    /// AP 网卡 MAC 地址（12 位大写十六进制，无分隔符）
+    mac: Option<String>,
+    hostname: Option<String>,
+    serial: Option<String>,
+}
+
+impl DeviceFacts {
+    fn collect(ap: &ApConfig) -> Self {
+        let address = Path::new(&ap.sysfs_root)
+            .join("class/net")
+            .join(ap.ap_interface())
+            .join("address");
+        let mac = std::fs::read_to_string(address)
+            .ok()
+            .map(|s| s.trim().replace([':', '-'], "").to_ascii_uppercase())
+            .filter(|s| s.len() == 12);
+        Self {
+            mac,
+            hostname: read_first(&["/proc/sys/kernel/hostname", "/etc/hostname"]),
+            serial: device_serial(),
+        }
+    }
+
+    fn get(&self, name: &str) -> Option<String> {
+        let mac_suffix = |n: usize| self.mac.as_ref().map(|m| m[m.len() - n..].to_string());
+        match name {
+            "mac4" => mac_suffix(4),
+            "mac6" => mac_suffix(6),
+            "mac" => self.mac.clone(),
+            "hostname" => self.hostname.clone(),
+            "serial" => self.serial.clone(),
+            _ => None,
+        }
+    }
+}
+
+/// 替换模板中的占位符；结果超过 32 字节时按字符边界截断
+fn render_ssid_template(template: &str, facts: &DeviceFacts) -> Result<String> {
+    check_ssid_template(template).map_err(|e| anyhow!("ap_ssid: {}", e))?;
+
+    let mut ssid = template.to_string();
+    for name in placeholders(template).map_err(|e| anyhow!("ap_ssid: {}", e))? {
+        let value = facts
+            .get(name)
+            .ok_or_else(|| anyhow!("ap_ssid: cannot determine a value for '{{{}}}'", name))?;
+        ssid = ssid.replace(&format!("{{{}}}", name), &value);
+    }
+
+    if ssid.len() > SSID_MAX_LEN {
+        let mut end = SSID_MAX_LEN;
+        while !ssid.is_char_boundary(end) {
+            end -= 1;
+        }
+        tracing::warn!("SSID '{}' is longer than {} bytes, truncating", ssid, SSID_MAX_LEN);
+        ssid.truncate(end);
+    }
+    Ok(ssid)
+}
+
+/// 找出模板中所有 `{name}` 占位符
+fn placeholders(template: &str) -> Result<Vec<&str>, String> {
+    let mut names = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let after = &rest[start + 1..];
+        let end = after
+            .find('}')
+            .ok_or_else(|| format!("unclosed '{{' in '{}'", template))?;
+        names.push(&after[..end]);
+        rest = &after[end + 1..];
+    }
+    Ok(names)
+}
+
+/// 设备序列号：设备树 > /proc/cpuinfo 的 Serial > machine-id
+fn device_serial() -> Option<String> {
+    read_first(&[
+        "/sys/firmware/devicetree/base/serial-number",
+        "/proc/device-tree/serial-number",
+    ])
+    .or_else(|| {
+        std::fs::read_to_string("/proc/cpuinfo").ok().and_then(|info| {
+            info.lines()
+                .filter_map(|l| l.split_once(':'))
+                .find(|(k, _)| k.trim() == "Serial")
+                .map(|(_, v)| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+        })
+    })
+    .or_else(|| read_first(&["/etc/machine-id"]))
+}
+
+/// 读取第一个存在且非空的文件（去掉首尾空白和设备树字符串末尾的 NUL）
+fn read_first(paths: &[&str]) -> Option<String> {
+    paths.iter().find_map(|p| {
+        std::fs::read_to_string(p)
+            .ok()
+            .map(|s| s.trim_matches(|c: char| c.is_whitespace() || c == '\0').to_string())
+            .filter(|s| !s.is_empty())
+    })
+}
+
+/// HMAC-SHA256(设备密钥, 序列号)，按拒绝采样映射到密码字符集
+///
+/// 一个摘要中可用的字节不够时，继续使用 HMAC(设备密钥, 序列号 || 计数器) 的输出。
+fn derive_psk(secret: &[u8], serial: &str) -> String {
+    let mut psk = String::with_capacity(GENERATED_PSK_LEN);
+    let mut counter = 0u8;
+    while psk.len() < GENERATED_PSK_LEN {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+        mac.update(serial.as_bytes());
+        if counter > 0 {
+            mac.update(&[counter]);
+        }
+        push_unbiased(&mut psk, &mac.finalize().into_bytes());
+        counter += 1;
+    }
+    psk
+}
+
+/// 从 /dev/urandom 生成随机密码
+fn random_psk() -> Result<String> {
+    let mut urandom = std::fs::File::open("/dev/urandom").context("Failed to open /dev/urandom")?;
+    let mut psk = String::with_capacity(GENERATED_PSK_LEN);
+    let mut buf = [0u8; 32];
+    while psk.len() < GENERATED_PSK_LEN {
+        urandom.read_exact(&mut buf).context("Failed to read /dev/urandom")?;
+        push_unbiased(&mut psk, &buf);
+    }
+    Ok(psk)
+}
+
+/// 把字节映射到密码字符集，直到密码达到 `GENERATED_PSK_LEN`
+///
+/// 拒绝采样：丢弃不小于字符集长度整数倍的字节，避免取模偏差。
+fn push_unbiased(psk: &mut String, bytes: &[u8]) {
+    let limit = (256 / PSK_ALPHABET.len() * PSK_ALPHABET.len()) as u8;
+    for &b in bytes.iter().filter(|&&b| b < limit) {
+        if psk.len() == GENERATED_PSK_LEN {
+            break;
+        }
+        psk.push(PSK_ALPHABET[b as usize % PSK_ALPHABET.len()] as char);
+    }
+}
+
+fn load_state(path: &Path) -> IdentityState {
+    match std::fs::read_to_string(path) {
+        Ok(text) => toml::from_str(&text).unwrap_or_else(|e| {
+            tracing::warn!("Ignoring unreadable identity file {}: {}", path.display(), e);
+            IdentityState::default()
+        }),
+        Err(_) => IdentityState::default(),
+    }
+}
+
+/// 先写临时文件再改名，避免掉电时留下半个文件；文件包含密码，权限设为 0600
+fn save_state(path: &Path, state: &IdentityState) -> Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let text = format!(
+        "# Generated by provisioner. Delete this file to generate a new AP identity.\n{}",
+        toml::to_string(state)?
+    );
+    // 临时文件创建时就是 0600，写入密码前不存在其他用户可读的窗口；
+    // 上次中断留下的临时文件先删掉，否则 create_new 会失败
+    let tmp = path.with_extension("tmp");
+    match std::fs::remove_file(&tmp) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&tmp)?;
+    file.write_all(text.as_bytes())?;
+    file.sync_all()?;
+    drop(file);
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn facts() -> DeviceFacts {
+        DeviceFacts {
+            mac: Some("B827EB123456".to_string()),
+            hostname: Some("echo-kitchen".to_string()),
+            serial: Some("10000000abcdef01".to_string()),
+        }
+    }
+
+    fn is_generated(psk: &str) -> bool {
+        psk.len() == GENERATED_PSK_LEN && psk.bytes().all(|b| PSK_ALPHABET.contains(&b))
+    }
+
+    #[test]
+    fn renders_templates() {
+        let render = |template| render_ssid_template(template, &facts()).unwrap();
+        assert_eq!(render("Echo-mate-{mac4}"), "Echo-mate-3456");
+        assert_eq!(render("Echo-{mac6}"), "Echo-123456");
+        assert_eq!(render("{hostname}/{mac}"), "echo-kitchen/B827EB123456");
+        assert_eq!(render("Echo-{serial}"), "Echo-10000000abcdef01");
+        assert_eq!(render("Echo-mate"), "Echo-mate");
+    }
+
+    #[test]
+    fn long_ssids_are_truncated_on_char_boundary() {
+        let facts = DeviceFacts {
+            hostname: Some("客厅里的回声配网设备".to_string()),
+            ..facts()
+        };
+        // "Echo-" 5 字节 + 每个汉字 3 字节，第 10 个汉字会跨过 32 字节
+        let ssid = render_ssid_template("Echo-{hostname}", &facts).unwrap();
+        assert_eq!(ssid, "Echo-客厅里的回声配网设");
+        assert_eq!(ssid.len(), 32);
+    }
+
+    #[test]
+    fn template_errors() {
+        assert!(check_ssid_template("Echo-{mac4}-{hostname}").is_ok());
+        assert!(check_ssid_template("Echo-{ip}").unwrap_err().contains("unknown placeholder '{ip}'"));
+        assert!(check_ssid_template("Echo-{mac4").unwrap_err().contains("unclosed '{'"));
+
+        let no_mac = DeviceFacts { mac: None, ..facts() };
+        let err = render_ssid_template("Echo-{mac4}", &no_mac).unwrap_err();
+        assert_eq!(err.to_string(), "ap_ssid: cannot determine a value for '{mac4}'");
+    }
+
+    #[test]
+    fn derived_psk_is_deterministic_and_device_unique() {
+        let psk = derive_psk(b"fleet secret", "10000000abcdef01");
+        assert!(is_generated(&psk), "{}", psk);
+        assert_eq!(psk, derive_psk(b"fleet secret", "10000000abcdef01"));
+        assert_ne!(psk, derive_psk(b"fleet secret", "10000000abcdef02"));
+        assert_ne!(psk, derive_psk(b"other secret", "10000000abcdef01"));
+
+        let many: std::collections::HashSet<String> =
+            (0..200).map(|i| derive_psk(b"fleet secret", &format!("serial-{}", i))).collect();
+        assert_eq!(many.len(), 200);
+        assert!(many.iter().all(|psk| is_generated(psk)));
+    }
+
+    #[test]
+    fn sampling_rejects_biased_bytes() {
+        // 字符集 31 个字符，248 及以上的字节会被丢弃
+        let mut psk = String::new();
+        push_unbiased(&mut psk, &[248, 255, 0, 30, 31, 247]);
+        assert_eq!(psk, "a9a9");
+        push_unbiased(&mut psk, &[0; 32]);
+        assert_eq!(psk.len(), GENERATED_PSK_LEN);
+        assert!(is_generated(&random_psk().unwrap()));
+    }
+
+    #[test]
+    fn identity_is_persisted_and_reused() {
+        let dir = tempfile::tempdir().unwrap();
+        let iface = dir.path().join("sys/class/net/wlan0");
+        std::fs::create_dir_all(&iface).unwrap();
+        std::fs::write(iface.join("address"), "b8:27:eb:12:34:56\n").unwrap();
+        let state_path = dir.path().join("state/identity.toml");
+        let overlay = format!(
+            "[ap]\nap_ssid = \"Echo-{{mac4}}\"\nap_psk = \"random\"\nidentity_state_path = \"{}\"\nsysfs_root = \"{}\"\n",
+            state_path.display(),
+            dir.path().join("sys").display()
+        );
+        let config = crate::config::test_app_config(&overlay).ap;
+
+        // doctor 只预览，不写入文件
+        assert_eq!(preview_identity(&config).unwrap().ssid, "Echo-3456");
+        assert!(!state_path.exists());
+
+        let mut first = config.clone();
+        resolve_identity(&mut first).unwrap();
+        assert_eq!(first.ssid, "Echo-3456");
+        assert!(is_generated(&first.psk), "{}", first.psk);
+        let mode = std::fs::metadata(&state_path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        // MAC 地址变了也沿用保存的 SSID 和密码
+        std::fs::write(iface.join("address"), "00:11:22:33:ab:cd\n").unwrap();
+        let mut second = config.clone();
+        resolve_identity(&mut second).unwrap();
+        assert_eq!((second.ssid, second.psk), (first.ssid, first.psk));
+    }
+}
@@ -0,0 +1,399 @@
+use crate::config::ApConfig;
+use anyhow::{Result, anyhow};
+use std::path::Path;
+
+/// `interface_name` 取这个值时自动检测无线网卡
+pub const AUTO_INTERFACE: &str = "auto";
+
+/// 多块无线网卡时用于挑选网卡的条件（均为可选，同时给出时必须都满足）
+#[derive(Debug, Clone, Default)]
+pub struct InterfaceMatcher {
+    /// MAC 地址前缀，例如 `b8:27:eb`（不区分大小写，`-` 与 `:` 等价）
+    pub mac_prefix: Option<String>,
+    /// 内核驱动名，例如 `brcmfmac`
+    pub driver: Option<String>,
+}
+
+impl InterfaceMatcher {
+    pub fn from_ap_config(ap: &ApConfig) -> Self {
+        Self {
+            mac_prefix: ap.interface_match_mac.clone(),
+            driver: ap.interface_match_driver.clone(),
+        }
+    }
+
+    fn matches(&self, iface: &WirelessInterface) -> bool {
+        let mac_ok = self.mac_prefix.as_ref().is_none_or(|prefix| {
+            normalize_mac(&iface.mac).starts_with(&normalize_mac(prefix))
+        });
+        let driver_ok = self
+            .driver
+            .as_ref()
+            .is_none_or(|d| iface.driver.as_deref().is_some_and(|x| x.eq_ignore_ascii_case(d)));
+        mac_ok && driver_ok
+    }
+
+    fn describe(&self) -> Vec<String> {
+        let mut parts = Vec::new();
+        if let Some(prefix) = &self.mac_prefix {
+            parts.push(format!("MAC prefix '{}'", prefix));
+        }
+        if let Some(driver) = &self.driver {
+            parts.push(format!("driver '{}'", driver));
+        }
+        parts
+    }
+}
+
+/// sysfs 中发现的一块无线网卡
+#[derive(Debug, Clone)]
+pub struct WirelessInterface {
+    pub name: String,
+    pub phy: Option<String>,
+    pub mac: String,
+    pub driver: Option<String>,
+    /// phy 是否支持 AP 模式；None 表示无法判断
+    pub ap_capable: Option<bool>,
+}
+
+/// 列出 `<sysfs_root>/class/net` 下的所有无线网卡（按名称排序）
+///
+/// 有 `wireless` 目录或 `phy80211` 链接的网卡视为无线网卡。
+/// `ap_probe` 根据 phy 名称判断是否支持 AP 模式，测试时可以替换为假实现。
+pub fn list_wireless_interfaces(
+    sysfs_root: &Path,
+    ap_probe: impl Fn(&str) -> Option<bool>,
+) -> Vec<WirelessInterface> {
+    let Ok(entries) = std::fs::read_dir(sysfs_root.join("class/net")) else {
+        return Vec::new();
+    };
+
+    let mut interfaces: Vec<WirelessInterface> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let dir = entry.path();
+            let phy = read_phy_name(&dir);
+            if !dir.join("wireless").exists() && phy.is_none() {
+                return None;
+            }
+            let ap_capable = phy.as_deref().and_then(&ap_probe);
+            Some(WirelessInterface {
+                name: entry.file_name().to_string_lossy().into_owned(),
+                mac: read_trimmed(&dir.join("address")).unwrap_or_default(),
+                driver: link_basename(&dir.join("device/driver")),
+                phy,
+                ap_capable,
+            })
+        })
+        .collect();
+    interfaces.sort_by(|a, b| a.name.cmp(&b.name));
+    interfaces
+}
+
+/// 按匹配条件和 AP 能力挑选网卡，返回选中的网卡和选择理由
+///
+/// 优先级：不在 `avoid` 中 > 支持 AP > 无法判断 > 不支持 AP；相同时取名称最小的。
+/// `avoid` 用于双射频模式下让 STA 避开已经分配给 AP 的网卡。
+pub fn select_interface<'a>(
+    candidates: &'a [WirelessInterface],
+    matcher: &InterfaceMatcher,
+    avoid: &[&str],
+) -> Result<(&'a WirelessInterface, String)> {
+    if candidates.is_empty() {
+        return Err(anyhow!("no wireless interface found"));
+    }
+
+    let matched: Vec<&WirelessInterface> = candidates.iter().filter(|i| matcher.matches(i)).collect();
+    let criteria = matcher.describe();
+    if matched.is_empty() {
+        return Err(anyhow!(
+            "no wireless interface matches {} (found: {})",
+            criteria.join(" and "),
+            candidates.iter().map(summary).collect::<Vec<_>>().join("; ")
+        ));
+    }
+
+    let rank = |i: &WirelessInterface| {
+        let ap_rank = match i.ap_capable {
+            Some(true) => 0,
+            None => 1,
+            Some(false) => 2,
+        };
+        (avoid.contains(&i.name.as_str()), ap_rank)
+    };
+    // candidates 已按名称排序，min_by_key 在并列时保留第一个
+    let chosen = matched.iter().copied().min_by_key(|i| rank(i)).expect("matched is not empty");
+
+    let mut reasons = Vec::new();
+    if !avoid.is_empty() {
+        if avoid.contains(&chosen.name.as_str()) {
+            reasons.push(format!("no candidate other than {}", avoid.join(", ")));
+        } else {
+            reasons.push(format!("not {}", avoid.join(", ")));
+        }
+    }
+    if !criteria.is_empty() {
+        reasons.push(format!("matches {}", criteria.join(" and ")));
+    }
+    reasons.push(
+        match chosen.ap_capable {
+            Some(true) => "supports AP mode",
+            None => "AP support unknown",
+            Some(false) => "no candidate supports AP mode",
+        }
+        .to_string(),
+    );
+    if matched.len() > 1 {
+        reasons.push(format!(
+            "first of {} candidates: {}",
+            matched.len(),
+            matched.iter().map(|i| i.name.as_str()).collect::<Vec<_>>().join(", ")
+        ));
+    } else {
+        reasons.push("only candidate".to_string());
+    }
+
+    Ok((chosen, reasons.join("; ")))
+}
+
+/// 把 `interface_name`、`ap_interface`、`sta_interface` 中的 `"auto"` 替换成检测到的网卡名
+///
+/// `sta_interface = "auto"` 会尽量避开 AP 所用的网卡，以便启用双射频模式。
+pub fn resolve_interfaces(ap: &mut ApConfig) -> Result<()> {
+    let is_auto = |v: Option<&str>| v == Some(AUTO_INTERFACE);
+    if !is_auto(Some(&ap.interface_name))
+        && !is_auto(ap.ap_interface.as_deref())
+        && !is_auto(ap.sta_interface.as_deref())
+    {
+        return Ok(());
+    }
+
+    let root = Path::new(&ap.sysfs_root).to_path_buf();
+    let candidates = list_wireless_interfaces(&root, phy_supports_ap);
+    let matcher = InterfaceMatcher::from_ap_config(ap);
+    let pick = |key: &str, avoid: &[&str]| -> Result<String> {
+        let (chosen, reason) = select_interface(&candidates, &matcher, avoid).map_err(|e| {
+            anyhow!("{} = \"auto\": {} under {}", key, e, root.join("class/net").display())
+        })?;
+        tracing::info!("📶 Auto-selected {} {}: {}", key, summary(chosen), reason);
+        Ok(chosen.name.clone())
+    };
+
+    if is_auto(Some(&ap.interface_name)) {
+        ap.interface_name = pick("interface_name", &[])?;
+    }
+    if is_auto(ap.ap_interface.as_deref()) {
+        ap.ap_interface = Some(pick("ap_interface", &[])?);
+    }
+    if is_auto(ap.sta_interface.as_deref()) {
+        let ap_interface = ap.ap_interface().to_string();
+        ap.sta_interface = Some(pick("sta_interface", &[&ap_interface])?);
+    }
+    Ok(())
+}
+
+/// 通过 `iw phy <phy> info`（nl80211）判断是否支持 AP 模式，无法判断时返回 None
+pub fn phy_supports_ap(phy: &str) -> Option<bool> {
+    let output = std::process::Command::new("iw")
+        .args(["phy", phy, "info"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let text = String::from_utf8_lossy(&output.stdout);
+    Some(parse_supported_modes(&text).iter().any(|m| m == "AP"))
+}
+
+/// 解析 `iw phy info` 中 "Supported interface modes:" 段落
+fn parse_supported_modes(info: &str) -> Vec<String> {
+    let mut modes = Vec::new();
+    let mut in_section = false;
+    for line in info.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with("Supported interface modes:") {
+            in_section = true;
+            continue;
+        }
+        if in_section {
+            match trimmed.strip_prefix("* ") {
+                Some(mode) => modes.push(mode.trim().to_string()),
+                None => break,
+            }
+        }
+    }
+    modes
+}
+
+/// 读取网卡的 phy 名称：真实 sysfs 中 `phy80211` 是指向 phy 目录的链接，
+/// 目录中的 `name` 文件即 phy 名称；退而求其次使用链接目标的文件名
+pub fn read_phy_name(iface_dir: &Path) -> Option<String> {
+    let link = iface_dir.join("phy80211");
+    read_trimmed(&link.join("name")).or_else(|| link_basename(&link))
+}
+
+fn summary(i: &WirelessInterface) -> String {
+    format!(
+        "{} (phy {}, driver {}, MAC {})",
+        i.name,
+        i.phy.as_deref().unwrap_or("?"),
+        i.driver.as_deref().unwrap_or("?"),
+        if i.mac.is_empty() { "?" } else { &i.mac }
+    )
+}
+
+fn read_trimmed(path: &Path) -> Option<String> {
+    std::fs::read_to_string(path)
+        .ok()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
+
+fn link_basename(path: &Path) -> Option<String> {
+    std::fs::read_link(path)
+        .ok()?
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+}
+
+fn normalize_mac(mac: &str) -> String {
+    mac.to_ascii_lowercase().replace('-', ":")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+    use std::path::PathBuf;
+
+    /// 在临时目录中按真实 sysfs 的布局创建一块网卡；`phy` 为 None 时是有线网卡
+    fn add_interface(root: &Path, name: &str, phy: Option<&str>, mac: &str, driver: &str) {
+        let dir = root.join("class/net").join(name);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("address"), format!("{}\n", mac)).unwrap();
+
+        let driver_dir = root.join("bus/drivers").join(driver);
+        std::fs::create_dir_all(&driver_dir).unwrap();
+        let device = root.join("devices").join(name);
+        std::fs::create_dir_all(&device).unwrap();
+        symlink(&driver_dir, device.join("driver")).unwrap();
+        symlink(&device, dir.join("device")).unwrap();
+
+        if let Some(phy) = phy {
+            let phy_dir = root.join("class/ieee80211").join(phy);
+            std::fs::create_dir_all(&phy_dir).unwrap();
+            std::fs::write(phy_dir.join("name"), format!("{}\n", phy)).unwrap();
+            symlink(&phy_dir, dir.join("phy80211")).unwrap();
+        }
+    }
+
+    /// 双射频网关：板载 brcmfmac（不支持 AP）和 USB 的 mt7601u（支持 AP），再加一块有线网卡
+    fn dual_radio_sysfs() -> (tempfile::TempDir, PathBuf) {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().to_path_buf();
+        add_interface(&root, "eth0", None, "b8:27:eb:00:00:01", "smsc95xx");
+        add_interface(&root, "wlan0", Some("phy0"), "B8:27:EB:12:34:56", "brcmfmac");
+        add_interface(&root, "wlan1", Some("phy1"), "00:0f:00:aa:bb:cc", "mt7601u");
+        (tmp, root)
+    }
+
+    fn ap_probe(phy: &str) -> Option<bool> {
+        match phy {
+            "phy0" => Some(false),
+            "phy1" => Some(true),
+            _ => None,
+        }
+    }
+
+    fn names(interfaces: &[WirelessInterface]) -> Vec<&str> {
+        interfaces.iter().map(|i| i.name.as_str()).collect()
+    }
+
+    #[test]
+    fn lists_only_wireless_interfaces() {
+        let (_tmp, root) = dual_radio_sysfs();
+        let interfaces = list_wireless_interfaces(&root, ap_probe);
+        assert_eq!(names(&interfaces), ["wlan0", "wlan1"]);
+        let wlan0 = &interfaces[0];
+        assert_eq!(wlan0.phy.as_deref(), Some("phy0"));
+        assert_eq!(wlan0.mac, "B8:27:EB:12:34:56");
+        assert_eq!(wlan0.driver.as_deref(), Some("brcmfmac"));
+        assert_eq!(wlan0.ap_capable, Some(false));
+
+        assert!(list_wireless_interfaces(&root.join("missing"), ap_probe).is_empty());
+    }
+
+    #[test]
+    fn auto_select_prefers_ap_capable() {
+        let (_tmp, root) = dual_radio_sysfs();
+        let interfaces = list_wireless_interfaces(&root, ap_probe);
+        let (chosen, reason) = select_interface(&interfaces, &InterfaceMatcher::default(), &[]).unwrap();
+        assert_eq!(chosen.name, "wlan1");
+        assert!(reason.contains("supports AP mode"), "{}", reason);
+
+        // 都无法判断时取名称最小的
+        let interfaces = list_wireless_interfaces(&root, |_| None);
+        let (chosen, _) = select_interface(&interfaces, &InterfaceMatcher::default(), &[]).unwrap();
+        assert_eq!(chosen.name, "wlan0");
+    }
+
+    #[test]
+    fn matchers_filter_candidates() {
+        let (_tmp, root) = dual_radio_sysfs();
+        let interfaces = list_wireless_interfaces(&root, ap_probe);
+
+        let by_mac = InterfaceMatcher {
+            mac_prefix: Some("b8-27-eb".to_string()),
+            driver: None,
+        };
+        let (chosen, reason) = select_interface(&interfaces, &by_mac, &[]).unwrap();
+        assert_eq!(chosen.name, "wlan0");
+        assert!(reason.contains("MAC prefix 'b8-27-eb'"), "{}", reason);
+
+        let by_driver = InterfaceMatcher {
+            mac_prefix: None,
+            driver: Some("MT7601U".to_string()),
+        };
+        assert_eq!(select_interface(&interfaces, &by_driver, &[]).unwrap().0.name, "wlan1");
+
+        let both = InterfaceMatcher {
+            mac_prefix: Some("b8:27:eb".to_string()),
+            driver: Some("mt7601u".to_string()),
+        };
+        let err = select_interface(&interfaces, &both, &[]).unwrap_err().to_string();
+        assert!(err.starts_with("no wireless interface matches MAC prefix 'b8:27:eb' and driver 'mt7601u'"), "{}", err);
+        assert!(err.contains("wlan0 (phy phy0, driver brcmfmac"), "{}", err);
+    }
+
+    #[test]
+    fn no_interfaces_is_an_error() {
+        let tmp = tempfile::tempdir().unwrap();
+        add_interface(tmp.path(), "eth0", None, "b8:27:eb:00:00:01", "smsc95xx");
+        let interfaces = list_wireless_interfaces(tmp.path(), ap_probe);
+        let err = select_interface(&interfaces, &InterfaceMatcher::default(), &[]).unwrap_err();
+        assert_eq!(err.to_string(), "no wireless interface found");
+    }
+
+    #[test]
+    fn dual_radio_sta_avoids_ap_interface() {
+        let (_tmp, root) = dual_radio_sysfs();
+        let interfaces = list_wireless_interfaces(&root, ap_probe);
+        let matcher = InterfaceMatcher::default();
+        let (ap, _) = select_interface(&interfaces, &matcher, &[]).unwrap();
+        let (sta, reason) = select_interface(&interfaces, &matcher, &[&ap.name]).unwrap();
+        assert_eq!((ap.name.as_str(), sta.name.as_str()), ("wlan1", "wlan0"));
+        assert!(reason.starts_with("not wlan1"), "{}", reason);
+
+        // 只有一块网卡时 STA 和 AP 共用
+        let only_ap: Vec<WirelessInterface> = interfaces.into_iter().filter(|i| i.name == "wlan1").collect();
+        let (sta, reason) = select_interface(&only_ap, &matcher, &["wlan1"]).unwrap();
+        assert_eq!(sta.name, "wlan1");
+        assert!(reason.starts_with("no candidate other than wlan1"), "{}", reason);
+    }
+
+    #[test]
+    fn parses_iw_supported_modes() {
+        let info = "Wiphy phy0\n\tSupported interface modes:\n\t\t * IBSS\n\t\t * managed\n\t\t * AP\n\tBand 1:\n";
+        assert_eq!(parse_supported_modes(info), ["IBSS", "managed", "AP"]);
+    }
+}
@@ -1,15 +1,41 @@
 mod backend;
+mod channel;
+mod charset;
+mod cli;
 mod config;
+mod config_layers;
+mod doctor;
+mod interface;
+mod monitor;
 mod structs;
 mod web_server;
 mod embed;
+mod error;
+mod events;
+mod hostapd;
+mod identity;
+mod psk;
+mod qr;
+mod rpc;
+mod session;
+mod shutdown;
+mod systemd;
 mod traits;
+mod trigger;
+mod validation;
 
 #[cfg(feature = "audio")]
 mod audio;
 
+#[cfg(feature = "dbus")]
+mod dbus;
+
 use anyhow::Result;
 use backend::WpaCtrlBackend;
+use clap::{CommandFactory, Parser};
+use cli::{Cli, Command, RunArgs};
+use config::AppConfig;
+use session::SessionOutcome;
 use std::sync::Arc;
 
 #[tokio::main]
@@ -19,32 +45,125 @@ async fn main() -> Result<()> {
         .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
         .init();
 
-    tracing::info!("🚀 Starting provisioner with wpa_ctrl backend...");
+    let cli = Cli::parse();
+    let run_flags = cli.run.if_unconfigured || cli.run.force;
+    if run_flags && !matches!(cli.command, None | Some(Command::Run(_))) {
+        Cli::command()
+            .error(
+                clap::error::ErrorKind::ArgumentConflict,
+                "--if-unconfigured and --force only apply to `run`",
+            )
+            .exit();
+    }
+    let config_path = cli.config.as_deref();
 
-    // 创建后端实例
-    let backend = Arc::new(WpaCtrlBackend::new()?);
-
-    // 执行 TDM 启动序列：扫描 -> 启动 AP
-    tracing::info!("📡 Executing initial scan and starting AP...");
-    let initial_networks = match backend.setup_and_scan().await {
-        Ok(networks) => {
-            tracing::info!(
-                "✅ Initial scan complete, found {} networks. AP started.",
-                networks.len()
-            );
-            networks
-        }
-        Err(e) => {
-            tracing::error!("❌ Failed to scan or start AP: {}. Exiting.", e);
+    // 这两个子命令需要自己处理配置错误
+    match cli.command {
+        Some(Command::PrintConfig) => return cli::print_config(config_path),
+        Some(Command::CheckConfig) => return cli::check_config(config_path),
+        _ => {}
+    }
+
+    // 加载配置
+    let (mut app_config, layers) = config::load_app_config(config_path)?;
+    tracing::info!("📄 Loaded configuration from: {}", layers.sources().join(" -> "));
+
+    // interface_name = "auto" 时检测无线网卡；doctor 需要在检测失败时继续给出报告
+    if let Err(e) = interface::resolve_interfaces(&mut app_config.ap) {
+        if !matches!(cli.command, Some(Command::Doctor)) {
             return Err(e);
         }
-    };
+        tracing::warn!("{:#}", e);
+    }
+
+    // 按模板生成 SSID，生成 random/derived 密码（需要在确定 AP 网卡之后）；
+    // 只有会启动 AP 或输出 AP 身份的子命令需要，doctor 把失败作为一项检查报告
+    let command = cli.command.unwrap_or(Command::Run(RunArgs::default()));
+    if matches!(
+        command,
+        Command::Run(_) | Command::Monitor | Command::Ap { .. } | Command::Identity | Command::Qr { .. }
+    ) {
+        identity::resolve_identity(&mut app_config.ap)?;
+    }
 
-    // 启动 Web 服务器
-    if let Err(e) = web_server::run_server(backend, initial_networks).await {
-        tracing::error!("❌ Web server failed: {}", e);
+    match command {
+        Command::Run(args) => run(&app_config, cli.run.merge(args)).await,
+        Command::Scan { json, restart } => cli::scan(&app_config, json, restart).await,
+        Command::Connect {
+            ssid,
+            ssid_hex,
+            password,
+            restart,
+        } => cli::connect(&app_config, ssid, ssid_hex, password, restart).await,
+        Command::Monitor => monitor::monitor(&app_config).await,
+        Command::Status => cli::status(&app_config).await,
+        Command::Ap { action } => cli::ap(&app_config, action).await,
+        Command::Forget => cli::forget(&app_config).await,
+        Command::Doctor => cli::doctor(&app_config),
+        Command::Identity => cli::identity(&app_config),
+        Command::Qr { format, output } => cli::qr(&app_config, format, output),
+        Command::PrintConfig | Command::CheckConfig => unreachable!("handled above"),
+    }
+}
+
+/// 完整的配网流程：扫描 -> 启动 AP -> 运行 Web 服务
+async fn run(app_config: &AppConfig, args: RunArgs) -> Result<()> {
+    // 只在需要时配网：先用已保存的网络联网
+    let if_unconfigured = args.if_unconfigured || app_config.provisioning.if_unconfigured;
+    shutdown::listen()?;
+    if args.force {
+        tracing::info!("🔧 Forced provisioning requested.");
+    } else if if_unconfigured {
+        let grace = app_config.provisioning.grace_period;
+        tracing::info!("🔎 Trying saved networks for {}s before provisioning...", grace.as_secs());
+        let backend = Arc::new(WpaCtrlBackend::with_saved_networks(app_config).await?);
+        let _systemd = systemd::spawn(&backend);
+        let online = tokio::select! {
+            online = backend.wait_until_online(grace) => online,
+            signal = shutdown::requested() => exit(signal.exit_code()),
+        };
+        match online {
+            Ok(true) => {
+                tracing::info!("✅ Device is already online, provisioning not needed.");
+                return Ok(());
+            }
+            Ok(false) => tracing::info!("Not online after {}s, starting provisioning.", grace.as_secs()),
+            Err(e) => tracing::warn!("Failed to check saved networks: {:#}. Starting provisioning.", e),
+        }
+    }
+
+    tracing::info!("🚀 Starting provisioner with wpa_ctrl backend...");
+    cli::print_identity(&app_config.ap);
+
+    // 预检：只记录问题，不阻止启动（上次遗留的 dnsmasq 等会在启动清理时被杀掉）
+    let report = doctor::run_checks(app_config);
+    report.log();
+    if report.has_failures() {
+        tracing::warn!("⚠️ Preflight checks found problems; run `provisioner doctor` for fix hints.");
+    }
+
+    // 创建后端实例
+    let backend = Arc::new(WpaCtrlBackend::new(app_config).await?);
+    let _systemd = systemd::spawn(&backend);
+    rpc::spawn_server(&app_config.control, backend.clone(), None)?;
+    #[cfg(feature = "dbus")]
+    let _dbus = dbus::serve(&app_config.dbus, backend.clone(), None).await?;
+    let outcome = session::run_session(backend, &app_config.provisioning).await;
+    shutdown::remove_sockets([&app_config.control.socket_path]);
+    match outcome? {
+        SessionOutcome::Provisioned => {
+            println!("Provisioning complete. Shutting down application.");
+        }
+        SessionOutcome::TimedOut(code) | SessionOutcome::Stopped(code) | SessionOutcome::Shutdown(code) => exit(code),
+        SessionOutcome::ServerStopped => {}
     }
 
     tracing::info!("🛑 Shutting down.");
     Ok(())
 }
+
+/// 以指定的退出码结束进程
+fn exit(code: i32) -> ! {
+    tracing::info!("🛑 Shutting down (exit code {}).", code);
+    std::process::exit(code);
+}
@@ -0,0 +1,306 @@
+use crate::backend::WpaCtrlBackend;
+use crate::config::{AppConfig, MonitorConfig};
+use crate::rpc;
+use crate::session::{self, SessionOutcome};
+use crate::shutdown;
+use crate::trigger::Triggers;
+use anyhow::Result;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+/// 链路状态的变化
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkEvent {
+    /// 已连上网络
+    Up,
+    /// 断开，或关联/认证失败
+    Down,
+}
+
+/// 把 wpa_supplicant 的事件消息转换成链路状态变化，无关的消息返回 None
+pub fn parse_link_event(message: &str) -> Option<LinkEvent> {
+    // 去掉 `<3>` 这样的优先级前缀
+    let body = match message.strip_prefix('<') {
+        Some(rest) => rest.split_once('>').map_or(rest, |(_, body)| body),
+        None => message,
+    };
+    let name = body.split_whitespace().next()?;
+    match name {
+        "CTRL-EVENT-CONNECTED" => Some(LinkEvent::Up),
+        "CTRL-EVENT-DISCONNECTED"
+        | "CTRL-EVENT-SSID-TEMP-DISABLED"
+        | "CTRL-EVENT-ASSOC-REJECT"
+        | "CTRL-EVENT-AUTH-REJECT"
+        | "CTRL-EVENT-NETWORK-NOT-FOUND" => Some(LinkEvent::Down),
+        _ => None,
+    }
+}
+
+/// 看门狗结束的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchdogOutcome {
+    /// 持续断线超过 `fallback_after`，应重新配网
+    Fallback,
+    /// 事件源关闭（wpa_supplicant 退出）
+    EventsClosed,
+}
+
+/// 断线看门狗：持续断线超过 `fallback_after` 时返回 `Fallback`
+///
+/// 断线计时从第一次断开开始；期间重新连上后需要保持 `stable_after` 才清除计时，
+/// 在此之前再次断开会沿用原来的起点，因此反复掉线也会被计入。
+/// 只使用 tokio 的时钟，可以在暂停的时钟下测试。
+pub async fn watch_link(
+    config: &MonitorConfig,
+    initial: LinkEvent,
+    events: &mut mpsc::Receiver<LinkEvent>,
+) -> WatchdogOutcome {
+    let mut down_since = (initial == LinkEvent::Down).then(Instant::now);
+    // 断线计时中重新连上的时间
+    let mut up_since: Option<Instant> = None;
+
+    loop {
+        let deadline = match (down_since, up_since) {
+            (Some(_), Some(up)) => Some(up + config.stable_after),
+            (Some(down), None) => Some(down + config.fallback_after),
+            (None, _) => None,
+        };
+        let expired = async {
+            match deadline {
+                Some(deadline) => tokio::time::sleep_until(deadline).await,
+                None => std::future::pending().await,
+            }
+        };
+
+        tokio::select! {
+            event = events.recv() => match event {
+                Some(LinkEvent::Down) => {
+                    up_since = None;
+                    if down_since.is_none() {
+                        tracing::info!("Link down, fallback to provisioning in {}s", config.fallback_after.as_secs());
+                        down_since = Some(Instant::now());
+                    }
+                }
+                Some(LinkEvent::Up) => {
+                    if down_since.is_some() && up_since.is_none() {
+                        up_since = Some(Instant::now());
+                    }
+                }
+                None => return WatchdogOutcome::EventsClosed,
+            },
+            () = expired => {
+                if up_since.is_some() {
+                    tracing::info!("Link stable again.");
+                    down_since = None;
+                    up_since = None;
+                } else {
+                    return WatchdogOutcome::Fallback;
+                }
+            }
+        }
+    }
+}
+
+/// `monitor`：常驻运行，断线时间过长或收到外部触发时重新进入配网，配网完成后继续监控
+///
+/// 整个过程沿用同一个 wpa_supplicant（配网得到的新网络可能没有写入配置文件），
+/// 只有在它退出时才重新用已保存的网络启动。
+pub async fn monitor(app_config: &AppConfig) -> Result<()> {
+    shutdown::listen()?;
+    let mut triggers = Triggers::spawn(&app_config.trigger)?;
+    let backend = Arc::new(WpaCtrlBackend::with_saved_networks(app_config).await?);
+    let _systemd = crate::systemd::spawn(&backend);
+    rpc::spawn_server(&app_config.control, backend.clone(), Some(triggers.handle()))?;
+    #[cfg(feature = "dbus")]
+    let _dbus = crate::dbus::serve(&app_config.dbus, backend.clone(), Some(triggers.handle())).await?;
+    // 控制接口已经可用：常驻服务此时就绪，不必等到联网或第一次配网
+    crate::systemd::notify_ready();
+    let online = wait_online(&backend, app_config).await;
+    // 启动时没有任何已保存的网络就直接配网；之后即使没有网络也先等满断线时间，
+    // 避免配网超时后马上又开启 AP
+    let mut provision = !online && backend.saved_network_count().await.unwrap_or(0) == 0;
+
+    loop {
+        if provision {
+            tracing::warn!("📴 Going back to provisioning.");
+            backend.disable_saved_networks().await?;
+            match session::run_session(backend.clone(), &app_config.provisioning).await {
+                Ok(SessionOutcome::Provisioned) => tracing::info!("✅ Re-provisioned, back to monitoring."),
+                Ok(SessionOutcome::TimedOut(_)) => tracing::info!("Provisioning timed out, back to monitoring."),
+                Ok(SessionOutcome::Stopped(_)) => tracing::info!("Provisioning stopped, back to monitoring."),
+                Ok(SessionOutcome::ServerStopped) => tracing::warn!("Web server stopped, back to monitoring."),
+                Ok(SessionOutcome::Shutdown(code)) => exit(app_config, code),
+                Err(e) => tracing::error!("Provisioning failed: {:#}. Back to monitoring.", e),
+            }
+            if backend.is_ap_active() {
+                let _ = backend.stop_ap().await;
+            }
+            triggers.drain();
+        }
+
+        let initial = if backend.is_connected().await { LinkEvent::Up } else { LinkEvent::Down };
+        backend.set_link_state(initial == LinkEvent::Up);
+        tracing::info!("👀 Monitoring connection (currently {:?})...", initial);
+        let outcome = tokio::select! {
+            outcome = watch_backend(&backend, &app_config.monitor, initial) => outcome,
+            _ = triggers.next() => {
+                provision = true;
+                continue;
+            }
+            signal = shutdown::requested() => exit(app_config, signal.exit_code()),
+        };
+        match outcome {
+            Ok(WatchdogOutcome::Fallback) => provision = true,
+            Ok(WatchdogOutcome::EventsClosed) => {
+                tracing::warn!("Lost wpa_supplicant events, restarting it with saved networks.");
+                backend.restart_with_saved_networks().await?;
+                wait_online(&backend, app_config).await;
+                provision = false;
+            }
+            Err(e) => {
+                tracing::warn!("{:#}. Restarting wpa_supplicant with saved networks.", e);
+                backend.restart_with_saved_networks().await?;
+                wait_online(&backend, app_config).await;
+                provision = false;
+            }
+        }
+    }
+}
+
+/// 删除监听的 socket 文件后以指定的退出码结束进程
+fn exit(app_config: &AppConfig, code: i32) -> ! {
+    shutdown::remove_sockets([&app_config.trigger.socket_path, &app_config.control.socket_path]);
+    tracing::info!("🛑 Shutting down (exit code {}).", code);
+    std::process::exit(code);
+}
+
+/// 等待 `grace_period` 看已保存的网络能否联网；期间收到退出信号时直接退出
+async fn wait_online(backend: &WpaCtrlBackend, app_config: &AppConfig) -> bool {
+    tokio::select! {
+        online = backend.wait_until_online(app_config.provisioning.grace_period) => online.unwrap_or(false),
+        signal = shutdown::requested() => exit(app_config, signal.exit_code()),
+    }
+}
+
+/// 把 wpa_supplicant 的事件转换成链路事件并交给看门狗
+///
+/// 转发不单独起任务，被取消（例如收到配网触发）时事件订阅随之释放。
+async fn watch_backend(backend: &WpaCtrlBackend, config: &MonitorConfig, initial: LinkEvent) -> Result<WatchdogOutcome> {
+    let mut raw = backend.subscribe_wpa_events()?;
+    let (sender, mut events) = mpsc::channel(32);
+    let forward = async move {
+        while let Some(message) = raw.recv().await {
+            tracing::debug!("wpa event: {}", message);
+            let Some(event) = parse_link_event(&message) else {
+                continue;
+            };
+            backend.set_link_state(event == LinkEvent::Up);
+            if sender.send(event).await.is_err() {
+                break;
+            }
+        }
+    };
+    tokio::select! {
+        outcome = watch_link(config, initial, &mut events) => Ok(outcome),
+        () = forward => Ok(WatchdogOutcome::EventsClosed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn config() -> MonitorConfig {
+        MonitorConfig {
+            fallback_after: Duration::from_secs(300),
+            stable_after: Duration::from_secs(60),
+        }
+    }
+
+    /// 按 `(等待秒数, 事件)` 依次发送事件，发完后再过 `linger` 秒关闭通道
+    fn script(steps: Vec<(u64, LinkEvent)>, linger: u64) -> mpsc::Receiver<LinkEvent> {
+        let (sender, receiver) = mpsc::channel(8);
+        tokio::spawn(async move {
+            for (delay, event) in steps {
+                tokio::time::sleep(Duration::from_secs(delay)).await;
+                sender.send(event).await.unwrap();
+            }
+            tokio::time::sleep(Duration::from_secs(linger)).await;
+        });
+        receiver
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn short_flap_does_not_fall_back() {
+        let start = Instant::now();
+        let mut events = script(vec![(10, LinkEvent::Down), (20, LinkEvent::Up)], 1000);
+        let outcome = watch_link(&config(), LinkEvent::Up, &mut events).await;
+        assert_eq!(outcome, WatchdogOutcome::EventsClosed);
+        assert_eq!(start.elapsed(), Duration::from_secs(1030));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn sustained_disconnect_falls_back() {
+        let start = Instant::now();
+        let mut events = script(vec![(10, LinkEvent::Down)], 1000);
+        let outcome = watch_link(&config(), LinkEvent::Up, &mut events).await;
+        assert_eq!(outcome, WatchdogOutcome::Fallback);
+        assert_eq!(start.elapsed(), Duration::from_secs(310));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn initially_down_falls_back() {
+        let start = Instant::now();
+        let mut events = script(Vec::new(), 1000);
+        let outcome = watch_link(&config(), LinkEvent::Down, &mut events).await;
+        assert_eq!(outcome, WatchdogOutcome::Fallback);
+        assert_eq!(start.elapsed(), Duration::from_secs(300));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn repeated_flaps_keep_the_original_deadline() {
+        // 每次只恢复 30 秒（不到 stable_after），断线计时从第一次断开算起
+        let start = Instant::now();
+        let flaps = (0..10).flat_map(|_| [(30, LinkEvent::Up), (30, LinkEvent::Down)]);
+        let steps = std::iter::once((10, LinkEvent::Down)).chain(flaps).collect();
+        let mut events = script(steps, 1000);
+        let outcome = watch_link(&config(), LinkEvent::Up, &mut events).await;
+        assert_eq!(outcome, WatchdogOutcome::Fallback);
+        assert_eq!(start.elapsed(), Duration::from_secs(310));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stable_link_clears_the_deadline() {
+        // 恢复满 60 秒后计时清零，下一次断线重新等满 300 秒
+        let start = Instant::now();
+        let mut events = script(vec![(10, LinkEvent::Down), (200, LinkEvent::Up), (100, LinkEvent::Down)], 1000);
+        let outcome = watch_link(&config(), LinkEvent::Up, &mut events).await;
+        assert_eq!(outcome, WatchdogOutcome::Fallback);
+        assert_eq!(start.elapsed(), Duration::from_secs(610));
+    }
+
+    #[test]
+    fn parses_link_events() {
+        assert_eq!(
+            parse_link_event("<3>CTRL-EVENT-CONNECTED - Connection to 00:11:22:33:44:55 completed [id=0 id_str=]"),
+            Some(LinkEvent::Up)
+        );
+        assert_eq!(
+            parse_link_event("<3>CTRL-EVENT-DISCONNECTED bssid=00:11:22:33:44:55 reason=3 locally_generated=1"),
+            Some(LinkEvent::Down)
+        );
+        assert_eq!(
+            parse_link_event("CTRL-EVENT-SSID-TEMP-DISABLED id=0 ssid=\"Home\" auth_failures=1 duration=10 reason=WRONG_KEY"),
+            Some(LinkEvent::Down)
+        );
+        assert_eq!(parse_link_event("<3>CTRL-EVENT-ASSOC-REJECT status_code=1"), Some(LinkEvent::Down));
+        assert_eq!(parse_link_event("<3>CTRL-EVENT-AUTH-REJECT 00:11:22:33:44:55"), Some(LinkEvent::Down));
+        assert_eq!(parse_link_event("<3>CTRL-EVENT-NETWORK-NOT-FOUND"), Some(LinkEvent::Down));
+        assert_eq!(parse_link_event("<3>CTRL-EVENT-SCAN-RESULTS"), None);
+        assert_eq!(parse_link_event("<3>CTRL-EVENT-CONNECTED-EXTRA"), None);
+        assert_eq!(parse_link_event(""), None);
+        assert_eq!(parse_link_event("<3>"), None);
+    }
+}
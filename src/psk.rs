@@ -0,0 +1,84 @@
+use pbkdf2::pbkdf2_hmac;
+use sha1::Sha1;
+
+/// IEEE 802.11i 规定的 PBKDF2 迭代次数
+const PSK_ITERATIONS: u32 = 4096;
+
+/// 由 SSID 原始字节和密码短语派生 256 位 PSK
+///
+/// 与 `wpa_passphrase` 的算法一致：PBKDF2-HMAC-SHA1(passphrase, ssid, 4096, 32)。
+pub fn derive_psk(ssid: &[u8], passphrase: &str) -> [u8; 32] {
+    let mut psk = [0u8; 32];
+    pbkdf2_hmac::<Sha1>(passphrase.as_bytes(), ssid, PSK_ITERATIONS, &mut psk);
+    psk
+}
+
+/// 派生 PSK 并编码为 wpa_supplicant `psk` 字段接受的 64 位十六进制字符串
+pub fn derive_psk_hex(ssid: &[u8], passphrase: &str) -> String {
+    hex::encode(derive_psk(ssid, passphrase))
+}
+
+/// 将字符串编码为 wpa_supplicant 的 `P"..."` 格式（printf 风格转义）
+///
+/// 用于必须传递明文的字段（如 SAE 的 `sae_password`），
+/// 引号、反斜杠和非 ASCII 字节都会被正确转义。
+pub fn wpa_printf_quote(value: &[u8]) -> String {
+    let mut out = String::with_capacity(value.len() + 3);
+    out.push_str("P\"");
+    for &b in value {
+        match b {
+            b'"' => out.push_str("\\\""),
+            b'\\' => out.push_str("\\\\"),
+            b'\n' => out.push_str("\\n"),
+            b'\r' => out.push_str("\\r"),
+            b'\t' => out.push_str("\\t"),
+            0x20..=0x7e => out.push(b as char),
+            _ => out.push_str(&format!("\\x{:02x}", b)),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// IEEE 802.11i-2004 附录 H.4 的测试向量
+    #[test]
+    fn ieee_802_11i_vectors() {
+        assert_eq!(
+            derive_psk_hex(b"IEEE", "password"),
+            "f42c6fc52df0ebef9ebb4b90b38a5f902e83fe1b135a70e23aed762e9710a12e"
+        );
+        assert_eq!(
+            derive_psk_hex(b"ThisIsASSID", "ThisIsAPassword"),
+            "0dc0d6eb90555ed6419756b9a15ec3e3209b63df707dd508d14581f8982721af"
+        );
+        assert_eq!(
+            derive_psk_hex(&[b'Z'; 32], &"a".repeat(32)),
+            "becb93866bb8c3832cb777c2f559807c8c59afcb6eae734885001300a981cc62"
+        );
+    }
+
+    #[test]
+    fn passphrase_length_boundaries() {
+        assert_eq!(
+            derive_psk_hex(b"Home", "12345678"),
+            "868d3c3ca7b80a0116d8d2303f8eec9bf64eb8b60bd942cef9fc6bfd48940d1e"
+        );
+        assert_eq!(
+            derive_psk_hex(b"Home", &"x".repeat(63)),
+            "d8f31053cc0006eecb56fdf47421aa99bafdf33de333c077ddd206e86225f05a"
+        );
+    }
+
+    #[test]
+    fn printf_quote_escapes() {
+        assert_eq!(wpa_printf_quote(b"plain pass"), r#"P"plain pass""#);
+        assert_eq!(wpa_printf_quote(br#"a"b\c"#), r#"P"a\"b\\c""#);
+        assert_eq!(wpa_printf_quote(b"tab\there\n"), r#"P"tab\there\n""#);
+        assert_eq!(wpa_printf_quote("密".as_bytes()), r#"P"\xe5\xaf\x86""#);
+        assert_eq!(wpa_printf_quote(&[0x00, 0x7f]), r#"P"\x00\x7f""#);
+    }
+}
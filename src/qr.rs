@@ -0,0 +1,222 @@
+use crate::config::ApConfig;
+use crate::hostapd::ApSecurity;
+use anyhow::{Context, Result, anyhow};
+use qrcode::render::{svg, unicode};
+use qrcode::{Color, EcLevel, QrCode};
+use serde::Deserialize;
+use std::path::Path;
+
+/// QR 码四周的空白（模块数，标准要求至少 4）
+const QUIET_ZONE: usize = 4;
+/// PNG 中每个模块的像素数
+const PNG_MODULE_PX: usize = 8;
+/// SVG 的最小边长（像素）
+const SVG_MIN_PX: u32 = 256;
+
+/// 二维码的输出格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum QrFormat {
+    /// 终端中显示的 Unicode 方块字符
+    Unicode,
+    #[default]
+    Svg,
+    Png,
+}
+
+impl QrFormat {
+    /// 按文件扩展名推断格式
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "svg" => Some(QrFormat::Svg),
+            "png" => Some(QrFormat::Png),
+            "txt" => Some(QrFormat::Unicode),
+            _ => None,
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            QrFormat::Unicode => "text/plain; charset=utf-8",
+            QrFormat::Svg => "image/svg+xml",
+            QrFormat::Png => "image/png",
+        }
+    }
+}
+
+/// 生成标准的 Wi-Fi 入网二维码内容：`WIFI:T:WPA;S:<ssid>;P:<psk>;;`
+///
+/// 开放网络使用 `T:nopass` 并省略密码；隐藏 SSID 追加 `H:true`。
+pub fn wifi_payload(ap: &ApConfig) -> String {
+    let mut payload = String::from("WIFI:");
+    if ap.security == ApSecurity::Open {
+        payload.push_str("T:nopass;");
+    } else {
+        payload.push_str("T:WPA;");
+    }
+    payload.push_str(&format!("S:{};", escape_field(&ap.ssid)));
+    if ap.security != ApSecurity::Open {
+        payload.push_str(&format!("P:{};", escape_field(&ap.psk)));
+    }
+    if ap.hostapd_hidden {
+        payload.push_str("H:true;");
+    }
+    payload.push(';');
+    payload
+}
+
+/// 转义 `WIFI:` 字段中有特殊含义的字符：`\ ; , : "`
+pub fn escape_field(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '\\' | ';' | ',' | ':' | '"') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// 为配网 AP 生成二维码
+pub fn ap_qr_code(ap: &ApConfig) -> Result<QrCode> {
+    QrCode::with_error_correction_level(wifi_payload(ap), EcLevel::M)
+        .map_err(|e| anyhow!("Failed to encode QR code: {}", e))
+}
+
+/// 按指定格式渲染
+pub fn render(code: &QrCode, format: QrFormat) -> Result<Vec<u8>> {
+    match format {
+        QrFormat::Unicode => Ok(render_unicode(code).into_bytes()),
+        QrFormat::Svg => Ok(render_svg(code).into_bytes()),
+        QrFormat::Png => render_png(code),
+    }
+}
+
+/// 每个字符表示上下两个模块，深色背景的终端下颜色需要反转才能被扫描
+pub fn render_unicode(code: &QrCode) -> String {
+    code.render::<unicode::Dense1x2>()
+        .dark_color(unicode::Dense1x2::Light)
+        .light_color(unicode::Dense1x2::Dark)
+        .quiet_zone(true)
+        .build()
+}
+
+pub fn render_svg(code: &QrCode) -> String {
+    code.render::<svg::Color>()
+        .min_dimensions(SVG_MIN_PX, SVG_MIN_PX)
+        .quiet_zone(true)
+        .build()
+}
+
+/// 8 位灰度 PNG，每个模块 `PNG_MODULE_PX` 像素
+pub fn render_png(code: &QrCode) -> Result<Vec<u8>> {
+    let modules = code.width();
+    let colors = code.to_colors();
+    let side = (modules + 2 * QUIET_ZONE) * PNG_MODULE_PX;
+
+    let mut pixels = vec![0xffu8; side * side];
+    for (i, color) in colors.iter().enumerate() {
+        if *color != Color::Dark {
+            continue;
+        }
+        let (mx, my) = (i % modules + QUIET_ZONE, i / modules + QUIET_ZONE);
+        for y in my * PNG_MODULE_PX..(my + 1) * PNG_MODULE_PX {
+            let row = y * side;
+            pixels[row + mx * PNG_MODULE_PX..row + (mx + 1) * PNG_MODULE_PX].fill(0);
+        }
+    }
+
+    let mut out = Vec::new();
+    let mut encoder = png::Encoder::new(&mut out, side as u32, side as u32);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().context("Failed to write PNG header")?;
+    writer.write_image_data(&pixels).context("Failed to write PNG data")?;
+    writer.finish().context("Failed to finish PNG")?;
+    Ok(out)
+}
+
+/// 写入文件（供墨水屏、LCD 显示程序读取），格式由扩展名决定
+///
+/// 先写临时文件再改名，避免显示程序读到写了一半的文件。
+pub fn write_qr_file(ap: &ApConfig, path: &Path) -> Result<()> {
+    let format = QrFormat::from_path(path)
+        .ok_or_else(|| anyhow!("{}: QR output must end in .png, .svg or .txt", path.display()))?;
+    let data = render(&ap_qr_code(ap)?, format)?;
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, data).with_context(|| format!("Failed to write {}", tmp.display()))?;
+    std::fs::rename(&tmp, path).with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_app_config;
+
+    fn ap_config(overlay: &str) -> ApConfig {
+        test_app_config(overlay).ap
+    }
+
+    #[test]
+    fn escapes_special_characters() {
+        assert_eq!(escape_field(r#"a\b;c,d:e"f"#), r#"a\\b\;c\,d\:e\"f"#);
+        assert_eq!(escape_field("回声 配网"), "回声 配网");
+    }
+
+    #[test]
+    fn wpa_payload() {
+        let mut ap = ap_config("");
+        ap.ssid = "Echo;mate".to_string();
+        ap.psk = r#"pass:"word""#.to_string();
+        assert_eq!(wifi_payload(&ap), r#"WIFI:T:WPA;S:Echo\;mate;P:pass\:\"word\";;"#);
+    }
+
+    #[test]
+    fn open_and_hidden_payloads() {
+        let ap = ap_config("[ap]\nap_security = \"open\"\nhostapd_hidden = true\n");
+        assert_eq!(wifi_payload(&ap), "WIFI:T:nopass;S:Echo-mate;H:true;;");
+
+        let ap = ap_config("[ap]\nap_security = \"sae\"\nap_psk = \"password123\"\nhostapd_hidden = true\n");
+        assert_eq!(wifi_payload(&ap), "WIFI:T:WPA;S:Echo-mate;P:password123;H:true;;");
+    }
+
+    #[test]
+    fn renders_all_formats() {
+        let code = ap_qr_code(&ap_config("")).unwrap();
+        let side = (code.width() + 2 * QUIET_ZONE) * PNG_MODULE_PX;
+
+        let unicode = render_unicode(&code);
+        assert_eq!(unicode.lines().count(), (code.width() + 2 * QUIET_ZONE).div_ceil(2));
+        assert!(unicode.contains('▀') || unicode.contains('▄'));
+
+        let svg = String::from_utf8(render(&code, QrFormat::Svg).unwrap()).unwrap();
+        assert!(svg.contains("<svg"), "{}", svg);
+        assert!(svg.trim_end().ends_with("</svg>"));
+
+        let png = render(&code, QrFormat::Png).unwrap();
+        let decoder = png::Decoder::new(std::io::Cursor::new(png));
+        let reader = decoder.read_info().unwrap();
+        let info = reader.info();
+        assert_eq!((info.width as usize, info.height as usize), (side, side));
+        assert_eq!(info.color_type, png::ColorType::Grayscale);
+    }
+
+    #[test]
+    fn format_from_extension() {
+        assert_eq!(QrFormat::from_path(Path::new("/run/qr.PNG")), Some(QrFormat::Png));
+        assert_eq!(QrFormat::from_path(Path::new("qr.svg")), Some(QrFormat::Svg));
+        assert_eq!(QrFormat::from_path(Path::new("qr.txt")), Some(QrFormat::Unicode));
+        assert_eq!(QrFormat::from_path(Path::new("qr.jpg")), None);
+    }
+
+    #[test]
+    fn writes_file_atomically() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("qr.png");
+        write_qr_file(&ap_config(""), &path).unwrap();
+        assert!(std::fs::read(&path).unwrap().starts_with(b"\x89PNG"));
+        assert!(!dir.path().join("qr.tmp").exists());
+        assert!(write_qr_file(&ap_config(""), &dir.path().join("qr.gif")).is_err());
+    }
+}
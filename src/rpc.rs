@@ -0,0 +1,404 @@
+use crate::backend::WpaCtrlBackend;
+use crate::config::ControlConfig;
+use crate::events::SequencedEvent;
+use crate::structs::ConnectionRequest;
+use crate::trigger::{self, TriggerHandle, TriggerSource};
+use crate::validation::{ValidationCode, ValidationError};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::UnixStream;
+use tokio::sync::mpsc;
+use tokio_stream::{Stream, StreamExt};
+use tokio_stream::wrappers::BroadcastStream;
+
+/// JSON-RPC 2.0 预定义的错误码
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INTERNAL_ERROR: i64 = -32603;
+/// 当前状态下不能执行（例如没有在配网时 `stop_provisioning`）
+const INVALID_STATE: i64 = -32000;
+
+/// 一个连接上排队等待写出的行数
+const OUTGOING_CAPACITY: usize = 64;
+
+/// JSON-RPC 请求；没有 `id` 的是通知，不回复
+#[derive(Debug, Deserialize)]
+struct RpcRequest {
+    #[serde(default)]
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+/// JSON-RPC 错误对象
+#[derive(Debug, Serialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<Value>,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+
+    fn invalid_params(e: serde_json::Error) -> Self {
+        Self::new(INVALID_PARAMS, format!("invalid params: {}", e))
+    }
+}
+
+impl From<ValidationError> for RpcError {
+    fn from(e: ValidationError) -> Self {
+        // 正在连接不是参数错误，和其它状态冲突一样使用 INVALID_STATE
+        if e.code == ValidationCode::Busy {
+            return Self::new(INVALID_STATE, e.message);
+        }
+        Self {
+            code: INVALID_PARAMS,
+            message: e.message,
+            data: Some(json!({ "code": e.code.as_str(), "field": e.field })),
+        }
+    }
+}
+
+/// 各连接共享的状态
+struct RpcContext {
+    backend: Arc<WpaCtrlBackend>,
+    /// `monitor` 模式下用于 `start_provisioning`；`run` 模式本身就在配网，没有触发入口
+    trigger: Option<TriggerHandle>,
+}
+
+/// 启动控制 socket（未配置 `socket_path` 时什么都不做）
+///
+/// 每行一个 JSON-RPC 2.0 请求，每个请求回复一行。与 Web 门户共用同一个后端，
+/// 连接请求同样在后台执行，结果通过 `subscribe` 的事件或 `status` 获取。
+pub fn spawn_server(config: &ControlConfig, backend: Arc<WpaCtrlBackend>, trigger: Option<TriggerHandle>) -> Result<()> {
+    let Some(path) = &config.socket_path else {
+        return Ok(());
+    };
+    let listener = trigger::bind_socket(path, config.socket_mode, config.socket_group.as_deref())?;
+    tracing::info!("🔌 JSON-RPC control socket listening on {}", path.display());
+
+    let context = Arc::new(RpcContext { backend, trigger });
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(handle_client(stream, context.clone()));
+                }
+                Err(e) => {
+                    tracing::warn!("Control socket accept failed: {}", e);
+                    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+/// 处理一个连接：按顺序执行请求；`subscribe` 之后事件通知与回复交错写出
+///
+/// 客户端关闭写方向后，已订阅的连接继续推送事件，直到写入失败。
+async fn handle_client(stream: UnixStream, context: Arc<RpcContext>) {
+    let (reader, mut writer) = stream.into_split();
+    let (outgoing, mut pending) = mpsc::channel::<String>(OUTGOING_CAPACITY);
+
+    let read = async move {
+        let mut lines = BufReader::new(reader).lines();
+        let mut subscription: Option<tokio::task::JoinHandle<()>> = None;
+        while let Ok(Some(line)) = lines.next_line().await {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let reply = match serde_json::from_str::<Value>(&line) {
+                Err(e) => Some(error_response(Value::Null, RpcError::new(PARSE_ERROR, e.to_string()))),
+                Ok(value) => match serde_json::from_value::<RpcRequest>(value) {
+                    Err(e) => Some(error_response(Value::Null, RpcError::new(INVALID_REQUEST, e.to_string()))),
+                    Ok(request) if request.method == "subscribe" => match subscribe(&context, request.params) {
+                        Err(e) => request.id.map(|id| error_response(id, e)),
+                        Ok(events) => {
+                            // 先写出回复再开始推送，客户端总是先收到订阅结果
+                            if let Some(id) = request.id
+                                && outgoing.send(response(id, Ok(json!({ "subscribed": true })))).await.is_err()
+                            {
+                                break;
+                            }
+                            forward_events(events, &outgoing, &mut subscription);
+                            None
+                        }
+                    },
+                    Ok(request) => {
+                        tracing::debug!(method = %request.method, "Handling control request");
+                        let result = dispatch(&context, &request.method, request.params).await;
+                        request.id.map(|id| response(id, result))
+                    }
+                },
+            };
+            if let Some(reply) = reply
+                && outgoing.send(reply).await.is_err()
+            {
+                break;
+            }
+        }
+    };
+    let write = async move {
+        while let Some(line) = pending.recv().await {
+            if writer.write_all(line.as_bytes()).await.is_err() {
+                break;
+            }
+        }
+    };
+    tokio::join!(read, write);
+}
+
+fn response(id: Value, result: Result<Value, RpcError>) -> String {
+    match result {
+        Ok(result) => line(json!({ "jsonrpc": "2.0", "id": id, "result": result })),
+        Err(error) => error_response(id, error),
+    }
+}
+
+fn error_response(id: Value, error: RpcError) -> String {
+    line(json!({ "jsonrpc": "2.0", "id": id, "error": error }))
+}
+
+fn line(value: Value) -> String {
+    let mut text = value.to_string();
+    text.push('\n');
+    text
+}
+
+/// 解析 `params`；没有参数时按空对象处理
+fn params<T: for<'de> Deserialize<'de>>(params: Value) -> Result<T, RpcError> {
+    let params = if params.is_null() { json!({}) } else { params };
+    serde_json::from_value(params).map_err(RpcError::invalid_params)
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ScanParams {
+    /// 重新扫描而不是返回缓存（AP 运行时仅双射频模式有效）
+    #[serde(default)]
+    refresh: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct SubscribeParams {
+    /// 从这个事件序号之后开始重放（与 SSE 的 `Last-Event-ID` 相同）
+    #[serde(default)]
+    last_event_id: Option<u64>,
+}
+
+async fn dispatch(context: &RpcContext, method: &str, raw_params: Value) -> Result<Value, RpcError> {
+    let backend = &context.backend;
+    match method {
+        "status" => Ok(status(backend).await),
+        "scan" => {
+            let ScanParams { refresh } = params(raw_params)?;
+            let networks = backend
+                .networks(refresh)
+                .await
+                .map_err(|e| RpcError::new(INTERNAL_ERROR, format!("Scan failed: {:#}", e)))?;
+            Ok(json!({ "networks": networks }))
+        }
+        "connect" => {
+            let request: ConnectionRequest = params(raw_params)?;
+            backend.start_connect(request)?;
+            Ok(json!({ "accepted": true }))
+        }
+        "start_provisioning" => {
+            trigger::start_provisioning(backend, context.trigger.as_ref(), TriggerSource::Rpc)
+                .map_err(|e| RpcError::new(INVALID_STATE, e.to_string()))?;
+            Ok(json!({ "accepted": true }))
+        }
+        "stop_provisioning" => {
+            backend
+                .request_stop()
+                .map_err(|e| RpcError::new(INVALID_STATE, e.to_string()))?;
+            Ok(json!({ "accepted": true }))
+        }
+        "saved_networks" => {
+            let networks = backend
+                .saved_networks()
+                .await
+                .map_err(|e| RpcError::new(INTERNAL_ERROR, format!("{:#}", e)))?;
+            Ok(json!({ "networks": networks }))
+        }
+        _ => Err(RpcError::new(METHOD_NOT_FOUND, format!("unknown method '{}'", method))),
+    }
+}
+
+/// `status`：设备状态、wpa_supplicant 的连接信息和最近一次连接结果
+async fn status(backend: &WpaCtrlBackend) -> Value {
+    let wpa = backend.status().await.unwrap_or_default();
+    let field = |key: &str| wpa.iter().find(|(k, _)| k == key).map(|(_, v)| v.clone());
+    json!({
+        "state": backend.state(),
+        "ap_active": backend.is_ap_active(),
+        "ap_ssid": backend.ap_config().ssid,
+        "dual_radio": backend.is_dual_radio(),
+        "wpa_state": field("wpa_state"),
+        "ssid": field("ssid"),
+        "ip_address": field("ip_address"),
+        "last_result": backend.last_result(),
+    })
+}
+
+/// `subscribe`：之后以 `event` 通知推送配网事件；重复订阅会替换之前的订阅
+fn subscribe(context: &RpcContext, raw_params: Value) -> Result<impl Stream<Item = SequencedEvent> + use<>, RpcError> {
+    let SubscribeParams { last_event_id } = params(raw_params)?;
+    let (replay, receiver) = context.backend.events().subscribe(last_event_id);
+    // 订阅者处理过慢导致的 Lagged 错误直接跳过
+    let live = BroadcastStream::new(receiver).filter_map(|res| res.ok());
+    Ok(tokio_stream::iter(replay).chain(live))
+}
+
+/// 在后台把事件写给客户端，替换之前的订阅
+fn forward_events(
+    events: impl Stream<Item = SequencedEvent> + Send + 'static,
+    outgoing: &mpsc::Sender<String>,
+    subscription: &mut Option<tokio::task::JoinHandle<()>>,
+) {
+    if let Some(previous) = subscription.take() {
+        previous.abort();
+    }
+    let outgoing = outgoing.clone();
+    *subscription = Some(tokio::spawn(async move {
+        let mut events = std::pin::pin!(events);
+        while let Some(seq) = events.next().await {
+            if outgoing.send(event_notification(&seq)).await.is_err() {
+                break;
+            }
+        }
+    }));
+}
+
+fn event_notification(seq: &SequencedEvent) -> String {
+    line(json!({
+        "jsonrpc": "2.0",
+        "method": "event",
+        "params": { "id": seq.id, "name": seq.event.name(), "event": seq.event },
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::ProvisionEvent;
+    use crate::validation::validate_connection_request;
+    use std::time::Duration;
+    use tokio::io::Lines;
+    use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+
+    /// 通过一对 Unix socket 连上 `handle_client`，返回客户端的读写两端
+    fn connect(backend: Arc<WpaCtrlBackend>) -> (Lines<BufReader<OwnedReadHalf>>, OwnedWriteHalf) {
+        let (client, server) = UnixStream::pair().unwrap();
+        tokio::spawn(handle_client(server, Arc::new(RpcContext { backend, trigger: None })));
+        let (reader, writer) = client.into_split();
+        (BufReader::new(reader).lines(), writer)
+    }
+
+    async fn next_reply(lines: &mut Lines<BufReader<OwnedReadHalf>>) -> Value {
+        let line = tokio::time::timeout(Duration::from_secs(5), lines.next_line())
+            .await
+            .expect("timed out waiting for a reply")
+            .unwrap()
+            .expect("connection closed");
+        serde_json::from_str(&line).unwrap()
+    }
+
+    #[test]
+    fn busy_maps_to_invalid_state() {
+        let error = RpcError::from(ValidationError::busy());
+        assert_eq!(error.code, INVALID_STATE);
+        assert_eq!(error.message, "a connection attempt is in progress");
+        assert!(error.data.is_none());
+    }
+
+    #[test]
+    fn validation_errors_map_to_invalid_params() {
+        let request = ConnectionRequest {
+            ssid: String::new(),
+            ssid_hex: None,
+            password: String::new(),
+        };
+        let error = RpcError::from(validate_connection_request(&request, &[]).unwrap_err());
+        assert_eq!(error.code, INVALID_PARAMS);
+        assert_eq!(error.data, Some(json!({ "code": "ssid_empty", "field": "ssid" })));
+    }
+
+    #[tokio::test]
+    async fn replies_line_by_line_with_error_codes() {
+        let backend = Arc::new(WpaCtrlBackend::detached(&crate::config::test_app_config("")));
+        let (mut lines, mut writer) = connect(backend);
+
+        // 一次写入多行：每行一个请求，通知（没有 id）不回复，空行忽略
+        writer
+            .write_all(
+                b"{\"jsonrpc\":\"2.0\",\"id\":7,\"method\":\"reboot\"}\n\
+                  not json\n\
+                  \n\
+                  {\"jsonrpc\":\"2.0\",\"method\":\"reboot\"}\n\
+                  {\"jsonrpc\":\"2.0\",\"id\":\"a\"}\n\
+                  {\"jsonrpc\":\"2.0\",\"id\":[1],\"method\":\"connect\",\"params\":{\"ssid\":\"\",\"password\":\"\"}}\n",
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            next_reply(&mut lines).await,
+            json!({ "jsonrpc": "2.0", "id": 7, "error": { "code": METHOD_NOT_FOUND, "message": "unknown method 'reboot'" } })
+        );
+
+        let parse_error = next_reply(&mut lines).await;
+        assert_eq!(parse_error["id"], Value::Null);
+        assert_eq!(parse_error["error"]["code"], PARSE_ERROR);
+
+        let invalid_request = next_reply(&mut lines).await;
+        assert_eq!(invalid_request["id"], Value::Null);
+        assert_eq!(invalid_request["error"]["code"], INVALID_REQUEST);
+
+        let invalid_params = next_reply(&mut lines).await;
+        assert_eq!(invalid_params["id"], json!([1]));
+        assert_eq!(invalid_params["error"]["code"], INVALID_PARAMS);
+        assert_eq!(invalid_params["error"]["data"], json!({ "code": "ssid_empty", "field": "ssid" }));
+    }
+
+    #[tokio::test]
+    async fn subscribe_streams_events_after_reply() {
+        let backend = Arc::new(WpaCtrlBackend::detached(&crate::config::test_app_config("")));
+        backend.events().publish(ProvisionEvent::ApStopped);
+        backend.events().publish(ProvisionEvent::ScanCompleted { count: 2 });
+        let (mut lines, mut writer) = connect(backend.clone());
+
+        writer
+            .write_all(b"{\"jsonrpc\":\"2.0\",\"id\":1,\"method\":\"subscribe\",\"params\":{\"last_event_id\":1}}\n")
+            .await
+            .unwrap();
+        assert_eq!(
+            next_reply(&mut lines).await,
+            json!({ "jsonrpc": "2.0", "id": 1, "result": { "subscribed": true } })
+        );
+
+        // 先补发 id 1 之后的事件，再推送实时事件
+        let replayed = next_reply(&mut lines).await;
+        assert_eq!(replayed["method"], "event");
+        assert_eq!(replayed["params"], json!({ "id": 2, "name": "scan_completed", "event": { "type": "scan_completed", "count": 2 } }));
+
+        backend.events().publish(ProvisionEvent::ProvisioningStopped);
+        let live = next_reply(&mut lines).await;
+        assert_eq!(live["params"]["id"], 3);
+        assert_eq!(live["params"]["name"], "provisioning_stopped");
+    }
+}
@@ -0,0 +1,182 @@
+use crate::backend::WpaCtrlBackend;
+use crate::config::ProvisioningConfig;
+use crate::events::{ProvisionEvent, ProvisionerState};
+use crate::shutdown::{self, ShutdownSignal};
+use crate::traits::AudioEvent;
+use crate::web_server;
+use anyhow::Result;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// 配网超时且没有回到之前的网络时的退出码
+pub const EXIT_PROVISIONING_TIMEOUT: i32 = 3;
+
+/// 检查超时的间隔
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// 一次配网会话的结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionOutcome {
+    /// 手机提交的网络已经连上
+    Provisioned,
+    /// 超时结束，附带进程退出码（回到了之前的网络时为 0）
+    TimedOut(i32),
+    /// 被本地应用主动结束，退出码同上
+    Stopped(i32),
+    /// 收到 SIGTERM/SIGINT，AP 已经关闭，附带进程退出码
+    Shutdown(i32),
+    /// Web 服务器意外退出
+    ServerStopped,
+}
+
+/// 一次完整的配网会话：扫描 -> 启动 AP -> 运行 Web 服务，直到配网成功、超时或被要求结束
+pub async fn run_session(backend: Arc<WpaCtrlBackend>, config: &ProvisioningConfig) -> Result<SessionOutcome> {
+    // 执行 TDM 启动序列：扫描 -> 启动 AP
+    tracing::info!("📡 Executing initial scan and starting AP...");
+    match backend.setup_and_scan().await {
+        Ok(networks) => {
+            tracing::info!(
+                "✅ Initial scan complete, found {} networks. AP started.",
+                networks.len()
+            );
+        }
+        // 扫描期间收到退出信号时不会再启动 AP，按退出流程收尾
+        Err(e) if let Some(signal) = shutdown::requested_signal() => {
+            tracing::warn!("AP not started: {:#}", e);
+            return Ok(SessionOutcome::Shutdown(finish(&backend, SessionEnd::Shutdown(signal)).await));
+        }
+        Err(e) => {
+            tracing::error!("❌ Failed to scan or start AP: {}.", e);
+            return Err(e);
+        }
+    }
+
+    // 运行 Web 服务器，直到配网成功或超时
+    tokio::select! {
+        result = web_server::run_server(backend.clone()) => {
+            // 收到退出信号时 Web 服务器会先处理完进行中的请求再返回
+            if let Some(signal) = shutdown::requested_signal() {
+                return Ok(SessionOutcome::Shutdown(finish(&backend, SessionEnd::Shutdown(signal)).await));
+            }
+            if let Err(e) = result {
+                tracing::error!("❌ Web server failed: {}", e);
+            }
+            Ok(SessionOutcome::ServerStopped)
+        }
+        () = backend.wait_provisioned() => Ok(SessionOutcome::Provisioned),
+        timeout = wait_for_timeout(&backend, config) => {
+            Ok(SessionOutcome::TimedOut(finish(&backend, SessionEnd::TimedOut(timeout)).await))
+        }
+        () = backend.wait_stop_requested() => {
+            Ok(SessionOutcome::Stopped(finish(&backend, SessionEnd::Stopped).await))
+        }
+    }
+}
+
+/// 会话超时的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionTimeout {
+    /// 超过 `[provisioning].timeout`
+    Total,
+    /// 超过 `[provisioning].idle_timeout`
+    Idle,
+}
+
+/// 配网会话在配网成功之前结束的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionEnd {
+    TimedOut(SessionTimeout),
+    /// 本地应用请求结束（`stop_provisioning`）
+    Stopped,
+    /// 收到 SIGTERM/SIGINT
+    Shutdown(ShutdownSignal),
+}
+
+/// 等待配网会话超时；两个超时都未设置时永远不返回
+///
+/// 空闲是指没有客户端连接 AP，也没有 HTTP 请求。连接尝试进行期间
+/// （单网卡模式下 AP 会暂时关闭）不计入空闲时间，也不会触发总超时。
+pub async fn wait_for_timeout(backend: &WpaCtrlBackend, config: &ProvisioningConfig) -> SessionTimeout {
+    if config.timeout.is_none() && config.idle_timeout.is_none() {
+        return std::future::pending().await;
+    }
+
+    let started = tokio::time::Instant::now();
+    backend.touch_activity();
+    loop {
+        tokio::time::sleep(POLL_INTERVAL).await;
+
+        if backend.is_connecting() {
+            backend.touch_activity();
+            continue;
+        }
+        if backend.is_ap_active() && backend.associated_stations().await.is_some_and(|n| n > 0) {
+            backend.touch_activity();
+        }
+
+        if config.timeout.is_some_and(|t| started.elapsed() >= t) {
+            return SessionTimeout::Total;
+        }
+        if config.idle_timeout.is_some_and(|t| backend.idle_for() >= t) {
+            return SessionTimeout::Idle;
+        }
+    }
+}
+
+/// 超时或被要求结束后的收尾：通知、关闭 AP，再尝试回到之前保存的网络
+///
+/// 返回进程退出码：回到了之前的网络时为 0，否则为 `EXIT_PROVISIONING_TIMEOUT`；
+/// 收到退出信号时不等待重新联网，返回信号对应的退出码。
+pub async fn finish(backend: &WpaCtrlBackend, end: SessionEnd) -> i32 {
+    match end {
+        SessionEnd::TimedOut(timeout) => {
+            let idle = timeout == SessionTimeout::Idle;
+            tracing::warn!(
+                "⏰ Provisioning {}, shutting down the AP.",
+                if idle { "idle timeout reached" } else { "timed out" }
+            );
+            backend.events().publish(ProvisionEvent::ProvisioningTimedOut { idle });
+            backend.play(AudioEvent::ProvisioningTimeout).await;
+        }
+        SessionEnd::Stopped => {
+            tracing::info!("⏹️ Provisioning stopped on request, shutting down the AP.");
+            backend.events().publish(ProvisionEvent::ProvisioningStopped);
+        }
+        SessionEnd::Shutdown(signal) => {
+            tracing::info!("🛑 {} received, shutting down the AP.", signal);
+        }
+    }
+    // 给 SSE 连接留出把事件推送出去的时间（退出时 Web 服务器已经关闭）
+    if !matches!(end, SessionEnd::Shutdown(_)) {
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+
+    if let Err(e) = backend.stop_ap().await {
+        tracing::warn!("Failed to stop AP cleanly: {:#}", e);
+    }
+
+    if let SessionEnd::Shutdown(signal) = end {
+        // 只把已保存的网络交还给 wpa_supplicant，由它自己重连
+        if let Err(e) = backend.enable_saved_networks().await {
+            tracing::warn!("Failed to re-enable saved networks: {:#}", e);
+        }
+        return signal.exit_code();
+    }
+
+    match backend.restore_saved_networks().await {
+        Ok(true) => {
+            tracing::info!("Back on the previously saved network.");
+            0
+        }
+        Ok(false) => {
+            tracing::info!("No saved network to go back to.");
+            backend.set_state(ProvisionerState::Offline);
+            EXIT_PROVISIONING_TIMEOUT
+        }
+        Err(e) => {
+            tracing::warn!("Failed to go back to a saved network: {:#}", e);
+            backend.set_state(ProvisionerState::Offline);
+            EXIT_PROVISIONING_TIMEOUT
+        }
+    }
+}
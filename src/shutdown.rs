@@ -0,0 +1,91 @@
+use anyhow::{Context, Result};
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::sync::watch;
+
+/// 请求退出的信号
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownSignal {
+    Terminate,
+    Interrupt,
+}
+
+impl ShutdownSignal {
+    /// 进程退出码，沿用 shell 的 128 + 信号值：SIGTERM 为 143，SIGINT 为 130
+    pub fn exit_code(self) -> i32 {
+        128 + match self {
+            ShutdownSignal::Terminate => 15,
+            ShutdownSignal::Interrupt => 2,
+        }
+    }
+}
+
+impl fmt::Display for ShutdownSignal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ShutdownSignal::Terminate => "SIGTERM",
+            ShutdownSignal::Interrupt => "SIGINT",
+        })
+    }
+}
+
+/// 整个进程共用的退出请求；各处只需等待它，不需要层层传递
+static SHUTDOWN: OnceLock<watch::Sender<Option<ShutdownSignal>>> = OnceLock::new();
+
+fn channel() -> &'static watch::Sender<Option<ShutdownSignal>> {
+    SHUTDOWN.get_or_init(|| watch::channel(None).0)
+}
+
+/// 接管 SIGTERM 和 SIGINT：第一次收到时开始优雅退出，再次收到时不做清理立即退出
+pub fn listen() -> Result<()> {
+    let mut terminate = signal(SignalKind::terminate()).context("Failed to register SIGTERM handler")?;
+    let mut interrupt = signal(SignalKind::interrupt()).context("Failed to register SIGINT handler")?;
+    tokio::spawn(async move {
+        loop {
+            let received = tokio::select! {
+                Some(()) = terminate.recv() => ShutdownSignal::Terminate,
+                Some(()) = interrupt.recv() => ShutdownSignal::Interrupt,
+                else => return,
+            };
+            if requested_signal().is_some() {
+                tracing::warn!("Received {} again, exiting without cleanup.", received);
+                std::process::exit(received.exit_code());
+            }
+            tracing::info!("🛑 Received {}, shutting down...", received);
+            crate::systemd::notify_stopping();
+            channel().send_replace(Some(received));
+        }
+    });
+    Ok(())
+}
+
+/// 已经收到的退出信号
+pub fn requested_signal() -> Option<ShutdownSignal> {
+    *channel().borrow()
+}
+
+/// 等待退出请求（已经收到时立即返回）；没有调用 `listen` 时永远不返回
+pub async fn requested() -> ShutdownSignal {
+    let mut receiver = channel().subscribe();
+    let signal = match receiver.wait_for(Option::is_some).await {
+        Ok(signal) => *signal,
+        Err(_) => None,
+    };
+    match signal {
+        Some(signal) => signal,
+        None => std::future::pending().await,
+    }
+}
+
+/// 删除本进程监听的 Unix socket 文件（触发 socket、控制 socket）
+pub fn remove_sockets<'a>(paths: impl IntoIterator<Item = &'a Option<PathBuf>>) {
+    for path in paths.into_iter().flatten() {
+        match std::fs::remove_file(path) {
+            Ok(()) => tracing::debug!("Removed socket {}", path.display()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => tracing::warn!("Failed to remove socket {}: {}", path.display(), e),
+        }
+    }
+}
@@ -1,16 +1,121 @@
+use crate::charset::SsidEncoding;
 use serde::{Deserialize, Serialize};
 
 /// 表示扫描到的单个 Wi-Fi 网络
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Network {
     pub ssid: String,
+    /// SSID 原始字节的十六进制编码（路由器实际广播的字节）
+    pub ssid_hex: String,
+    /// 按检测到的字符集解码后用于展示的名称
+    pub display_name: String,
+    /// 检测到的 SSID 字符编码
+    pub encoding: SsidEncoding,
     pub signal: u8,       // 信号强度，0到100
+    /// 中心频率（MHz）
+    #[serde(default)]
+    pub frequency: u32,
     pub security: String, // "WPA2", "WPA", "Open" 等
 }
 
 /// /api/connect 的请求体
+///
+/// 优先使用 `ssid_hex` 标识网络，`ssid` 仅在未提供 `ssid_hex` 时按 UTF-8 编码使用。
 #[derive(Debug, Clone, Deserialize)]
 pub struct ConnectionRequest {
+    #[serde(default)]
     pub ssid: String,
+    #[serde(default)]
+    pub ssid_hex: Option<String>,
     pub password: String,
 }
+
+impl ConnectionRequest {
+    /// 目标网络 SSID 的原始字节
+    pub fn ssid_bytes(&self) -> Result<Vec<u8>, hex::FromHexError> {
+        match &self.ssid_hex {
+            Some(h) => hex::decode(h),
+            None => Ok(self.ssid.as_bytes().to_vec()),
+        }
+    }
+
+    /// 用于日志和展示的 SSID
+    pub fn display_ssid(&self) -> String {
+        if !self.ssid.is_empty() {
+            return self.ssid.clone();
+        }
+        match self.ssid_bytes() {
+            Ok(bytes) => String::from_utf8_lossy(&bytes).to_string(),
+            Err(_) => String::new(),
+        }
+    }
+}
+
+/// 连接失败的原因（机器可读）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureReason {
+    /// 在超时时间内没有完成连接
+    Timeout,
+    /// 四次握手阶段失败，通常是密码错误
+    WrongPassword,
+    /// 无法与路由器关联（信号太弱、网络不存在等）
+    AssociationFailed,
+    /// 与 wpa_supplicant 交互时出现内部错误
+    Internal,
+}
+
+impl std::fmt::Display for FailureReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let text = match self {
+            FailureReason::Timeout => "connection timed out",
+            FailureReason::WrongPassword => "wrong password",
+            FailureReason::AssociationFailed => "association failed",
+            FailureReason::Internal => "internal error",
+        };
+        f.write_str(text)
+    }
+}
+
+impl std::error::Error for FailureReason {}
+
+/// 最近一次连接尝试的结果（TDM 模式下手机重新连上 AP 后展示）
+#[derive(Debug, Clone, Serialize)]
+pub struct LastAttempt {
+    pub ssid: String,
+    /// 尝试开始的时间（Unix 时间戳，秒）
+    pub timestamp: u64,
+    pub reason: FailureReason,
+    /// 从开始到失败所用的时间（毫秒）
+    pub duration_ms: u64,
+}
+
+/// 最近一次连接尝试的结果，成功和失败都会记录（读取后不清除）
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ConnectResult {
+    pub ssid: String,
+    /// 尝试开始的时间（Unix 时间戳，秒）
+    pub timestamp: u64,
+    pub success: bool,
+    /// 失败原因，成功时为 None
+    pub reason: Option<FailureReason>,
+    /// 从开始到结束所用的时间（毫秒）
+    pub duration_ms: u64,
+}
+
+/// wpa_supplicant 中保存的一个网络（`LIST_NETWORKS` 的一行）
+#[derive(Debug, Clone, Serialize)]
+pub struct SavedNetwork {
+    pub id: u32,
+    pub ssid: String,
+    /// 例如 `[CURRENT]`、`[DISABLED]`
+    pub flags: String,
+}
+
+/// /api/scan 的响应体
+#[derive(Debug, Clone, Serialize)]
+pub struct ScanResponse {
+    pub networks: Vec<Network>,
+    /// 上一次失败的连接尝试（展示一次后清除）
+    pub last_attempt: Option<LastAttempt>,
+}
@@ -0,0 +1,200 @@
+use crate::backend::WpaCtrlBackend;
+use crate::events::{ProvisionEvent, ProvisionerState};
+use anyhow::{Context, Result, anyhow};
+use sd_notify::NotifyState;
+use std::os::fd::FromRawFd;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio_stream::StreamExt;
+use tokio_stream::wrappers::BroadcastStream;
+
+/// systemd 传入的 HTTP 监听 socket（socket 激活），进程内只读取一次环境变量
+static ACTIVATED_LISTENER: OnceLock<Option<std::net::TcpListener>> = OnceLock::new();
+
+/// 通知 systemd 服务已就绪（`READY=1`）；没有 `NOTIFY_SOCKET` 时什么都不做
+///
+/// 可以重复调用：`monitor` 每次进入配网都会再发一次，systemd 会忽略多余的通知。
+pub fn notify_ready() {
+    notify(&[NotifyState::Ready]);
+}
+
+/// 通知 systemd 服务开始退出（`STOPPING=1`）
+pub fn notify_stopping() {
+    notify(&[NotifyState::Stopping]);
+}
+
+fn notify(state: &[NotifyState]) {
+    // 保留 NOTIFY_SOCKET，之后还要发送状态和看门狗通知
+    if let Err(e) = sd_notify::notify(false, state) {
+        tracing::warn!("Failed to notify systemd: {}", e);
+    }
+}
+
+/// 取得 socket 激活传入的 HTTP 监听 socket
+///
+/// 每次返回一个新的副本：`monitor` 每次配网都会重新启动 Web 服务器，
+/// 会话结束时只关闭副本，原 socket 一直保留到进程退出。
+pub fn activated_listener() -> Result<Option<tokio::net::TcpListener>> {
+    let listener = ACTIVATED_LISTENER.get_or_init(|| {
+        let fd = match sd_notify::listen_fds() {
+            Ok(mut fds) => fds.next()?,
+            Err(e) => {
+                tracing::warn!("Ignoring invalid socket activation environment: {}", e);
+                return None;
+            }
+        };
+        // SAFETY: LISTEN_FDS 交给本进程的描述符只在这里接管一次
+        Some(unsafe { std::net::TcpListener::from_raw_fd(fd) })
+    });
+    let Some(listener) = listener else {
+        return Ok(None);
+    };
+    let listener = listener.try_clone().context("Failed to duplicate activated socket")?;
+    listener
+        .set_nonblocking(true)
+        .context("Failed to set activated socket to non-blocking")?;
+    let listener = tokio::net::TcpListener::from_std(listener)
+        .map_err(|e| anyhow!("Activated socket is not a TCP listener: {}", e))?;
+    Ok(Some(listener))
+}
+
+/// 跟随后端运行的 systemd 通知任务，丢弃时停止
+pub struct ServiceNotifier {
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Drop for ServiceNotifier {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+/// 由 systemd 启动时，把状态变化同步到 `STATUS=`，并在启用看门狗时定期发送 `WATCHDOG=1`
+pub fn spawn(backend: &Arc<WpaCtrlBackend>) -> ServiceNotifier {
+    let mut tasks = Vec::new();
+    if std::env::var_os("NOTIFY_SOCKET").is_none() {
+        return ServiceNotifier { tasks };
+    }
+    tasks.push(tokio::spawn(mirror_status(backend.clone())));
+
+    let mut usec = 0;
+    if sd_notify::watchdog_enabled(false, &mut usec) && usec > 0 {
+        // systemd 建议以超时的一半为间隔发送
+        let interval = Duration::from_micros(usec) / 2;
+        tracing::info!("🐶 systemd watchdog enabled, pinging every {}ms.", interval.as_millis());
+        let backend = backend.clone();
+        tasks.push(tokio::spawn(watchdog(interval, move || {
+            let backend = backend.clone();
+            async move { backend.ping().await }
+        })));
+    }
+    ServiceNotifier { tasks }
+}
+
+/// 把状态变化写成 `STATUS=` 文本，`systemctl status` 中可以看到
+async fn mirror_status(backend: Arc<WpaCtrlBackend>) {
+    let (_, receiver) = backend.events().subscribe(None);
+    // 订阅后再读取当前状态，不会漏掉中间的变化
+    notify(&[NotifyState::Status(&status_text(&backend, backend.state()))]);
+    // 订阅者处理过慢导致的 Lagged 错误直接跳过
+    let mut events = BroadcastStream::new(receiver).filter_map(|res| res.ok());
+    while let Some(seq) = events.next().await {
+        if let ProvisionEvent::StateChanged { state } = seq.event {
+            notify(&[NotifyState::Status(&status_text(&backend, state))]);
+        }
+    }
+}
+
+fn status_text(backend: &WpaCtrlBackend, state: ProvisionerState) -> String {
+    let ap = backend.ap_config();
+    match state {
+        ProvisionerState::Starting => "Starting wpa_supplicant".to_string(),
+        ProvisionerState::Online => "Online".to_string(),
+        ProvisionerState::Offline => "Offline, waiting for a saved network".to_string(),
+        ProvisionerState::Provisioning => format!("Provisioning: AP '{}', portal on {}", ap.ssid, ap.bind_addr),
+        ProvisionerState::Connecting => "Connecting to the submitted network".to_string(),
+    }
+}
+
+/// 只在健康检查（wpa_supplicant 控制接口能及时回复 PING）通过时喂狗
+///
+/// wpa_supplicant 卡死或退出后不再发送 `WATCHDOG=1`，由 systemd 按 `WatchdogSec` 重启服务。
+async fn watchdog<F, Fut>(interval: Duration, health_check: F)
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        ticker.tick().await;
+        match tokio::time::timeout(interval, health_check()).await {
+            Ok(Ok(())) => notify(&[NotifyState::Watchdog]),
+            Ok(Err(e)) => tracing::warn!("wpa_ctrl health check failed, skipping watchdog ping: {:#}", e),
+            Err(_) => tracing::warn!("wpa_ctrl health check timed out, skipping watchdog ping."),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_app_config;
+    use tokio::net::UnixDatagram;
+
+    async fn next_message(socket: &UnixDatagram) -> String {
+        let mut buf = [0u8; 512];
+        let len = tokio::time::timeout(Duration::from_secs(5), socket.recv(&mut buf))
+            .await
+            .expect("no notification from the service")
+            .unwrap();
+        String::from_utf8_lossy(&buf[..len]).into_owned()
+    }
+
+    /// 只有这个测试设置 NOTIFY_SOCKET；用假的健康检查代替 wpa_supplicant 的 PING
+    #[tokio::test]
+    async fn notifications_reach_notify_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notify.sock");
+        let socket = UnixDatagram::bind(&path).unwrap();
+        // SAFETY: 测试中只通过 std 读取环境变量（有锁保护），其它测试不使用 NOTIFY_SOCKET
+        unsafe { std::env::set_var("NOTIFY_SOCKET", &path) };
+
+        notify_ready();
+        assert_eq!(next_message(&socket).await, "READY=1\n");
+
+        let backend = Arc::new(WpaCtrlBackend::detached(&test_app_config("")));
+        let mirror = tokio::spawn(mirror_status(backend.clone()));
+        assert_eq!(next_message(&socket).await, "STATUS=Starting wpa_supplicant\n");
+        backend.set_state(ProvisionerState::Provisioning);
+        assert_eq!(
+            next_message(&socket).await,
+            "STATUS=Provisioning: AP 'Echo-mate', portal on 192.168.4.1:80\n"
+        );
+        mirror.abort();
+
+        // 健康检查失败时不喂狗，恢复后继续发送 WATCHDOG=1
+        let healthy = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let check = {
+            let healthy = healthy.clone();
+            move || {
+                let ok = healthy.load(std::sync::atomic::Ordering::SeqCst);
+                async move { if ok { Ok(()) } else { Err(anyhow!("PING timed out")) } }
+            }
+        };
+        let dog = tokio::spawn(watchdog(Duration::from_millis(20), check));
+        let mut buf = [0u8; 64];
+        let early = tokio::time::timeout(Duration::from_millis(100), socket.recv(&mut buf)).await;
+        assert!(early.is_err(), "watchdog pinged while unhealthy");
+        healthy.store(true, std::sync::atomic::Ordering::SeqCst);
+        assert_eq!(next_message(&socket).await, "WATCHDOG=1\n");
+        dog.abort();
+
+        notify_stopping();
+        assert_eq!(next_message(&socket).await, "STOPPING=1\n");
+        unsafe { std::env::remove_var("NOTIFY_SOCKET") };
+    }
+}
@@ -26,6 +26,8 @@ pub enum AudioEvent {
     ConnectionSuccess,
     /// 连接失败
     ConnectionFailed,
+    /// 配网超时，AP 即将关闭
+    ProvisioningTimeout,
 }
 
 /// 一个提供语音播报的通用 Trait
@@ -0,0 +1,474 @@
+use crate::backend::WpaCtrlBackend;
+use crate::config::TriggerConfig;
+use anyhow::{Context, Result, anyhow};
+use std::fmt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::mpsc;
+
+/// 触发 socket 接受的命令
+pub const PROVISION_COMMAND: &str = "provision";
+
+/// 标志文件的检查间隔
+const FLAG_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// evdev 的按键事件类型 (EV_KEY)
+const EV_KEY: u16 = 0x01;
+/// 按键事件的 value：松开、按下、自动重复
+const KEY_RELEASED: i32 = 0;
+const KEY_PRESSED: i32 = 1;
+const KEY_REPEAT: i32 = 2;
+
+/// `struct input_event` 的大小：`timeval` 的两个字段在内核 ABI 中都是
+/// `unsigned long`（32 位 time64 用户态也一样），其后是 type、code (u16) 和 value (i32)
+const INPUT_EVENT_SIZE: usize = 2 * size_of::<usize>() + 8;
+
+/// 触发来源
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriggerSource {
+    Socket,
+    FlagFile,
+    Signal,
+    Input,
+    /// JSON-RPC 控制 socket 的 `start_provisioning`
+    Rpc,
+    /// D-Bus 的 `StartProvisioning`
+    #[cfg(feature = "dbus")]
+    Dbus,
+}
+
+impl fmt::Display for TriggerSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TriggerSource::Socket => "trigger socket",
+            TriggerSource::FlagFile => "flag file",
+            TriggerSource::Signal => "SIGUSR1",
+            TriggerSource::Input => "input device",
+            TriggerSource::Rpc => "JSON-RPC",
+            #[cfg(feature = "dbus")]
+            TriggerSource::Dbus => "D-Bus",
+        })
+    }
+}
+
+/// 所有已启用触发源的汇总
+///
+/// 各触发源在后台任务中运行，配网进行中时产生的触发会被 `drain` 丢弃。
+pub struct Triggers {
+    sender: mpsc::Sender<TriggerSource>,
+    receiver: mpsc::Receiver<TriggerSource>,
+}
+
+/// 从程序内部（例如控制 socket）发起触发
+#[derive(Clone)]
+pub struct TriggerHandle(mpsc::Sender<TriggerSource>);
+
+impl TriggerHandle {
+    pub fn fire(&self, source: TriggerSource) {
+        fire(&self.0, source);
+    }
+}
+
+impl Triggers {
+    /// 启动配置中启用的全部触发源；socket 无法监听或信号无法注册时返回错误
+    pub fn spawn(config: &TriggerConfig) -> Result<Self> {
+        let (sender, receiver) = mpsc::channel(8);
+
+        if let Some(path) = &config.socket_path {
+            let listener = bind_socket(path, config.socket_mode, config.socket_group.as_deref())?;
+            tracing::info!("🔘 Provisioning trigger: socket {}", path.display());
+            tokio::spawn(watch_socket(listener, sender.clone()));
+        }
+        if let Some(path) = &config.flag_file {
+            tracing::info!("🔘 Provisioning trigger: flag file {}", path.display());
+            tokio::spawn(watch_flag_file(path.clone(), sender.clone()));
+        }
+        if config.sigusr1 {
+            use tokio::signal::unix::{SignalKind, signal};
+            let mut signals = signal(SignalKind::user_defined1()).context("Failed to register SIGUSR1 handler")?;
+            tracing::info!("🔘 Provisioning trigger: SIGUSR1");
+            let sender = sender.clone();
+            tokio::spawn(async move {
+                while signals.recv().await.is_some() {
+                    fire(&sender, TriggerSource::Signal);
+                }
+            });
+        }
+        if let Some(path) = &config.input_device {
+            tracing::info!(
+                "🔘 Provisioning trigger: input {} (key {}, hold {}ms)",
+                path.display(),
+                config.input_key_code.map_or("any".to_string(), |code| code.to_string()),
+                config.input_hold.as_millis()
+            );
+            tokio::spawn(watch_input(
+                path.clone(),
+                config.input_key_code,
+                config.input_hold,
+                sender.clone(),
+            ));
+        }
+
+        Ok(Self { sender, receiver })
+    }
+
+    pub fn handle(&self) -> TriggerHandle {
+        TriggerHandle(self.sender.clone())
+    }
+
+    /// 等待下一次触发；没有任何触发时永远不返回
+    pub async fn next(&mut self) -> TriggerSource {
+        match self.receiver.recv().await {
+            Some(source) => source,
+            None => std::future::pending().await,
+        }
+    }
+
+    /// 丢弃已经排队的触发（配网期间按下的按键不应在结束后再次触发配网）
+    pub fn drain(&mut self) {
+        while self.receiver.try_recv().is_ok() {}
+    }
+}
+
+/// 控制接口（JSON-RPC、D-Bus）请求进入配网
+///
+/// 只有 `monitor` 模式有触发入口；`run` 模式启动后本身就在配网。
+pub fn start_provisioning(backend: &WpaCtrlBackend, trigger: Option<&TriggerHandle>, source: TriggerSource) -> Result<()> {
+    if backend.state().is_provisioning() {
+        return Err(anyhow!("already provisioning"));
+    }
+    let trigger = trigger.ok_or_else(|| anyhow!("starting provisioning is only available in monitor mode"))?;
+    trigger.fire(source);
+    Ok(())
+}
+
+/// 发送一次触发；队列已满说明已经有触发在等待处理，直接丢弃
+fn fire(sender: &mpsc::Sender<TriggerSource>, source: TriggerSource) {
+    tracing::info!("🔘 Provisioning requested via {}.", source);
+    let _ = sender.try_send(source);
+}
+
+/// 监听 Unix socket，清理上次遗留的 socket 文件，并设置文件权限和所属的组
+///
+/// 触发 socket 和控制 socket 共用，socket 文件的权限决定了哪些程序能够连接。
+pub fn bind_socket(path: &Path, mode: u32, group: Option<&str>) -> Result<UnixListener> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory {}", parent.display()))?;
+    }
+    match std::fs::remove_file(path) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e).with_context(|| format!("Failed to remove stale socket {}", path.display())),
+    }
+    let listener = UnixListener::bind(path).with_context(|| format!("Failed to listen on {}", path.display()))?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+        .with_context(|| format!("Failed to set permissions on {}", path.display()))?;
+    if let Some(group) = group {
+        let gid = lookup_group(group)?;
+        std::os::unix::fs::chown(path, None, Some(gid))
+            .with_context(|| format!("Failed to change group of {} to {}", path.display(), group))?;
+    }
+    Ok(listener)
+}
+
+/// 在 /etc/group 中查找组 ID
+fn lookup_group(name: &str) -> Result<u32> {
+    if let Ok(gid) = name.parse() {
+        return Ok(gid);
+    }
+    let groups = std::fs::read_to_string(Path::new("/etc/group")).context("Failed to read /etc/group")?;
+    groups
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split(':');
+            let group = fields.next()?;
+            let gid = fields.nth(1)?.parse().ok()?;
+            (group == name).then_some(gid)
+        })
+        .next()
+        .ok_or_else(|| anyhow!("group '{}' not found", name))
+}
+
+async fn watch_socket(listener: UnixListener, sender: mpsc::Sender<TriggerSource>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(handle_client(stream, sender.clone()));
+            }
+            Err(e) => {
+                tracing::warn!("Trigger socket accept failed: {}", e);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+    }
+}
+
+/// 每行一个命令，逐行回复 `ok` 或 `error: ...`
+async fn handle_client(stream: UnixStream, sender: mpsc::Sender<TriggerSource>) {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        let reply = match line.trim() {
+            "" => continue,
+            PROVISION_COMMAND => {
+                fire(&sender, TriggerSource::Socket);
+                "ok\n".to_string()
+            }
+            other => format!("error: unknown command '{}'\n", other),
+        };
+        if writer.write_all(reply.as_bytes()).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// 轮询标志文件：文件出现时触发并删除
+///
+/// 没有权限删除时只在文件从无到有时触发一次，避免每秒重复触发。
+async fn watch_flag_file(path: PathBuf, sender: mpsc::Sender<TriggerSource>) {
+    let mut existed = false;
+    loop {
+        let exists = tokio::fs::try_exists(&path).await.unwrap_or(false);
+        if exists && !existed {
+            fire(&sender, TriggerSource::FlagFile);
+            if let Err(e) = tokio::fs::remove_file(&path).await {
+                tracing::warn!("Failed to remove trigger file {}: {}", path.display(), e);
+            }
+        }
+        existed = exists && tokio::fs::try_exists(&path).await.unwrap_or(false);
+        tokio::time::sleep(FLAG_POLL_INTERVAL).await;
+    }
+}
+
+/// 一条 evdev 按键事件
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    /// 内核记录的事件时间
+    pub time: Duration,
+    pub code: u16,
+    pub value: i32,
+}
+
+/// 解析一条 `struct input_event`，非按键事件返回 None
+pub fn parse_input_event(buf: &[u8; INPUT_EVENT_SIZE]) -> Option<KeyEvent> {
+    const WORD: usize = size_of::<usize>();
+    let word = |i: usize| usize::from_ne_bytes(buf[i * WORD..(i + 1) * WORD].try_into().unwrap());
+    let rest = &buf[2 * WORD..];
+    let kind = u16::from_ne_bytes([rest[0], rest[1]]);
+    if kind != EV_KEY {
+        return None;
+    }
+    Some(KeyEvent {
+        time: Duration::from_secs(word(0) as u64) + Duration::from_micros(word(1) as u64),
+        code: u16::from_ne_bytes([rest[2], rest[3]]),
+        value: i32::from_ne_bytes([rest[4], rest[5], rest[6], rest[7]]),
+    })
+}
+
+/// 按键长按判定：按下后保持 `hold` 时触发一次（`hold` 为零时按下即触发）
+///
+/// 按住时间用事件自带的时间戳计算，gpio-keys 默认不自动重复，这种情况下在松开时判定。
+#[derive(Debug)]
+pub struct KeyHold {
+    code: Option<u16>,
+    hold: Duration,
+    /// 按下的时间，以及本次按下是否已经触发过
+    pressed: Option<(Duration, bool)>,
+}
+
+impl KeyHold {
+    pub fn new(code: Option<u16>, hold: Duration) -> Self {
+        Self { code, hold, pressed: None }
+    }
+
+    /// 处理一个按键事件，需要触发时返回 true
+    pub fn feed(&mut self, event: KeyEvent) -> bool {
+        if self.code.is_some_and(|code| code != event.code) {
+            return false;
+        }
+        match event.value {
+            KEY_PRESSED => {
+                let fired = self.hold.is_zero();
+                self.pressed = Some((event.time, fired));
+                fired
+            }
+            KEY_REPEAT | KEY_RELEASED => {
+                let fired = match &mut self.pressed {
+                    Some((since, fired)) if !*fired && event.time.saturating_sub(*since) >= self.hold => {
+                        *fired = true;
+                        true
+                    }
+                    _ => false,
+                };
+                if event.value == KEY_RELEASED {
+                    self.pressed = None;
+                }
+                fired
+            }
+            _ => false,
+        }
+    }
+}
+
+/// 读取 evdev 事件流；设备消失（或测试用的事件文件读完）时结束
+async fn watch_input(path: PathBuf, code: Option<u16>, hold: Duration, sender: mpsc::Sender<TriggerSource>) {
+    let mut file = match tokio::fs::File::open(&path).await {
+        Ok(file) => file,
+        Err(e) => {
+            tracing::warn!("Failed to open trigger input {}: {}", path.display(), e);
+            return;
+        }
+    };
+    let mut key = KeyHold::new(code, hold);
+    let mut buf = [0u8; INPUT_EVENT_SIZE];
+    loop {
+        if let Err(e) = file.read_exact(&mut buf).await {
+            if e.kind() == std::io::ErrorKind::UnexpectedEof {
+                tracing::info!("Trigger input {} reached end of stream.", path.display());
+            } else {
+                tracing::warn!("Failed to read trigger input {}: {}", path.display(), e);
+            }
+            return;
+        }
+        if let Some(event) = parse_input_event(&buf)
+            && key.feed(event)
+        {
+            fire(&sender, TriggerSource::Input);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::MetadataExt;
+
+    /// KEY_RESTART
+    const KEY: u16 = 408;
+    const EV_SYN: u16 = 0x00;
+
+    /// 按内核 ABI 编码一条 `struct input_event`，时间以毫秒给出
+    fn input_event(millis: u64, kind: u16, code: u16, value: i32) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(INPUT_EVENT_SIZE);
+        buf.extend_from_slice(&((millis / 1000) as usize).to_ne_bytes());
+        buf.extend_from_slice(&((millis % 1000 * 1000) as usize).to_ne_bytes());
+        buf.extend_from_slice(&kind.to_ne_bytes());
+        buf.extend_from_slice(&code.to_ne_bytes());
+        buf.extend_from_slice(&value.to_ne_bytes());
+        buf
+    }
+
+    /// 一次按键事件，后面跟着内核总会发送的 SYN_REPORT
+    fn key(millis: u64, code: u16, value: i32) -> Vec<u8> {
+        let mut buf = input_event(millis, EV_KEY, code, value);
+        buf.extend(input_event(millis, EV_SYN, 0, 0));
+        buf
+    }
+
+    /// 把事件写入普通文件，用 `watch_input` 读到文件结尾，返回触发次数
+    async fn count_triggers(events: &[Vec<u8>], code: Option<u16>, hold_ms: u64) -> usize {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("event0");
+        std::fs::write(&path, events.concat()).unwrap();
+        let (sender, mut receiver) = mpsc::channel(8);
+        watch_input(path, code, Duration::from_millis(hold_ms), sender).await;
+        let mut count = 0;
+        while let Ok(source) = receiver.try_recv() {
+            assert_eq!(source, TriggerSource::Input);
+            count += 1;
+        }
+        count
+    }
+
+    #[test]
+    fn parses_raw_input_event() {
+        let buf: [u8; INPUT_EVENT_SIZE] = input_event(12_345, EV_KEY, KEY, KEY_PRESSED).try_into().unwrap();
+        assert_eq!(
+            parse_input_event(&buf),
+            Some(KeyEvent {
+                time: Duration::from_millis(12_345),
+                code: KEY,
+                value: KEY_PRESSED,
+            })
+        );
+        let syn: [u8; INPUT_EVENT_SIZE] = input_event(12_345, EV_SYN, 0, 0).try_into().unwrap();
+        assert_eq!(parse_input_event(&syn), None);
+    }
+
+    #[tokio::test]
+    async fn press_fires_without_hold() {
+        let events = [key(1_000, KEY, KEY_PRESSED), key(1_100, KEY, KEY_RELEASED)];
+        assert_eq!(count_triggers(&events, Some(KEY), 0).await, 1);
+        // 没有指定按键时任意按键都触发
+        let events = [key(1_000, 30, KEY_PRESSED), key(1_100, 30, KEY_RELEASED), key(2_000, KEY, KEY_PRESSED)];
+        assert_eq!(count_triggers(&events, None, 0).await, 2);
+        // 其它按键被忽略
+        assert_eq!(count_triggers(&events[..2], Some(KEY), 0).await, 0);
+    }
+
+    #[tokio::test]
+    async fn short_press_does_not_reach_hold_threshold() {
+        let events = [key(1_000, KEY, KEY_PRESSED), key(3_999, KEY, KEY_RELEASED)];
+        assert_eq!(count_triggers(&events, Some(KEY), 3_000).await, 0);
+    }
+
+    #[tokio::test]
+    async fn hold_fires_once_on_repeat() {
+        let events = [
+            key(1_000, KEY, KEY_PRESSED),
+            key(2_500, KEY, KEY_REPEAT),
+            key(4_000, KEY, KEY_REPEAT),
+            key(4_500, KEY, KEY_REPEAT),
+            key(6_000, KEY, KEY_RELEASED),
+        ];
+        assert_eq!(count_triggers(&events, Some(KEY), 3_000).await, 1);
+    }
+
+    #[tokio::test]
+    async fn hold_fires_on_release_without_repeat() {
+        // gpio-keys 默认不自动重复，在松开时判定；每次按下分别计算
+        let events = [
+            key(1_000, KEY, KEY_PRESSED),
+            key(4_200, KEY, KEY_RELEASED),
+            key(10_000, KEY, KEY_PRESSED),
+            key(10_500, KEY, KEY_RELEASED),
+            key(20_000, KEY, KEY_PRESSED),
+            key(23_000, KEY, KEY_RELEASED),
+        ];
+        assert_eq!(count_triggers(&events, Some(KEY), 3_000).await, 2);
+    }
+
+    #[tokio::test]
+    async fn truncated_event_is_ignored() {
+        let mut events = vec![key(1_000, KEY, KEY_PRESSED)];
+        events.push(input_event(2_000, EV_KEY, KEY, KEY_PRESSED)[..INPUT_EVENT_SIZE - 1].to_vec());
+        assert_eq!(count_triggers(&events, Some(KEY), 0).await, 1);
+    }
+
+    #[tokio::test]
+    async fn socket_gets_mode_and_group() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("run/trigger.sock");
+        let gid = std::fs::metadata(dir.path()).unwrap().gid();
+        let listener = bind_socket(&path, 0o600, Some(&gid.to_string())).unwrap();
+        let metadata = std::fs::metadata(&path).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+        assert_eq!(metadata.gid(), gid);
+
+        // 重新监听时替换遗留的 socket 文件
+        drop(listener);
+        let _listener = bind_socket(&path, 0o660, None).unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o660);
+    }
+
+    #[test]
+    fn unknown_group_is_an_error() {
+        assert_eq!(lookup_group("1234").unwrap(), 1234);
+        let err = lookup_group("no-such-group-for-provisioner").unwrap_err();
+        assert_eq!(err.to_string(), "group 'no-such-group-for-provisioner' not found");
+    }
+}
@@ -0,0 +1,254 @@
+use crate::structs::{ConnectionRequest, Network};
+
+/// SSID 的最大字节数（IEEE 802.11）
+const SSID_MAX_LEN: usize = 32;
+/// WPA 密码短语的长度范围（字符数）
+const PASSPHRASE_MIN_LEN: usize = 8;
+const PASSPHRASE_MAX_LEN: usize = 63;
+/// 直接给出 PSK 时的十六进制长度
+const PSK_HEX_LEN: usize = 64;
+
+/// 机器可读的校验错误码
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValidationCode {
+    /// SSID 为空
+    SsidEmpty,
+    /// SSID 超过 32 字节
+    SsidTooLong,
+    /// ssid_hex 不是合法的十六进制字符串
+    SsidHexInvalid,
+    /// 密码长度不在 8–63 之间（且不是 64 位十六进制）
+    PasswordLength,
+    /// 密码包含不可打印或非 ASCII 字符
+    PasswordCharset,
+    /// 加密网络缺少密码
+    PasswordRequired,
+    /// 开放网络却提供了密码
+    PasswordNotAllowed,
+    /// 已经有一次连接尝试正在进行
+    Busy,
+}
+
+impl ValidationCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ValidationCode::SsidEmpty => "ssid_empty",
+            ValidationCode::SsidTooLong => "ssid_too_long",
+            ValidationCode::SsidHexInvalid => "ssid_hex_invalid",
+            ValidationCode::PasswordLength => "password_length",
+            ValidationCode::PasswordCharset => "password_charset",
+            ValidationCode::PasswordRequired => "password_required",
+            ValidationCode::PasswordNotAllowed => "password_not_allowed",
+            ValidationCode::Busy => "busy",
+        }
+    }
+}
+
+/// 连接请求校验失败
+#[derive(Debug, Clone)]
+pub struct ValidationError {
+    pub code: ValidationCode,
+    /// 出错的请求字段名
+    pub field: &'static str,
+    pub message: String,
+}
+
+impl ValidationError {
+    fn new(code: ValidationCode, field: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            field,
+            message: message.into(),
+        }
+    }
+
+    /// 已经有一次连接尝试正在进行，请求本身没有问题
+    pub fn busy() -> Self {
+        Self::new(ValidationCode::Busy, "request", "a connection attempt is in progress")
+    }
+}
+
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// 在操作无线网卡之前校验连接请求
+///
+/// `networks` 为已扫描到的网络列表，用于检查加密方式是否与密码一致；
+/// 目标不在列表中时（例如隐藏网络）跳过这一项检查。
+pub fn validate_connection_request(
+    req: &ConnectionRequest,
+    networks: &[Network],
+) -> Result<(), ValidationError> {
+    let ssid = req.ssid_bytes().map_err(|e| {
+        ValidationError::new(
+            ValidationCode::SsidHexInvalid,
+            "ssid_hex",
+            format!("ssid_hex is not valid hex: {}", e),
+        )
+    })?;
+    let field = if req.ssid_hex.is_some() { "ssid_hex" } else { "ssid" };
+    validate_ssid(&ssid, field)?;
+
+    let ssid_hex = hex::encode(&ssid);
+    let network = networks.iter().find(|n| n.ssid_hex == ssid_hex);
+    // SAE 密码没有 8–63 的长度限制，连接时用 `wpa_printf_quote` 原样转义传递
+    let is_sae = network.is_some_and(|n| n.security == "WPA3");
+    if !req.password.is_empty() && !is_sae {
+        validate_passphrase(&req.password)?;
+    }
+
+    if let Some(network) = network {
+        let is_open = network.security == "Open";
+        if is_open && !req.password.is_empty() {
+            return Err(ValidationError::new(
+                ValidationCode::PasswordNotAllowed,
+                "password",
+                format!("network '{}' is open and does not take a password", network.display_name),
+            ));
+        }
+        if !is_open && req.password.is_empty() {
+            return Err(ValidationError::new(
+                ValidationCode::PasswordRequired,
+                "password",
+                format!("network '{}' requires a password", network.display_name),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// SSID 必须为 1–32 字节
+fn validate_ssid(ssid: &[u8], field: &'static str) -> Result<(), ValidationError> {
+    if ssid.is_empty() {
+        return Err(ValidationError::new(
+            ValidationCode::SsidEmpty,
+            field,
+            "SSID must not be empty",
+        ));
+    }
+    if ssid.len() > SSID_MAX_LEN {
+        return Err(ValidationError::new(
+            ValidationCode::SsidTooLong,
+            field,
+            format!("SSID is {} bytes, at most {} allowed", ssid.len(), SSID_MAX_LEN),
+        ));
+    }
+    Ok(())
+}
+
+/// 密码必须是 8–63 个可打印 ASCII 字符，或者正好 64 个十六进制字符（原始 PSK）
+fn validate_passphrase(password: &str) -> Result<(), ValidationError> {
+    if is_hex_psk(password) {
+        return Ok(());
+    }
+    if !password.bytes().all(|b| (0x20..=0x7e).contains(&b)) {
+        return Err(ValidationError::new(
+            ValidationCode::PasswordCharset,
+            "password",
+            "password must contain only printable ASCII characters",
+        ));
+    }
+    let len = password.len();
+    if !(PASSPHRASE_MIN_LEN..=PASSPHRASE_MAX_LEN).contains(&len) {
+        return Err(ValidationError::new(
+            ValidationCode::PasswordLength,
+            "password",
+            format!(
+                "password must be {}-{} characters or {} hex digits, got {}",
+                PASSPHRASE_MIN_LEN, PASSPHRASE_MAX_LEN, PSK_HEX_LEN, len
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// 判断是否为 64 位十六进制的原始 PSK
+pub fn is_hex_psk(password: &str) -> bool {
+    password.len() == PSK_HEX_LEN && password.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::charset::SsidEncoding;
+
+    fn request(ssid: &str, password: &str) -> ConnectionRequest {
+        ConnectionRequest {
+            ssid: ssid.to_string(),
+            ssid_hex: None,
+            password: password.to_string(),
+        }
+    }
+
+    fn network(ssid: &str, security: &str) -> Network {
+        Network {
+            ssid: ssid.to_string(),
+            ssid_hex: hex::encode(ssid),
+            display_name: ssid.to_string(),
+            encoding: SsidEncoding::Utf8,
+            signal: 80,
+            frequency: 2437,
+            security: security.to_string(),
+        }
+    }
+
+    fn code(req: &ConnectionRequest, networks: &[Network]) -> Option<ValidationCode> {
+        validate_connection_request(req, networks).err().map(|e| e.code)
+    }
+
+    #[test]
+    fn accepts_valid_requests() {
+        assert_eq!(code(&request("Home", "password"), &[]), None);
+        assert_eq!(code(&request("Home", &"x".repeat(63)), &[]), None);
+        assert_eq!(code(&request("Home", &"aB".repeat(32)), &[]), None);
+        assert_eq!(code(&request("Cafe", ""), &[network("Cafe", "Open")]), None);
+        // 不在扫描结果中（隐藏网络）时不检查加密方式
+        assert_eq!(code(&request("Hidden", ""), &[]), None);
+    }
+
+    #[test]
+    fn rejects_bad_ssid() {
+        assert_eq!(code(&request("", "password"), &[]), Some(ValidationCode::SsidEmpty));
+        assert_eq!(code(&request(&"s".repeat(33), "password"), &[]), Some(ValidationCode::SsidTooLong));
+
+        let req = ConnectionRequest {
+            ssid: String::new(),
+            ssid_hex: Some("zz".to_string()),
+            password: "password".to_string(),
+        };
+        let err = validate_connection_request(&req, &[]).unwrap_err();
+        assert_eq!(err.code, ValidationCode::SsidHexInvalid);
+        assert_eq!(err.field, "ssid_hex");
+    }
+
+    #[test]
+    fn rejects_bad_wpa2_passwords() {
+        assert_eq!(code(&request("Home", "short"), &[]), Some(ValidationCode::PasswordLength));
+        assert_eq!(code(&request("Home", &"x".repeat(64)), &[]), Some(ValidationCode::PasswordLength));
+        assert_eq!(code(&request("Home", "密码密码密码"), &[]), Some(ValidationCode::PasswordCharset));
+        assert_eq!(code(&request("Home", r#"pa"ss\word"#), &[]), None);
+        assert_eq!(code(&request("Home", "pass\u{7}word"), &[]), Some(ValidationCode::PasswordCharset));
+    }
+
+    #[test]
+    fn checks_password_against_security() {
+        let networks = [network("Home", "WPA2"), network("Cafe", "Open")];
+        assert_eq!(code(&request("Home", ""), &networks), Some(ValidationCode::PasswordRequired));
+        assert_eq!(code(&request("Cafe", "password"), &networks), Some(ValidationCode::PasswordNotAllowed));
+    }
+
+    #[test]
+    fn sae_passwords_have_no_length_limit() {
+        let networks = [network("Home", "WPA3")];
+        assert_eq!(code(&request("Home", "short"), &networks), None);
+        assert_eq!(code(&request("Home", &"x".repeat(100)), &networks), None);
+        assert_eq!(code(&request("Home", "密码"), &networks), None);
+        assert_eq!(code(&request("Home", ""), &networks), Some(ValidationCode::PasswordRequired));
+    }
+}
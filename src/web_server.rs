@@ -1,115 +1,241 @@
 use crate::backend::WpaCtrlBackend;
 use crate::embed::EmbedFrontend;
-use crate::structs::{ConnectionRequest, Network};
+use crate::error::ApiError;
+use crate::events::SequencedEvent;
+use crate::qr::{self, QrFormat};
+use crate::shutdown;
+use crate::structs::{ConnectionRequest, ScanResponse};
+use crate::systemd;
 use crate::traits::UiAssetProvider;
 use axum::{
     body::Body,
-    extract::State,
-    http::{header, StatusCode, Uri},
-    response::{IntoResponse, Json, Response},
+    extract::{rejection::JsonRejection, Query, Request, State},
+    http::{header, HeaderMap, StatusCode, Uri},
+    middleware::{self, Next},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Json, Response,
+    },
     routing::{get, post},
     Router,
 };
-use std::sync::{Arc, Mutex};
+use serde::Deserialize;
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::net::TcpListener;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::{Stream, StreamExt};
+
+/// 收到退出信号后等待进行中的 HTTP 请求完成的最长时间
+const SHUTDOWN_DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
 
 /// Web 服务器状态
 struct AppState {
     backend: Arc<WpaCtrlBackend>,
-    // TDM 模式：缓存启动时扫描的网络列表
-    initial_networks: Arc<Mutex<Vec<Network>>>,
     // UI 资产提供器
     ui_provider: Arc<dyn UiAssetProvider>,
 }
 
-/// 启动 Web 服务器（TDM 模式）
-pub async fn run_server(
-    backend: Arc<WpaCtrlBackend>,
-    initial_networks: Vec<Network>,
-) -> anyhow::Result<()> {
+/// 启动 Web 服务器
+///
+/// 单网卡 (TDM) 模式下扫描结果来自启动时的扫描（由后端缓存）；
+/// 双射频模式下前端可以通过 `/api/scan?refresh=true` 重新扫描。
+pub async fn run_server(backend: Arc<WpaCtrlBackend>) -> anyhow::Result<()> {
+    let app = router(backend.clone());
+
+    // 由 systemd 的 .socket 单元激活时使用传入的 socket，忽略 bind_addr
+    let listener = match systemd::activated_listener()? {
+        Some(listener) => {
+            tracing::info!("🌐 TDM Web server listening on {} (socket activation)", listener.local_addr()?);
+            listener
+        }
+        None => {
+            let bind_addr = backend.ap_config().bind_addr;
+            tracing::info!("🌐 TDM Web server listening on {}", bind_addr);
+            TcpListener::bind(bind_addr).await?
+        }
+    };
+    systemd::notify_ready();
+
+    // 收到退出信号后不再接受新连接，等待进行中的请求完成（SSE 连接会随之结束）
+    let serve = axum::serve(listener, app.into_make_service()).with_graceful_shutdown(async {
+        shutdown::requested().await;
+    });
+    tokio::select! {
+        result = serve => result?,
+        () = async {
+            shutdown::requested().await;
+            tokio::time::sleep(SHUTDOWN_DRAIN_TIMEOUT).await;
+        } => tracing::warn!("HTTP connections still open after {}s, closing them.", SHUTDOWN_DRAIN_TIMEOUT.as_secs()),
+    }
+
+    Ok(())
+}
+
+/// 构建路由
+fn router(backend: Arc<WpaCtrlBackend>) -> Router {
     // 初始化 EmbedFrontend
     let ui_provider = Arc::new(EmbedFrontend::new());
 
     let app_state = Arc::new(AppState {
-        backend: backend.clone(),
-        initial_networks: Arc::new(Mutex::new(initial_networks)),
+        backend,
         ui_provider,
     });
 
-    // 构建路由
-    let app = Router::new()
+    Router::new()
         .route("/api/scan", get(api_scan_tdm))
         .route("/api/connect", post(api_connect_tdm))
         .route("/api/backend_kind", get(api_backend_kind_tdm))
+        .route("/api/events", get(api_events))
+        .route("/api/last_attempt", get(api_last_attempt))
+        .route("/api/ap_qr", get(api_ap_qr))
         .route("/generate_204", get(handle_captive_portal))
         .fallback(get(serve_static_asset))
-        .with_state(app_state.clone());
+        .layer(middleware::from_fn_with_state(app_state.clone(), track_activity))
+        .with_state(app_state)
+}
 
-    let bind_addr = backend.ap_config().bind_addr;
-    tracing::info!("🌐 TDM Web server listening on {}", bind_addr);
+/// 每个 HTTP 请求都算作一次用户活动，推迟空闲超时
+async fn track_activity(State(state): State<Arc<AppState>>, request: Request, next: Next) -> Response {
+    state.backend.touch_activity();
+    next.run(request).await
+}
 
-    let listener = TcpListener::bind(bind_addr).await?;
-    axum::serve(listener, app.into_make_service()).await?;
+#[derive(Debug, Default, Deserialize)]
+struct ScanQuery {
+    /// 重新扫描而不是返回缓存（仅双射频模式有效）
+    #[serde(default)]
+    refresh: bool,
+}
 
-    Ok(())
+/// 返回扫描结果
+///
+/// 默认返回缓存的列表；双射频模式下带 `refresh=true` 时先在 STA 网卡上重新扫描，
+/// 单网卡模式下扫描需要关闭 AP，因此忽略 `refresh`。
+/// 同时附带上一次失败的连接尝试，让前端可以提示用户重试。
+async fn api_scan_tdm(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<ScanQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    tracing::debug!(refresh = query.refresh, "Handling /api/scan");
+    let networks = state
+        .backend
+        .networks(query.refresh)
+        .await
+        .map_err(|e| ApiError::internal(format!("Scan failed: {}", e)))?;
+    let response = ScanResponse {
+        networks,
+        last_attempt: state.backend.take_last_attempt(),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
+
+/// 返回上一次失败的连接尝试（读取后清除；没有记录时返回 null）
+async fn api_last_attempt(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    (StatusCode::OK, Json(state.backend.take_last_attempt())).into_response()
 }
 
-/// 返回缓存的扫描结果（TDM 模式）
-async fn api_scan_tdm(State(state): State<Arc<AppState>>) -> impl IntoResponse {
-    tracing::debug!("Handling /api/scan (TDM): returning cached list");
-    let networks = state.initial_networks.lock().unwrap().clone();
-    (StatusCode::OK, Json(networks)).into_response()
+#[derive(Debug, Default, Deserialize)]
+struct QrQuery {
+    #[serde(default)]
+    format: QrFormat,
+}
+
+/// 配网 AP 的入网二维码，`?format=svg`（默认）或 `?format=png`
+async fn api_ap_qr(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<QrQuery>,
+) -> Result<Response, ApiError> {
+    let ap_config = state.backend.ap_config();
+    let data = qr::ap_qr_code(&ap_config)
+        .and_then(|code| qr::render(&code, query.format))
+        .map_err(|e| ApiError::internal(format!("{:#}", e)))?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, query.format.content_type()),
+            (header::CACHE_CONTROL, "no-store"),
+        ],
+        data,
+    )
+        .into_response())
 }
 
-/// 返回后端类型
-async fn api_backend_kind_tdm() -> impl IntoResponse {
-    (StatusCode::OK, Json(serde_json::json!({ "kind": "tdm" }))).into_response()
+/// 返回后端类型：单网卡分时复用为 `tdm`，双射频为 `dual`
+async fn api_backend_kind_tdm(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let kind = if state.backend.is_dual_radio() { "dual" } else { "tdm" };
+    (StatusCode::OK, Json(serde_json::json!({ "kind": kind }))).into_response()
 }
 
 /// 处理连接请求（TDM 模式）
 /// 使用"发送并忘记"(Fire and Forget) 模式：
 /// 立即返回 200 OK，然后在后台执行实际的连接工作。
 /// 这避免了竞争条件：浏览器因为 AP 被关闭而无法接收响应。
+///
+/// 在关闭 AP 之前先校验请求，不合法的请求直接返回 400/422，
+/// 避免为一个注定失败的请求白白断开 AP；已有连接在进行时返回 409。
 async fn api_connect_tdm(
     State(state): State<Arc<AppState>>,
-    Json(payload): Json<ConnectionRequest>,
-) -> impl IntoResponse {
-    tracing::debug!(ssid = %payload.ssid, "Handling /api/connect request (TDM)");
-
-    // 克隆 backend Arc 以在后台任务中使用
-    let backend_clone = state.backend.clone();
-
-    // 生成后台任务来执行实际的连接工作
-    tokio::spawn(async move {
-        // connect 函数在后台运行，它包含：
-        // 1. 停止 AP
-        // 2. 连接到目标网络
-        // 3. 运行 DHCP 获取 IP
-        // 4. 成功时调用 std::process::exit(0)
-        // 5. 失败时重启 AP 并返回 Err
-        if let Err(e) = backend_clone.connect(&payload).await {
-            // 如果连接失败，connect 函数会自己重启 AP
-            // 我们只需要记录错误，不需要退出程序
-            // 这样用户可以重新连接 AP 并重试
-            tracing::error!(
-                "Connection failed: {}. AP should have been restarted automatically.",
-                e
-            );
-            // 移除了 std::process::exit(1)，让程序继续运行
-            // Web 服务器会继续接受用户的下一次尝试
-        }
-    });
+    payload: Result<Json<ConnectionRequest>, JsonRejection>,
+) -> Result<impl IntoResponse, ApiError> {
+    let Json(payload) = payload?;
+    tracing::debug!(ssid = %payload.display_ssid(), "Handling /api/connect request (TDM)");
+
+    // 校验通过后在后台任务中执行实际的连接工作
+    state.backend.start_connect(payload)?;
 
     // 立即返回 200 OK，在 AP 关闭之前发送给浏览器
     // 这样用户就能在手机上看到成功提示，即使设备随后断开 Wi-Fi
-    (
+    Ok((
         StatusCode::OK,
         Json(serde_json::json!({
             "status": "success",
             "message": "Connection request received. Device is now switching networks."
         })),
+    ))
+}
+
+/// 配网进度事件流（Server-Sent Events）
+///
+/// 客户端重连时浏览器会自动带上 `Last-Event-ID`，
+/// 我们据此从重放缓冲区中补发错过的事件。
+async fn api_events(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let last_event_id = headers
+        .get("last-event-id")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<u64>().ok());
+    tracing::debug!(?last_event_id, "Handling /api/events subscription");
+
+    let (replay, receiver) = state.backend.events().subscribe(last_event_id);
+
+    // 订阅者处理过慢导致的 Lagged 错误直接跳过
+    let live = BroadcastStream::new(receiver).filter_map(|res| res.ok());
+    // 收到退出信号时结束事件流，否则优雅关闭会一直等待这个长连接
+    let stop = tokio_stream::once(()).then(|()| shutdown::requested()).map(|_| None);
+    let stream = tokio_stream::iter(replay)
+        .chain(live)
+        .map(Some)
+        .merge(stop)
+        .map_while(|seq| seq)
+        .map(|seq| Ok(to_sse_event(&seq)));
+
+    Sse::new(stream).keep_alive(
+        KeepAlive::new()
+            .interval(Duration::from_secs(15))
+            .text("keepalive"),
     )
-        .into_response()
+}
+
+/// 将内部事件转换为 SSE 帧
+fn to_sse_event(seq: &SequencedEvent) -> Event {
+    let data = serde_json::to_string(&seq.event).unwrap_or_else(|_| "{}".to_string());
+    Event::default()
+        .id(seq.id.to_string())
+        .event(seq.event.name())
+        .data(data)
 }
 
 /// 处理捕获门户检测请求（Captive Portal Detection）
@@ -131,7 +257,7 @@ async fn handle_captive_portal() -> impl IntoResponse {
 async fn serve_static_asset(
     State(state): State<Arc<AppState>>,
     uri: Uri,
-) -> impl IntoResponse {
+) -> Result<Response, ApiError> {
     let mut path = uri.path().trim_start_matches('/').to_string();
 
     // 如果路径为空 (即请求根目录 "/"), 则服务 "index.html"
@@ -147,9 +273,7 @@ async fn serve_static_asset(
                 .status(StatusCode::OK)
                 .header(header::CONTENT_TYPE, mime)
                 .body(Body::from(data))
-                .unwrap_or_else(|_| {
-                    (StatusCode::INTERNAL_SERVER_ERROR, "Failed to build response").into_response()
-                })
+                .map_err(|_| ApiError::internal("Failed to build response"))
         }
         Err(e) => {
             // 失败 (例如 404 Not Found)
@@ -157,8 +281,51 @@ async fn serve_static_asset(
             // 对于 SPA (单页应用) 来说，
             // 更好的做法可能是在找不到文件时重定向回 index.html。
             // 但对于这个简单的 UI，返回 404 是清晰且正确的。
-            (StatusCode::NOT_FOUND, "Not Found").into_response()
+            Err(ApiError::not_found(format!("Asset not found: {}", path)))
         }
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::ProvisionEvent;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn events_replay_after_last_event_id() {
+        let config = crate::config::test_app_config("");
+        let backend = Arc::new(WpaCtrlBackend::detached(&config));
+        for count in 0..3 {
+            backend.events().publish(ProvisionEvent::ScanCompleted { count });
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(axum::serve(listener, router(backend.clone()).into_make_service()).into_future());
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET /api/events HTTP/1.1\r\nHost: localhost\r\nLast-Event-ID: 1\r\n\r\n")
+            .await
+            .unwrap();
+        // 补发的事件之后紧接着实时事件
+        backend.events().publish(ProvisionEvent::ApStopped);
+
+        let mut response = String::new();
+        let mut buf = [0u8; 1024];
+        while !response.contains("event: ap_stopped") {
+            let n = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut buf))
+                .await
+                .expect("timed out waiting for events")
+                .unwrap();
+            assert!(n > 0, "connection closed: {}", response);
+            response.push_str(&String::from_utf8_lossy(&buf[..n]));
+        }
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        let ids: Vec<&str> = response.lines().filter(|l| l.starts_with("id:")).collect();
+        assert_eq!(ids, ["id: 2", "id: 3", "id: 4"], "{}", response);
+    }
+}
@@ -11,21 +11,23 @@ document.addEventListener('DOMContentLoaded', () => {
   const connectionStatus = document.getElementById('connection-status');
   const modalClose = document.getElementById('modal-close');
 
-  let selectedSsid = null;
+  let selectedNetwork = null;
   let backendKind = 'unknown';
 
   function showScannerStatus(text){
     wifiList.innerHTML = `<div class="scanner-status"><div class="spinner"></div><div class="scanner-text">${text}</div></div>`;
   }
 
-  async function fetchWifiNetworks(){
+  // refresh 为 true 时要求后端重新扫描（仅双射频模式支持）
+  async function fetchWifiNetworks(refresh){
     showScannerStatus('正在扫描 Wi‑Fi...');
     refreshBtn.disabled = true;
     try {
-      const res = await fetch('/api/scan');
+      const res = await fetch(refresh === true ? '/api/scan?refresh=true' : '/api/scan');
       if(!res.ok) throw new Error('扫描失败: ' + res.status);
-      const nets = await res.json();
-      renderList(nets);
+      const data = await res.json();
+      renderList(data.networks);
+      showLastAttempt(data.last_attempt);
     } catch(err){
       console.warn('scan error', err);
       showScannerStatus('扫描失败，7秒后重试...');
@@ -35,6 +37,24 @@ document.addEventListener('DOMContentLoaded', () => {
     }
   }
 
+  const REASON_TEXT = {
+    wrong_password: '密码错误',
+    timeout: '连接超时',
+    association_failed: '无法连接到路由器',
+    internal: '设备内部错误'
+  };
+  function showLastAttempt(attempt){
+    const banner = document.getElementById('last-attempt');
+    if(!banner) return;
+    if(!attempt){
+      banner.style.display = 'none';
+      return;
+    }
+    const reason = REASON_TEXT[attempt.reason] || attempt.reason;
+    banner.textContent = `连接 “${attempt.ssid}” 失败：${reason}，请重试。`;
+    banner.style.display = 'block';
+  }
+
   function renderList(nets){
     if(!nets || nets.length === 0){
       showScannerStatus('未找到可用网络');
@@ -49,8 +69,8 @@ document.addEventListener('DOMContentLoaded', () => {
         <div class="network-left">
           <img class="wifi-svg" src="assets/wifi.svg" alt="wifi">
           <div class="network-info">
-            <div class="net-ssid">${escapeHtml(n.ssid)}</div>
-            <div class="net-meta">${escapeHtml(n.security || 'Unknown')} • 信号 ${n.signal}%</div>
+            <div class="net-ssid">${escapeHtml(networkName(n))}</div>
+            <div class="net-meta">${escapeHtml(n.security || 'Unknown')} • 信号 ${n.signal}%${encodingTag(n)}</div>
           </div>
         </div>
         <div class="net-right">
@@ -60,18 +80,26 @@ document.addEventListener('DOMContentLoaded', () => {
       `;
       el.addEventListener('click', () => {
         if(n.security && n.security !== 'Open'){
-          openModal(n.ssid);
+          openModal(n);
         } else {
-          connect(n.ssid, '');
+          connect(n, '');
         }
       });
       wifiList.appendChild(el);
     });
   }
 
-  function openModal(ssid){
-    selectedSsid = ssid;
-    modalSsid.textContent = `连接 ${ssid}`;
+  // 优先展示按字符集检测解码后的名称（GBK 等旧路由器）
+  function networkName(n){
+    return n.display_name || n.ssid;
+  }
+  function encodingTag(n){
+    return (n.encoding && n.encoding !== 'utf-8') ? ` • ${escapeHtml(n.encoding.toUpperCase())}` : '';
+  }
+
+  function openModal(network){
+    selectedNetwork = network;
+    modalSsid.textContent = `连接 ${networkName(network)}`;
     passwordInput.value = '';
     connectionStatus.textContent = '';
     modal.style.display = 'flex';
@@ -83,22 +111,22 @@ document.addEventListener('DOMContentLoaded', () => {
     modal.setAttribute('aria-hidden', 'true');
   }
 
-  async function connect(ssid, password){
+  async function connect(network, password){
     connectBtn.disabled = true;
     connectionStatus.textContent = '正在发送连接请求...';
     try{
       const res = await fetch('/api/connect', {
         method: 'POST',
         headers:{'Content-Type':'application/json'},
-        body: JSON.stringify({ssid, password})
+        // ssid_hex 保证后端使用路由器广播的原始字节
+        body: JSON.stringify({ssid: networkName(network), ssid_hex: network.ssid_hex, password})
       });
       if(!res.ok){
-        const e = await res.json().catch(()=>({error:'连接失败'}));
-        throw new Error(e.error || '连接失败');
+        const e = await res.json().catch(()=>({message:'连接失败'}));
+        throw new Error(e.message || '连接失败');
       }
       connectionStatus.textContent = '✓ 请求已接收\n\n设备正在关闭热点并尝试连接... \n如果失败，稍等片刻后请重新连接此配网 Wi-Fi。';
       connectionStatus.style.color = '#2dd4bf';
-      setTimeout(closeModal, 4000);
     }catch(err){
       connectionStatus.textContent = '连接失败：' + err.message;
       connectionStatus.style.color = '#ff6b6b';
@@ -106,13 +134,69 @@ document.addEventListener('DOMContentLoaded', () => {
     }
   }
 
+  // 订阅配网进度事件 (SSE)，EventSource 断线后会自动带 Last-Event-ID 重连
+  const STEP_TEXT = {
+    associating: '正在关联路由器...',
+    handshake: '正在验证密码 (四次握手)...',
+    dhcp: '正在获取 IP 地址...',
+    verifying: '正在校验连接...'
+  };
+  function handleProvisionEvent(ev){
+    switch(ev.type){
+      case 'connect_started':
+        connectionStatus.textContent = `正在连接 ${ev.ssid}...`;
+        connectionStatus.style.color = '#2dd4bf';
+        break;
+      case 'ap_stopped':
+        connectionStatus.textContent += '\n热点已关闭，手机可能会断开连接。';
+        break;
+      case 'connect_step':
+        connectionStatus.textContent = STEP_TEXT[ev.step] || ev.step;
+        break;
+      case 'connect_succeeded':
+        connectionStatus.textContent = `✓ 已成功连接到 ${ev.ssid}`;
+        connectionStatus.style.color = '#2dd4bf';
+        setTimeout(closeModal, 4000);
+        break;
+      case 'connect_failed':
+        connectionStatus.textContent = `连接 ${ev.ssid} 失败：${REASON_TEXT[ev.reason] || ev.reason}`;
+        connectionStatus.style.color = '#ff6b6b';
+        connectBtn.disabled = false;
+        break;
+      case 'provisioning_timed_out': {
+        const banner = document.getElementById('last-attempt');
+        if(banner){
+          banner.textContent = ev.idle ? '长时间未操作，配网热点即将关闭。' : '配网已超时，热点即将关闭。';
+          banner.style.display = 'block';
+        }
+        break;
+      }
+      case 'provisioning_stopped': {
+        const banner = document.getElementById('last-attempt');
+        if(banner){
+          banner.textContent = '设备已结束配网，热点即将关闭。';
+          banner.style.display = 'block';
+        }
+        break;
+      }
+    }
+  }
+  if(window.EventSource){
+    const source = new EventSource('/api/events');
+    ['connect_started','ap_stopped','connect_step','connect_succeeded','connect_failed','provisioning_timed_out','provisioning_stopped'].forEach(name => {
+      source.addEventListener(name, msg => {
+        try { handleProvisionEvent(JSON.parse(msg.data)); } catch(e){ console.warn('bad event', e); }
+      });
+    });
+  }
+
   connectForm.addEventListener('submit', (ev)=>{
     ev.preventDefault();
     const pwd = passwordInput.value || '';
-    connect(selectedSsid, pwd);
+    connect(selectedNetwork, pwd);
   });
   cancelBtn.addEventListener('click', closeModal);
-  refreshBtn.addEventListener('click', fetchWifiNetworks);
+  refreshBtn.addEventListener('click', () => fetchWifiNetworks(backendKind === 'dual'));
   if(modalClose){ modalClose.addEventListener('click', closeModal); }
 
   function signalBarsHtml(signal){